@@ -0,0 +1,672 @@
+use crate::config::*;
+use crate::connection::*;
+use crate::message::*;
+use crate::observer::*;
+use crate::statement::*;
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::Barrier;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Order statistics over a set of duration samples.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub min: Duration,
+    pub mean: Duration,
+    pub p99: Duration,
+}
+
+impl Summary {
+    pub fn from_samples(samples: &[Duration]) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+
+        let mut sorted = samples.to_vec();
+        sorted.sort();
+
+        let total: Duration = sorted.iter().sum();
+        let p99 = (sorted.len() * 99).div_ceil(100) - 1;
+
+        Self {
+            min: sorted[0],
+            mean: total / sorted.len() as u32,
+            p99: sorted[p99],
+        }
+    }
+}
+
+/// The outcome of running a query repeatedly.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BenchReport {
+    pub iterations: usize,
+    /// How the query was sent.
+    pub mode: QueryMode,
+    pub rows: u64,
+    /// Wall-clock time for all iterations.
+    pub elapsed: Duration,
+    /// Per-query time, from sending it to the connection being ready again.
+    pub latency: Summary,
+    /// Per-query time to the first row (or to completion, without rows).
+    pub ttfb: Summary,
+    /// Total time spent blocked reading responses off the socket.
+    pub network_wait: Duration,
+    /// Total time spent parsing the responses once read.
+    pub parse_time: Duration,
+    /// For [`bench_connect`], where the time to connect went.
+    pub connect_phases: Option<ConnectPhases>,
+}
+
+/// Connection setup time split by phase. `tls` is zero for plain
+/// connections, and `auth` runs from the end of the previous phase to
+/// `AuthenticationOk`, startup packet included.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectPhases {
+    pub tcp: Summary,
+    pub tls: Summary,
+    pub auth: Summary,
+}
+
+/// What [`BenchReport::to_prometheus`] starts metric names with.
+pub const DEFAULT_METRIC_PREFIX: &str = "toydrv_bench";
+
+impl BenchReport {
+    /// Completed queries per second.
+    pub fn throughput(&self) -> f64 {
+        self.iterations as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// The report in the Prometheus text exposition format, to push to a
+    /// pushgateway. Metric names start with [`DEFAULT_METRIC_PREFIX`].
+    pub fn to_prometheus(&self) -> String {
+        self.to_prometheus_with_prefix(DEFAULT_METRIC_PREFIX)
+    }
+
+    /// Like [`BenchReport::to_prometheus`], with metric names starting with
+    /// `prefix` and an underscore.
+    ///
+    /// Durations are in seconds, as Prometheus prefers. The latency and time
+    /// to first byte statistics are gauges, one per statistic, since they
+    /// describe this run alone rather than a distribution Prometheus could
+    /// aggregate.
+    pub fn to_prometheus_with_prefix(&self, prefix: &str) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: f64| {
+            out.push_str(&format!("# HELP {}_{} {}\n", prefix, name, help));
+            out.push_str(&format!("# TYPE {}_{} {}\n", prefix, name, kind));
+            out.push_str(&format!(
+                "{}_{} {}\n",
+                prefix,
+                name,
+                prometheus_value(value)
+            ));
+        };
+
+        metric(
+            "queries_total",
+            "counter",
+            "Queries completed.",
+            self.iterations as f64,
+        );
+        metric("rows_total", "counter", "Rows returned.", self.rows as f64);
+        metric(
+            "elapsed_seconds",
+            "gauge",
+            "Wall-clock time for all queries.",
+            self.elapsed.as_secs_f64(),
+        );
+        metric(
+            "throughput_queries_per_second",
+            "gauge",
+            "Queries completed per second.",
+            self.throughput(),
+        );
+        for (name, what, summary) in [
+            ("latency", "Query latency", self.latency),
+            ("ttfb", "Time to first row", self.ttfb),
+        ] {
+            for (statistic, value) in [
+                ("min", summary.min),
+                ("mean", summary.mean),
+                ("p99", summary.p99),
+            ] {
+                metric(
+                    &format!("{}_{}_seconds", name, statistic),
+                    "gauge",
+                    &format!("{}, {}.", what, statistic),
+                    value.as_secs_f64(),
+                );
+            }
+        }
+        metric(
+            "network_wait_seconds_total",
+            "counter",
+            "Time spent blocked reading responses.",
+            self.network_wait.as_secs_f64(),
+        );
+        metric(
+            "parse_time_seconds_total",
+            "counter",
+            "Time spent parsing responses.",
+            self.parse_time.as_secs_f64(),
+        );
+        out
+    }
+}
+
+/// Formats a sample value, spelling infinities as Prometheus does, such as
+/// the throughput of a run that took no measurable time.
+fn prometheus_value(value: f64) -> String {
+    match value {
+        f64::INFINITY => "+Inf".to_string(),
+        f64::NEG_INFINITY => "-Inf".to_string(),
+        value => value.to_string(),
+    }
+}
+
+/// Per-query timings gathered by one connection.
+#[derive(Debug, Default)]
+struct Samples {
+    latencies: Vec<Duration>,
+    ttfbs: Vec<Duration>,
+    rows: u64,
+    read_timings: ReadTimings,
+}
+
+impl Samples {
+    fn merge(&mut self, other: Samples) {
+        self.latencies.extend(other.latencies);
+        self.ttfbs.extend(other.ttfbs);
+        self.rows += other.rows;
+        self.read_timings.merge(other.read_timings);
+    }
+
+    fn into_report(self, mode: QueryMode, elapsed: Duration) -> BenchReport {
+        BenchReport {
+            iterations: self.latencies.len(),
+            mode,
+            rows: self.rows,
+            elapsed,
+            latency: Summary::from_samples(&self.latencies),
+            ttfb: Summary::from_samples(&self.ttfbs),
+            network_wait: self.read_timings.network_wait,
+            parse_time: self.read_timings.parse_time,
+            connect_phases: None,
+        }
+    }
+}
+
+/// Runs `query` `iterations` times as `mode` says, timing each run to its
+/// first row and to its end.
+///
+/// Simple queries are collected whole, so their time to first row is their
+/// full latency. With [`QueryMode::ExtendedOnce`] the statement is parsed
+/// before the clock starts.
+pub fn run_benchmark(
+    connection: &mut Connection,
+    query: &str,
+    iterations: usize,
+    mode: QueryMode,
+) -> Result<BenchReport, RuntimeError> {
+    let statement;
+    let source = match mode {
+        QueryMode::Simple => QuerySource::Simple(query),
+        QueryMode::ExtendedOnce => {
+            statement = connection.prepare(query)?;
+            QuerySource::Prepared(&statement)
+        }
+        QueryMode::ExtendedEach => QuerySource::Extended(query),
+    };
+    let started = Instant::now();
+    let samples = collect_samples(connection, source, iterations)?;
+    Ok(samples.into_report(mode, started.elapsed()))
+}
+
+/// Runs `iterations` queries spread over `concurrency` connections, each on
+/// its own thread. Connections are opened by their thread, since they can't
+/// be sent across, and all of them are up before the clock starts.
+pub fn run_concurrent_benchmark(
+    config: &Config,
+    query: &str,
+    concurrency: usize,
+    iterations: usize,
+) -> Result<BenchReport, RuntimeError> {
+    let concurrency = concurrency.max(1);
+    let ready = Barrier::new(concurrency + 1);
+
+    let (results, elapsed) = thread::scope(|scope| {
+        let workers: Vec<_> = (0..concurrency)
+            .map(|worker| {
+                // The first `iterations % concurrency` workers take one extra.
+                let share =
+                    iterations / concurrency + usize::from(worker < iterations % concurrency);
+                let ready = &ready;
+                scope.spawn(move || {
+                    let connection = Connection::connect(config);
+                    ready.wait();
+                    collect_samples(&mut connection?, QuerySource::Extended(query), share)
+                })
+            })
+            .collect();
+
+        ready.wait();
+        let started = Instant::now();
+        let results: Vec<Result<Samples, RuntimeError>> = workers
+            .into_iter()
+            .map(|worker| worker.join().expect("benchmark worker panicked"))
+            .collect();
+        (results, started.elapsed())
+    });
+
+    let mut samples = Samples::default();
+    for result in results {
+        samples.merge(result?);
+    }
+    Ok(samples.into_report(QueryMode::ExtendedEach, elapsed))
+}
+
+/// Runs the concurrent benchmark at each of `levels` in turn, so the point
+/// where more connections stop adding throughput stands out.
+pub fn run_saturation_sweep(
+    config: &Config,
+    query: &str,
+    levels: &[usize],
+    per_level_iterations: usize,
+) -> Result<Vec<(usize, BenchReport)>, RuntimeError> {
+    levels
+        .iter()
+        .map(|&level| {
+            let report = run_concurrent_benchmark(config, query, level, per_level_iterations)?;
+            Ok((level, report))
+        })
+        .collect()
+}
+
+/// The table [`setup_bench_table`] creates.
+pub const BENCH_TABLE: &str = "toydrv_bench";
+
+/// Recreates [`BENCH_TABLE`] with integer, text and timestamp columns.
+pub const BENCH_TABLE_DDL: &str = "DROP TABLE IF EXISTS toydrv_bench; \
+     CREATE TABLE toydrv_bench (\
+     id integer PRIMARY KEY, \
+     name text NOT NULL, \
+     created_at timestamptz NOT NULL)";
+
+/// Creates [`BENCH_TABLE`], replacing any previous one, loads `rows`
+/// synthetic rows into it with `COPY`, and analyzes it so the planner knows
+/// its size. This gives the benchmark something to query on an empty
+/// database.
+pub fn setup_bench_table(connection: &mut Connection, rows: usize) -> Result<(), RuntimeError> {
+    connection.execute(BENCH_TABLE_DDL)?;
+    connection.copy_in_csv(
+        BENCH_TABLE,
+        &["id", "name", "created_at"],
+        (0..rows).map(bench_table_row),
+    )?;
+    connection.execute(&format!("ANALYZE {}", BENCH_TABLE))?;
+    Ok(())
+}
+
+/// The CSV fields of row `i` of [`BENCH_TABLE`]. Timestamps are a second
+/// apart, wrapping around every day.
+pub fn bench_table_row(i: usize) -> Vec<String> {
+    let seconds = i % 86_400;
+    vec![
+        i.to_string(),
+        format!("name {}", i),
+        format!(
+            "2024-01-01 {:02}:{:02}:{:02}+00",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        ),
+    ]
+}
+
+/// Opens and cleanly closes a connection `iterations` times without running
+/// anything on it. `ttfb` is the time until the connection is ready for
+/// queries, and `latency` includes closing it.
+pub fn bench_connect(config: &Config, iterations: usize) -> Result<BenchReport, RuntimeError> {
+    let timer = Rc::new(PhaseTimer::default());
+    let mut samples = Samples::default();
+    let mut phases = [vec![], vec![], vec![]];
+
+    let started = Instant::now();
+    for _ in 0..iterations {
+        let start = Instant::now();
+        let connection = Connection::connect_with_observer(config, timer.clone())?;
+        samples.ttfbs.push(start.elapsed());
+        connection.close();
+        samples.latencies.push(start.elapsed());
+
+        for (phase, duration) in phases.iter_mut().zip(timer.phases()) {
+            phase.push(duration);
+        }
+    }
+
+    let mut report = samples.into_report(QueryMode::default(), started.elapsed());
+    let [tcp, tls, auth] = phases.map(|phase| Summary::from_samples(&phase));
+    report.connect_phases = Some(ConnectPhases { tcp, tls, auth });
+    Ok(report)
+}
+
+/// Records when each connection step happened, for [`bench_connect`].
+#[derive(Debug, Default)]
+struct PhaseTimer {
+    start: Cell<Option<Instant>>,
+    tcp_connected: Cell<Option<Instant>>,
+    tls_upgraded: Cell<Option<Instant>>,
+    authenticated: Cell<Option<Instant>>,
+}
+
+impl PhaseTimer {
+    /// The TCP, TLS and authentication durations of the last connection.
+    fn phases(&self) -> [Duration; 3] {
+        let start = self.start.get().unwrap_or_else(Instant::now);
+        let tcp = self.tcp_connected.get().unwrap_or(start);
+        let tls = self.tls_upgraded.get().unwrap_or(tcp);
+        let auth = self.authenticated.get().unwrap_or(tls);
+        [tcp - start, tls - tcp, auth - tls]
+    }
+}
+
+impl ConnectionObserver for PhaseTimer {
+    fn on_connect_start(&self, _addr: &str) {
+        self.start.set(Some(Instant::now()));
+        self.tcp_connected.set(None);
+        self.tls_upgraded.set(None);
+        self.authenticated.set(None);
+    }
+
+    fn on_tcp_connected(&self) {
+        self.tcp_connected.set(Some(Instant::now()));
+    }
+
+    fn on_tls_upgrade(&self) {
+        self.tls_upgraded.set(Some(Instant::now()));
+    }
+
+    fn on_authenticated(&self) {
+        self.authenticated.set(Some(Instant::now()));
+    }
+}
+
+/// What [`collect_samples`] runs on each iteration.
+#[derive(Clone, Copy)]
+enum QuerySource<'a> {
+    Simple(&'a str),
+    Extended(&'a str),
+    Prepared(&'a Statement),
+}
+
+/// Runs the iterations with read timing on, so the report can tell waiting
+/// on the server apart from the driver's own parsing.
+fn collect_samples(
+    connection: &mut Connection,
+    source: QuerySource,
+    iterations: usize,
+) -> Result<Samples, RuntimeError> {
+    connection.set_read_timing(true);
+    let samples = time_queries(connection, source, iterations);
+    let read_timings = connection.take_read_timings().unwrap_or_default();
+    connection.set_read_timing(false);
+    Ok(Samples {
+        read_timings,
+        ..samples?
+    })
+}
+
+fn time_queries(
+    connection: &mut Connection,
+    source: QuerySource,
+    iterations: usize,
+) -> Result<Samples, RuntimeError> {
+    let mut latencies = Vec::with_capacity(iterations);
+    let mut ttfbs = Vec::with_capacity(iterations);
+    let mut rows = 0;
+
+    for _ in 0..iterations {
+        let start = Instant::now();
+        let mut stream = match source {
+            QuerySource::Simple(query) => {
+                rows += connection.query(query)?.rows.len() as u64;
+                ttfbs.push(start.elapsed());
+                latencies.push(start.elapsed());
+                continue;
+            }
+            QuerySource::Extended(query) => connection.query_stream(query)?,
+            QuerySource::Prepared(statement) => statement.query_stream(connection)?,
+        };
+        for row in stream.by_ref() {
+            row?;
+            rows += 1;
+        }
+        ttfbs.push(
+            stream
+                .time_to_first_byte()
+                .unwrap_or_else(|| start.elapsed()),
+        );
+        drop(stream);
+        latencies.push(start.elapsed());
+    }
+
+    Ok(Samples {
+        latencies,
+        ttfbs,
+        rows,
+        read_timings: ReadTimings::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Answers every streamed query with one row until the client leaves,
+    /// counting the queries in `served`.
+    fn serve_streams(session: &mut MockSession, served: &AtomicUsize) {
+        session.accept();
+        loop {
+            match session.receive() {
+                (b'X', _) => return,
+                (b'P', _) => {}
+                (prefix, body) => panic!("unexpected {:?}: {:?}", char::from(prefix), body),
+            }
+            for prefix in [b'B', b'E', b'H'] {
+                session.expect(prefix);
+            }
+            let mut reply = [frame(b'1', b""), frame(b'2', b"")].concat();
+            reply.extend(data_row(&[Some("1")]));
+            reply.extend(command_complete("SELECT 1"));
+            session.send(&reply);
+            session.expect(b'S');
+            session.send(&ready(b'I'));
+            served.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn saturation_sweep_runs_every_iteration_at_each_level() {
+        let served = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&served);
+        // One connection per worker, over all three levels.
+        let server =
+            MockServer::start_many(1 + 2 + 3, move |session| serve_streams(session, &counter));
+
+        let reports = run_saturation_sweep(&server.config(), "SELECT 1", &[1, 2, 3], 7).unwrap();
+        server.finish();
+
+        let levels: Vec<usize> = reports.iter().map(|(level, _)| *level).collect();
+        assert_eq!(levels, [1, 2, 3]);
+        for (_, report) in &reports {
+            assert_eq!(report.iterations, 7);
+            assert_eq!(report.rows, 7);
+            assert_eq!(report.mode, QueryMode::ExtendedEach);
+        }
+        assert_eq!(served.load(Ordering::Relaxed), 3 * 7);
+    }
+
+    #[test]
+    fn extended_once_parses_the_query_a_single_time() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            session.expect(b'P');
+            session.expect(b'S');
+            session.send(&[frame(b'1', b""), ready(b'I')].concat());
+            let mut executions = 0;
+            loop {
+                match session.receive() {
+                    (b'X', _) => break,
+                    // The statement's Close, once the run is over.
+                    (b'C', _) => continue,
+                    (b'B', _) => {}
+                    (prefix, body) => panic!("unexpected {:?}: {:?}", char::from(prefix), body),
+                }
+                session.expect(b'E');
+                session.expect(b'H');
+                let mut reply = frame(b'2', b"");
+                reply.extend(data_row(&[Some("1")]));
+                reply.extend(command_complete("SELECT 1"));
+                session.send(&reply);
+                session.expect(b'S');
+                session.send(&ready(b'I'));
+                executions += 1;
+            }
+            assert_eq!(executions, 5);
+        });
+        let mut connection = server.connect();
+
+        let report =
+            run_benchmark(&mut connection, "SELECT 1", 5, QueryMode::ExtendedOnce).unwrap();
+        assert_eq!(report.mode, QueryMode::ExtendedOnce);
+        assert_eq!(report.rows, 5);
+        drop(connection);
+        server.finish();
+    }
+
+    #[test]
+    fn time_to_first_row_stops_at_the_first_row() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            for prefix in [b'P', b'B', b'E', b'H'] {
+                session.expect(prefix);
+            }
+            session.send(&[frame(b'1', b""), frame(b'2', b"")].concat());
+            thread::sleep(Duration::from_millis(20));
+            session.send(&data_row(&[Some("1")]));
+            thread::sleep(Duration::from_millis(30));
+            let mut reply = data_row(&[Some("2")]);
+            reply.extend(command_complete("SELECT 2"));
+            session.send(&reply);
+            session.expect(b'S');
+            session.send(&ready(b'I'));
+        });
+        let mut connection = server.connect();
+
+        let report =
+            run_benchmark(&mut connection, "SELECT 1", 1, QueryMode::ExtendedEach).unwrap();
+        assert_eq!(report.rows, 2);
+        assert!(report.ttfb.min >= Duration::from_millis(20));
+        assert!(report.latency.min >= report.ttfb.min + Duration::from_millis(30));
+        server.finish();
+    }
+
+    #[test]
+    fn connect_benchmark_times_every_phase_of_every_connection() {
+        let server = MockServer::start_many(4, |session| {
+            session.read_startup();
+            thread::sleep(Duration::from_millis(10));
+            session.admit();
+            session.expect(b'X');
+        });
+
+        let report = bench_connect(&server.config(), 4).unwrap();
+        server.finish();
+
+        assert_eq!(report.iterations, 4);
+        let phases = report.connect_phases.unwrap();
+        // Plaintext, so no time goes to TLS; the server's pause is in auth.
+        assert_eq!(phases.tls, Summary::default());
+        assert!(phases.auth.min >= Duration::from_millis(10));
+        assert!(report.latency.min >= phases.tcp.min + phases.auth.min);
+    }
+
+    #[test]
+    fn exports_prometheus_metrics_under_the_prefix() {
+        let report = BenchReport {
+            iterations: 4,
+            rows: 8,
+            elapsed: Duration::from_secs(2),
+            latency: Summary {
+                min: Duration::from_millis(250),
+                mean: Duration::from_millis(500),
+                p99: Duration::from_millis(750),
+            },
+            ..BenchReport::default()
+        };
+        let text = report.to_prometheus_with_prefix("pgbench");
+
+        for line in text.lines().filter(|line| !line.starts_with('#')) {
+            assert!(line.starts_with("pgbench_"), "{}", line);
+        }
+        assert!(text.contains("# TYPE pgbench_queries_total counter\npgbench_queries_total 4\n"));
+        assert!(text.contains("pgbench_throughput_queries_per_second 2\n"));
+        for (statistic, value) in [("min", "0.25"), ("mean", "0.5"), ("p99", "0.75")] {
+            let name = format!("pgbench_latency_{}_seconds", statistic);
+            assert!(text.contains(&format!("# TYPE {} gauge\n{} {}\n", name, name, value)));
+        }
+    }
+
+    #[test]
+    fn bench_table_ddl_and_rows_agree() {
+        assert!(BENCH_TABLE_DDL.starts_with(&format!("DROP TABLE IF EXISTS {}; ", BENCH_TABLE)));
+        assert!(BENCH_TABLE_DDL.contains(&format!("CREATE TABLE {} (", BENCH_TABLE)));
+        // The CSV fields follow the columns in the order they're declared.
+        let columns = ["id integer", "name text", "created_at timestamptz"]
+            .map(|column| BENCH_TABLE_DDL.find(column).unwrap());
+        assert!(columns.is_sorted());
+        for (i, created_at) in [
+            (0, "2024-01-01 00:00:00+00"),
+            (3_723, "2024-01-01 01:02:03+00"),
+            (86_400, "2024-01-01 00:00:00+00"),
+        ] {
+            assert_eq!(
+                bench_table_row(i),
+                [i.to_string(), format!("name {}", i), created_at.to_string()]
+            );
+        }
+        assert_eq!(
+            crate::copy::csv_line(&bench_table_row(7)),
+            "7,name 7,2024-01-01 00:00:07+00\n"
+        );
+    }
+
+    #[test]
+    fn read_time_is_split_into_waiting_and_parsing() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            session.expect_query();
+            thread::sleep(Duration::from_millis(30));
+            let mut reply = row_description(&[("n", 23)]);
+            reply.extend(data_row(&[Some("1")]));
+            reply.extend(command_complete("SELECT 1"));
+            reply.extend(ready(b'I'));
+            session.send(&reply);
+        });
+        let mut connection = server.connect();
+
+        let report = run_benchmark(&mut connection, "SELECT 1", 1, QueryMode::Simple).unwrap();
+        assert!(report.network_wait >= Duration::from_millis(30));
+        assert!(report.parse_time > Duration::ZERO);
+        assert!(report.parse_time < report.network_wait);
+        // Timing is only on for the run.
+        assert_eq!(connection.take_read_timings(), None);
+        drop(connection);
+        server.finish();
+    }
+}
@@ -0,0 +1,182 @@
+use crate::connection::*;
+use crate::message::*;
+use crate::row::*;
+
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+/// Asks the server to cancel whatever the given backend is running.
+///
+/// The request goes over a fresh, unencrypted connection that the server
+/// closes without answering, so success only means the request was sent.
+pub fn send_cancel_request(
+    addr: &str,
+    process_id: u32,
+    secret_key: i32,
+) -> Result<(), RuntimeError> {
+    let mut stream = TcpStream::connect(addr)?;
+    stream.write_all(
+        &FrontendMessage::CancelRequest {
+            process_id,
+            secret_key,
+        }
+        .to_bytes()?,
+    )?;
+    Ok(())
+}
+
+/// What it takes to cancel a connection's running query from elsewhere,
+/// such as another thread, without access to the connection itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CancelToken {
+    addr: String,
+    /// The backend's process ID and secret key, `None` if the server never
+    /// sent them.
+    backend_key: Option<(u32, i32)>,
+}
+
+impl CancelToken {
+    /// Asks the server to cancel the query the connection is running, if
+    /// any, as [`send_cancel_request`] does.
+    pub fn cancel(&self) -> Result<(), RuntimeError> {
+        let (process_id, secret_key) = self.backend_key.ok_or(RuntimeError::ParseMessageError)?;
+        send_cancel_request(&self.addr, process_id, secret_key)
+    }
+}
+
+impl Connection {
+    /// A token that cancels this connection's queries, and can be sent to
+    /// another thread and outlive the connection.
+    pub fn cancel_token(&self) -> CancelToken {
+        CancelToken {
+            addr: self.addr.clone(),
+            backend_key: self.backend_key,
+        }
+    }
+
+    /// Runs a query, cancelling it if it hasn't completed within `deadline`.
+    ///
+    /// A timer thread holds the backend's key and sends a `CancelRequest` once
+    /// the deadline passes; the query then fails with the server's
+    /// `query_canceled` error. If the query finishes first, the timer is
+    /// stopped and joined before returning.
+    pub fn query_with_deadline(
+        &mut self,
+        query: &str,
+        deadline: Duration,
+    ) -> Result<ResultSet, RuntimeError> {
+        let (result, _) = self.with_cancel_timer(deadline, |connection| connection.query(query))?;
+        result
+    }
+
+    /// Runs `f` under the timeout from [`Connection::set_query_timeout`], if
+    /// any, turning a failure after the cancel into [`RuntimeError::Timeout`].
+    pub(crate) fn with_query_timeout<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, RuntimeError>,
+    ) -> Result<T, RuntimeError> {
+        let Some(timeout) = self.query_timeout else {
+            return f(self);
+        };
+        match self.with_cancel_timer(timeout, f)? {
+            (Err(_), true) => Err(RuntimeError::Timeout),
+            (result, _) => result,
+        }
+    }
+
+    /// Runs `f` with a timer thread armed to cancel it after `deadline`, and
+    /// reports whether the cancel was sent.
+    fn with_cancel_timer<T>(
+        &mut self,
+        deadline: Duration,
+        f: impl FnOnce(&mut Self) -> Result<T, RuntimeError>,
+    ) -> Result<(Result<T, RuntimeError>, bool), RuntimeError> {
+        let (process_id, secret_key) = self.backend_key.ok_or(RuntimeError::ParseMessageError)?;
+        let addr = self.addr.clone();
+        let (done, finished) = mpsc::channel::<()>();
+        let fired = Arc::new(AtomicBool::new(false));
+
+        let timer = thread::spawn({
+            let fired = Arc::clone(&fired);
+            move || {
+                if let Err(mpsc::RecvTimeoutError::Timeout) = finished.recv_timeout(deadline) {
+                    fired.store(true, Ordering::SeqCst);
+                    let _ = send_cancel_request(&addr, process_id, secret_key);
+                }
+            }
+        });
+
+        let result = f(self);
+
+        drop(done);
+        let _ = timer.join();
+
+        Ok((result, fired.load(Ordering::SeqCst)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::*;
+
+    #[test]
+    fn a_query_past_its_deadline_is_cancelled() {
+        let server = MockServer::start_cancellable(|session, expect_cancel| {
+            session.accept();
+            assert_eq!(session.expect_query(), "SELECT pg_sleep(10)");
+            expect_cancel();
+            let mut reply = error_response("57014", "canceling statement due to user request");
+            reply.extend(ready(b'I'));
+            session.send(&reply);
+        });
+        let mut connection = server.connect();
+
+        let result =
+            connection.query_with_deadline("SELECT pg_sleep(10)", Duration::from_millis(20));
+        let Err(RuntimeError::PgError(error)) = result else {
+            panic!("expected the query to be cancelled, got {:?}", result);
+        };
+        assert_eq!(error.code(), Some("57014"));
+        server.finish();
+    }
+
+    #[test]
+    fn a_query_over_the_timeout_fails_with_timeout() {
+        let server = MockServer::start_cancellable(|session, expect_cancel| {
+            session.accept();
+            assert_eq!(session.expect_query(), "UPDATE t SET n = n + 1");
+            expect_cancel();
+            let mut reply = error_response("57014", "canceling statement due to user request");
+            reply.extend(ready(b'I'));
+            session.send(&reply);
+        });
+        let mut connection = server.connect();
+        connection.set_query_timeout(Some(Duration::from_millis(20)));
+
+        let result = connection.execute("UPDATE t SET n = n + 1");
+        assert!(matches!(result, Err(RuntimeError::Timeout)));
+        server.finish();
+    }
+
+    #[test]
+    fn a_token_cancels_from_another_thread() {
+        let server = MockServer::start_cancellable(|session, expect_cancel| {
+            session.accept();
+            expect_cancel();
+        });
+        let connection = server.connect();
+
+        let token = connection.cancel_token();
+        thread::spawn(move || token.cancel())
+            .join()
+            .unwrap()
+            .unwrap();
+        drop(connection);
+        server.finish();
+    }
+}
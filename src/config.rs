@@ -1,8 +1,19 @@
 use std::{
     env::{self, VarError},
+    fs,
     num::ParseIntError,
+    time::Duration,
 };
 
+/// What generated prepared statement names start with, unless configured.
+pub const DEFAULT_STATEMENT_PREFIX: &str = "toydrv_";
+
+/// The size of the read buffer, unless configured.
+pub const DEFAULT_BUFFER_SIZE: usize = 8192;
+
+/// The `application_name` sent when neither it nor a fallback is configured.
+pub const DEFAULT_APPLICATION_NAME: &str = "toy-driver-benchmark";
+
 #[derive(Debug)]
 pub struct Config {
     pub host: String,
@@ -10,12 +21,191 @@ pub struct Config {
     pub user: String,
     pub database: String,
     pub password: String,
+    /// The most connecting may take, from the TCP connect through TLS and
+    /// authentication to the server being ready. `None` waits forever.
+    pub connect_timeout: Option<Duration>,
+    /// Set explicitly; takes precedence over `fallback_application_name`.
+    pub application_name: Option<String>,
+    /// Used when no `application_name` is set, like libpq's option of the
+    /// same name.
+    pub fallback_application_name: Option<String>,
+    /// The `client_encoding` asked for at startup. Strings are decoded as
+    /// UTF-8 regardless, so anything else is only useful to test how a
+    /// server with a different encoding is handled.
+    pub client_encoding: String,
+    /// The start of every prepared statement name the driver generates,
+    /// followed by a random part unique to the connection, to tell its
+    /// statements apart in `pg_prepared_statements` and from other clients'.
+    pub statement_prefix: String,
+    pub query: String,
+    pub iterations: usize,
+    pub query_mode: QueryMode,
+    pub ssl_mode: SslMode,
+    /// Whether GSSAPI encryption is asked for before TLS.
+    pub negotiation_order: NegotiationOrder,
+    /// The hostname sent for SNI and checked against the server certificate,
+    /// for when it differs from `host`, such as behind a proxy or when
+    /// connecting to an IP address.
+    pub ssl_sni_host: Option<String>,
+    pub channel_binding: ChannelBinding,
+    /// Bytes to pre-allocate for reading messages, also used as the size of
+    /// the read buffer. A tuning knob for large results only; any value
+    /// works, buffers still grow as needed.
+    pub initial_buffer_size: usize,
+    /// The socket receive buffer (`SO_RCVBUF`) to ask the OS for. `None`
+    /// leaves it to the OS, which on Linux keeps the buffer autotuned; setting
+    /// it turns that off.
+    pub receive_buffer_size: Option<usize>,
+    /// The `replication` startup parameter (`true` for physical, `database`
+    /// for logical replication), needed to run `START_REPLICATION`.
+    pub replication: Option<String>,
+    /// The protocol minor version to ask for; servers up to 17 only speak 3.0.
+    pub protocol_minor_version: u16,
+    /// Protocol options sent as `_pq_.<name>` startup parameters, which the
+    /// server answers with a `NegotiateProtocolVersion` listing the ones it
+    /// doesn't recognize.
+    pub protocol_extensions: Vec<(String, String)>,
+}
+
+impl Config {
+    /// The `application_name` to send at startup.
+    pub fn effective_application_name(&self) -> &str {
+        self.application_name
+            .as_deref()
+            .or(self.fallback_application_name.as_deref())
+            .unwrap_or(DEFAULT_APPLICATION_NAME)
+    }
+
+    /// The hostname the TLS session is established for.
+    pub fn tls_host(&self) -> &str {
+        self.ssl_sni_host.as_deref().unwrap_or(&self.host)
+    }
+
+    /// A warning for a benchmark query with no statement in it, which the
+    /// server answers with an `EmptyQueryResponse` and nothing is measured.
+    pub fn empty_query_warning(&self) -> Option<&'static str> {
+        self.query
+            .trim()
+            .is_empty()
+            .then_some("the benchmark query is empty, so no statement will run")
+    }
+}
+
+/// How the benchmark query is sent, to measure what parsing and planning it
+/// every time costs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueryMode {
+    /// A `SimpleQuery` per iteration, with rows collected at the end. This
+    /// is how the benchmark always sent its query.
+    #[default]
+    Simple,
+    /// Parsed once as a named statement, then bound and executed each time.
+    ExtendedOnce,
+    /// Parsed, bound and executed each time through the unnamed statement.
+    ExtendedEach,
+}
+
+impl QueryMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "simple" => Some(Self::Simple),
+            "extended-once" => Some(Self::ExtendedOnce),
+            "extended-each" => Some(Self::ExtendedEach),
+            _ => None,
+        }
+    }
+}
+
+/// Whether to encrypt the connection, following libpq's `sslmode` values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SslMode {
+    /// Never ask for TLS.
+    Disable,
+    /// Use TLS if the server supports it, or fall back to plaintext.
+    Prefer,
+    /// Fail unless the connection can be encrypted.
+    Require,
+}
+
+impl SslMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "disable" => Some(Self::Disable),
+            "prefer" => Some(Self::Prefer),
+            "require" => Some(Self::Require),
+            _ => None,
+        }
+    }
+}
+
+/// Which encryption requests are sent after connecting, and in what order,
+/// following libpq's precedence of `gssencmode` over `sslmode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NegotiationOrder {
+    /// Only send `RequestSSL`, as `ssl_mode` says; libpq's
+    /// `gssencmode=disable`.
+    #[default]
+    Ssl,
+    /// Send `RequestGssEncryption` first, then fall back to `RequestSSL` and
+    /// plaintext as `ssl_mode` allows. GSSAPI encryption itself isn't
+    /// supported, so this only works against servers that decline it.
+    GssThenSsl,
+}
+
+impl NegotiationOrder {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "ssl" => Some(Self::Ssl),
+            "gss,ssl" => Some(Self::GssThenSsl),
+            _ => None,
+        }
+    }
+}
+
+/// Whether SCRAM authentication binds to the TLS session, following libpq's
+/// `channel_binding` values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelBinding {
+    /// Always use plain `SCRAM-SHA-256`, even when binding is available.
+    Disable,
+    /// Bind when the server offers `SCRAM-SHA-256-PLUS` over TLS.
+    #[default]
+    Prefer,
+    /// Refuse to authenticate without channel binding.
+    Require,
+}
+
+impl ChannelBinding {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "disable" => Some(Self::Disable),
+            "prefer" => Some(Self::Prefer),
+            "require" => Some(Self::Require),
+            _ => None,
+        }
+    }
+}
+
+impl Default for SslMode {
+    /// Encryption is required unless the crate was built without TLS.
+    fn default() -> Self {
+        if cfg!(feature = "tls") {
+            Self::Require
+        } else {
+            Self::Disable
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum ConfigParseError {
     VarError(VarError),
     ParseIntError(ParseIntError),
+    IoError(std::io::Error),
+    /// The named variable is set to a value that isn't one of its options.
+    InvalidValue(String),
+    /// The named variable is set but empty, where a value is required.
+    Incomplete(String),
 }
 
 impl From<VarError> for ConfigParseError {
@@ -30,12 +220,263 @@ impl From<ParseIntError> for ConfigParseError {
     }
 }
 
+impl From<std::io::Error> for ConfigParseError {
+    fn from(error: std::io::Error) -> Self {
+        Self::IoError(error)
+    }
+}
+
 pub fn load_config_from_env() -> Result<Config, ConfigParseError> {
+    // Sizes both the read buffer and, only when set, the socket's.
+    let recv_buffer_bytes = optional_var("RECV_BUFFER_BYTES")?
+        .map(|bytes| bytes.parse::<usize>())
+        .transpose()?;
+
     Ok(Config {
-        host: env::var("HOST")?,
+        host: required_var("HOST")?,
         port: env::var("PORT").map(|p| p.parse::<u16>())??,
-        user: env::var("USER")?,
-        database: env::var("DATABASE")?,
+        user: required_var("USER")?,
+        database: required_var("DATABASE")?,
         password: env::var("PASSWORD")?,
+        // In whole seconds, with 0 meaning no limit, as in libpq.
+        connect_timeout: optional_var("CONNECT_TIMEOUT")?
+            .map(|seconds| seconds.parse::<u64>())
+            .transpose()?
+            .filter(|&seconds| seconds > 0)
+            .map(Duration::from_secs),
+        application_name: optional_var("APPLICATION_NAME")?,
+        fallback_application_name: optional_var("FALLBACK_APPLICATION_NAME")?,
+        client_encoding: var_or("CLIENT_ENCODING", "UTF8")?,
+        statement_prefix: var_or("STATEMENT_PREFIX", DEFAULT_STATEMENT_PREFIX)?,
+        query: load_query_from_env()?,
+        iterations: var_or("ITERATIONS", "1")?.parse()?,
+        query_mode: match env::var("QUERY_MODE") {
+            Ok(value) => QueryMode::parse(&value)
+                .ok_or_else(|| ConfigParseError::InvalidValue("QUERY_MODE".to_string()))?,
+            Err(VarError::NotPresent) => QueryMode::default(),
+            Err(error) => Err(error)?,
+        },
+        ssl_mode: match env::var("SSLMODE") {
+            Ok(value) => SslMode::parse(&value)
+                .ok_or_else(|| ConfigParseError::InvalidValue("SSLMODE".to_string()))?,
+            Err(VarError::NotPresent) => SslMode::default(),
+            Err(error) => Err(error)?,
+        },
+        negotiation_order: match env::var("NEGOTIATION_ORDER") {
+            Ok(value) => NegotiationOrder::parse(&value)
+                .ok_or_else(|| ConfigParseError::InvalidValue("NEGOTIATION_ORDER".to_string()))?,
+            Err(VarError::NotPresent) => NegotiationOrder::default(),
+            Err(error) => Err(error)?,
+        },
+        ssl_sni_host: optional_var("SSL_SNI_HOST")?,
+        channel_binding: match env::var("CHANNEL_BINDING") {
+            Ok(value) => ChannelBinding::parse(&value)
+                .ok_or_else(|| ConfigParseError::InvalidValue("CHANNEL_BINDING".to_string()))?,
+            Err(VarError::NotPresent) => ChannelBinding::default(),
+            Err(error) => Err(error)?,
+        },
+        initial_buffer_size: recv_buffer_bytes.unwrap_or(DEFAULT_BUFFER_SIZE),
+        receive_buffer_size: recv_buffer_bytes,
+        replication: optional_var("REPLICATION")?,
+        protocol_minor_version: var_or("PROTOCOL_MINOR_VERSION", "0")?.parse()?,
+        protocol_extensions: match optional_var("PROTOCOL_EXTENSIONS")? {
+            Some(value) => parse_protocol_extensions(&value)
+                .ok_or_else(|| ConfigParseError::InvalidValue("PROTOCOL_EXTENSIONS".to_string()))?,
+            None => vec![],
+        },
     })
 }
+
+/// The benchmark query comes from `QUERY`, or else from the file at
+/// `QUERY_FILE`, or else defaults to `SELECT 1`.
+fn load_query_from_env() -> Result<String, ConfigParseError> {
+    match env::var("QUERY") {
+        Ok(query) => return Ok(query),
+        Err(VarError::NotPresent) => {}
+        Err(error) => return Err(error.into()),
+    }
+
+    match env::var("QUERY_FILE") {
+        Ok(path) => Ok(fs::read_to_string(path)?),
+        Err(VarError::NotPresent) => Ok("SELECT 1".to_string()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Parses a comma-separated list of `name=value` pairs.
+fn parse_protocol_extensions(value: &str) -> Option<Vec<(String, String)>> {
+    value
+        .split(',')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            Some((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Reads a variable that must be set to a non-empty value. An empty host,
+/// user or database would still make a well-formed startup packet, only to
+/// fail with a confusing error from the server.
+fn required_var(key: &str) -> Result<String, ConfigParseError> {
+    let value = env::var(key)?;
+    if value.is_empty() {
+        return Err(ConfigParseError::Incomplete(key.to_string()));
+    }
+    Ok(value)
+}
+
+/// Reads a variable that may be left unset.
+fn optional_var(key: &str) -> Result<Option<String>, ConfigParseError> {
+    match env::var(key) {
+        Ok(value) => Ok(Some(value)),
+        Err(VarError::NotPresent) => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Reads an optional variable, falling back to `default` when it isn't set.
+fn var_or(key: &str, default: &str) -> Result<String, ConfigParseError> {
+    match env::var(key) {
+        Ok(value) => Ok(value),
+        Err(VarError::NotPresent) => Ok(default.to_string()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::*;
+    use crate::mock::*;
+    use std::sync::{Mutex, PoisonError};
+
+    /// Serializes the tests that go through the process environment.
+    static ENV: Mutex<()> = Mutex::new(());
+
+    /// Loads a configuration with the required variables set, then `vars`
+    /// set on top of them, or removed for `None`.
+    fn load_with(vars: &[(&str, Option<&str>)]) -> Result<Config, ConfigParseError> {
+        let _guard = ENV.lock().unwrap_or_else(PoisonError::into_inner);
+        let required = [
+            ("HOST", Some("localhost")),
+            ("PORT", Some("5432")),
+            ("USER", Some("postgres")),
+            ("DATABASE", Some("postgres")),
+            ("PASSWORD", Some("pencil")),
+            ("QUERY", None),
+            ("QUERY_FILE", None),
+        ];
+        for (key, value) in required.iter().chain(vars) {
+            match value {
+                Some(value) => env::set_var(key, value),
+                None => env::remove_var(key),
+            }
+        }
+        load_config_from_env()
+    }
+
+    #[test]
+    fn an_empty_query_warns_and_completes() {
+        let config = load_with(&[("QUERY", Some(""))]).unwrap();
+        assert!(config.empty_query_warning().is_some());
+
+        let server = MockServer::start(|session| {
+            session.accept();
+            assert_eq!(session.expect_query(), "");
+            session.send(&[frame(b'I', b""), ready(b'I')].concat());
+        });
+        let mut connection = server.connect();
+        let report = run_benchmark(
+            &mut connection,
+            &config.query,
+            config.iterations,
+            config.query_mode,
+        )
+        .unwrap();
+        assert_eq!(report.iterations, 1);
+        assert_eq!(report.rows, 0);
+        drop(connection);
+        server.finish();
+    }
+
+    #[test]
+    fn parses_protocol_extensions() {
+        assert_eq!(
+            parse_protocol_extensions("_pq_.a=1, b = two,"),
+            Some(vec![
+                ("_pq_.a".to_string(), "1".to_string()),
+                ("b".to_string(), "two".to_string()),
+            ])
+        );
+        assert_eq!(parse_protocol_extensions(""), Some(vec![]));
+        assert_eq!(parse_protocol_extensions("a=1,b"), None);
+    }
+
+    #[test]
+    fn parses_negotiation_order() {
+        assert_eq!(NegotiationOrder::parse("ssl"), Some(NegotiationOrder::Ssl));
+        assert_eq!(
+            NegotiationOrder::parse("gss,ssl"),
+            Some(NegotiationOrder::GssThenSsl)
+        );
+        assert_eq!(NegotiationOrder::parse("gss"), None);
+    }
+
+    #[test]
+    fn application_name_falls_back_in_order() {
+        let name = |application: Option<&str>, fallback: Option<&str>| {
+            load_with(&[
+                ("APPLICATION_NAME", application),
+                ("FALLBACK_APPLICATION_NAME", fallback),
+            ])
+            .unwrap()
+            .effective_application_name()
+            .to_string()
+        };
+        assert_eq!(name(Some("app"), Some("fallback")), "app");
+        assert_eq!(name(None, Some("fallback")), "fallback");
+        assert_eq!(name(None, None), DEFAULT_APPLICATION_NAME);
+    }
+
+    #[test]
+    fn query_overrides_the_default_and_query_file() {
+        assert_eq!(load_with(&[]).unwrap().query, "SELECT 1");
+        let config = load_with(&[
+            ("QUERY", Some("SELECT now()")),
+            ("QUERY_FILE", Some("/nonexistent/query.sql")),
+        ])
+        .unwrap();
+        assert_eq!(config.query, "SELECT now()");
+    }
+
+    #[test]
+    fn an_empty_host_is_incomplete() {
+        let result = load_with(&[("HOST", Some(""))]);
+        assert!(
+            matches!(&result, Err(ConfigParseError::Incomplete(key)) if key == "HOST"),
+            "{:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn an_empty_user_is_incomplete() {
+        let result = load_with(&[("USER", Some(""))]);
+        assert!(
+            matches!(&result, Err(ConfigParseError::Incomplete(key)) if key == "USER"),
+            "{:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn an_empty_database_is_incomplete() {
+        let result = load_with(&[("DATABASE", Some(""))]);
+        assert!(
+            matches!(&result, Err(ConfigParseError::Incomplete(key)) if key == "DATABASE"),
+            "{:?}",
+            result
+        );
+    }
+}
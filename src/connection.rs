@@ -0,0 +1,2720 @@
+use crate::config::*;
+use crate::large_object::*;
+use crate::message::*;
+use crate::notify::*;
+use crate::observer::*;
+use crate::row::*;
+use crate::scram::*;
+use crate::sql::*;
+use crate::statement::*;
+use crate::stream::*;
+use crate::transport::*;
+use crate::types::*;
+use crate::wire_log::*;
+
+use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+#[cfg(feature = "flate2")]
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(feature = "flate2")]
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// The id given to the next connection opened, see [`Connection::id`].
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// A client connection to a PostgreSQL server.
+///
+/// Outgoing messages are buffered and only hit the socket when the connection
+/// is flushed, which `receive` always does before blocking on a read. A full
+/// extended-protocol exchange therefore costs a single write syscall instead
+/// of one per message. Reads are buffered too, sized by
+/// `config.initial_buffer_size`, so a burst of small replies takes one read.
+pub struct Connection {
+    stream: BufWriter<BufStream<Transport>>,
+    reader: MessageReader,
+    statement_count: u64,
+    /// Starts every generated statement name: the configured prefix and a
+    /// random part, so two connections are unlikely to pick the same names.
+    statement_prefix: String,
+    closed_statements: Rc<RefCell<Vec<String>>>,
+    fetch_size: u32,
+    max_rows: usize,
+    max_message_length: usize,
+    pub(crate) query_timeout: Option<Duration>,
+    read_only: Option<bool>,
+    pub(crate) large_object_functions: Option<LargeObjectFunctions>,
+    startup_notices: Vec<PgError>,
+    pub(crate) addr: String,
+    pub(crate) backend_key: Option<(u32, i32)>,
+    observer: Rc<dyn ConnectionObserver>,
+    broken: bool,
+    parameters: HashMap<String, String>,
+    unsupported_client_encoding: Option<String>,
+    auth_method: AuthMethod,
+    protocol_minor_version: u16,
+    rejected_protocol_extensions: Vec<String>,
+    pub(crate) notifications: VecDeque<Notification>,
+    transaction_status: ReadyForQueryStatus,
+    cursor_transaction: bool,
+    /// How many `ReadyForQuery` replies are still due for messages sent.
+    pending_ready: usize,
+    max_pipeline_depth: usize,
+    /// Replies read early to relieve a deep pipeline, not yet handed out.
+    backlog: VecDeque<BackendMessage>,
+    id: u64,
+    last_command_tag: Option<String>,
+    type_cache: TypeCache,
+    /// Statements prepared by [`Connection::query_by_keys`], by their SQL and
+    /// array type.
+    key_statements: HashMap<(String, u32), Statement>,
+    /// The timer from [`Connection::set_message_timer`], with when the last
+    /// message was read.
+    message_timer: Option<(MessageTimer, Instant)>,
+    wire_log: WireLog,
+}
+
+/// Called with the prefix of every message read, such as `'D'` for a
+/// `DataRow`, and the time elapsed since the one before it.
+pub type MessageTimer = Box<dyn FnMut(char, Duration)>;
+
+impl Connection {
+    pub fn connect(config: &Config) -> Result<Self, RuntimeError> {
+        Self::connect_with_observer(config, Rc::new(NoopObserver))
+    }
+
+    /// Connects while reporting lifecycle events to `observer`, which is kept
+    /// until the connection closes.
+    pub fn connect_with_observer(
+        config: &Config,
+        observer: Rc<dyn ConnectionObserver>,
+    ) -> Result<Self, RuntimeError> {
+        let result = Self::handshake(config, Rc::clone(&observer));
+        if let Err(error) = &result {
+            observer.on_error(error);
+        }
+        result
+    }
+
+    /// Runs [`Connection::establish`] within `config.connect_timeout`. Every
+    /// step fails once the deadline passes, and that failure is reported as
+    /// a timeout whatever form it took, such as a TLS handshake error.
+    fn handshake(
+        config: &Config,
+        observer: Rc<dyn ConnectionObserver>,
+    ) -> Result<Self, RuntimeError> {
+        let deadline = config
+            .connect_timeout
+            .map(|timeout| Instant::now() + timeout);
+        match Self::establish(config, observer, deadline) {
+            Err(_) if deadline.is_some_and(|deadline| Instant::now() >= deadline) => {
+                Err(RuntimeError::Timeout)
+            }
+            result => result,
+        }
+    }
+
+    fn establish(
+        config: &Config,
+        observer: Rc<dyn ConnectionObserver>,
+        deadline: Option<Instant>,
+    ) -> Result<Self, RuntimeError> {
+        let addr = format!("{}:{}", config.host, config.port);
+        observer.on_connect_start(&addr);
+
+        let mut client = connect_tcp(&addr, deadline)?;
+        observer.on_tcp_connected();
+        if let Some(size) = config.receive_buffer_size {
+            // Only a hint: the connection works the same without it.
+            let _ = set_receive_buffer_size(&client, size);
+        }
+        apply_deadline(&client, deadline)?;
+        if config.negotiation_order == NegotiationOrder::GssThenSsl {
+            request_gss_encryption(&mut client)?;
+        }
+        let transport = negotiate_tls(client, config)?;
+        if transport.is_tls() {
+            observer.on_tls_upgrade();
+        }
+
+        let id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
+
+        let mut connection = Self {
+            stream: BufWriter::new(BufStream::with_capacity(
+                config.initial_buffer_size,
+                transport,
+            )),
+            reader: MessageReader::with_capacity(config.initial_buffer_size),
+            statement_count: 0,
+            statement_prefix: format!("{}{:08x}_", config.statement_prefix, random_u32()),
+            closed_statements: Rc::default(),
+            fetch_size: 0,
+            max_rows: 0,
+            max_message_length: MAX_MESSAGE_LENGTH as usize,
+            query_timeout: None,
+            read_only: None,
+            large_object_functions: None,
+            startup_notices: vec![],
+            addr,
+            backend_key: None,
+            observer,
+            broken: false,
+            parameters: HashMap::new(),
+            unsupported_client_encoding: None,
+            auth_method: AuthMethod::Trust,
+            protocol_minor_version: config.protocol_minor_version,
+            rejected_protocol_extensions: vec![],
+            notifications: VecDeque::new(),
+            transaction_status: ReadyForQueryStatus::Idle,
+            cursor_transaction: false,
+            pending_ready: 0,
+            max_pipeline_depth: 0,
+            backlog: VecDeque::new(),
+            id,
+            last_command_tag: None,
+            type_cache: TypeCache::new(),
+            key_statements: HashMap::new(),
+            message_timer: None,
+            wire_log: WireLog::default(),
+        };
+
+        connection.send(FrontendMessage::StartupMessage {
+            user: config.user.clone(),
+            database: config.database.clone(),
+            minor_version: config.protocol_minor_version,
+            parameters: startup_parameters(config),
+        })?;
+
+        let mut scram = None;
+
+        loop {
+            apply_deadline(connection.stream.get_ref().get_ref().tcp_stream(), deadline)?;
+            match connection.receive()? {
+                BackendMessage::AuthenticationCleartextPassword => {
+                    if config.channel_binding == ChannelBinding::Require {
+                        return Err(RuntimeError::ChannelBindingRequired);
+                    }
+                    connection.auth_method = AuthMethod::CleartextPassword;
+                    connection.send(FrontendMessage::PasswordMessage {
+                        password: config.password.clone(),
+                    })?
+                }
+                BackendMessage::AuthenticationSasl { mechanisms } => {
+                    let client = connection.start_scram(config, &mechanisms)?;
+                    connection.auth_method = AuthMethod::Sasl {
+                        offered: mechanisms,
+                        selected: client.mechanism().to_string(),
+                    };
+                    scram = Some(client);
+                }
+                BackendMessage::AuthenticationSaslContinue { data } => {
+                    let client = scram.as_mut().ok_or(RuntimeError::ParseMessageError)?;
+                    let server_first =
+                        std::str::from_utf8(&data).map_err(|_| RuntimeError::ParseMessageError)?;
+                    let client_final = client.client_final(server_first)?;
+                    connection.send(FrontendMessage::SaslResponse {
+                        data: client_final.into_bytes(),
+                    })?;
+                }
+                BackendMessage::AuthenticationSaslFinal { data } => {
+                    let client = scram.as_ref().ok_or(RuntimeError::ParseMessageError)?;
+                    let server_final =
+                        std::str::from_utf8(&data).map_err(|_| RuntimeError::ParseMessageError)?;
+                    client.verify_server_final(server_final)?;
+                }
+                BackendMessage::AuthenticationGss => {
+                    return Err(RuntimeError::UnsupportedAuth("GSSAPI"))
+                }
+                BackendMessage::AuthenticationSspi => {
+                    return Err(RuntimeError::UnsupportedAuth("SSPI"))
+                }
+                // Any other authentication request would otherwise leave both
+                // sides waiting on each other.
+                BackendMessage::Unknown { prefix: 'R', .. } => {
+                    return Err(RuntimeError::UnsupportedAuth("unknown"))
+                }
+                BackendMessage::ErrorResponse(fields) => {
+                    return Err(RuntimeError::PgError(PgError { fields }))
+                }
+                BackendMessage::NoticeResponse(fields) => {
+                    connection.startup_notices.push(PgError { fields })
+                }
+                BackendMessage::BackendKeyData {
+                    process_id,
+                    secret_key,
+                } => connection.backend_key = Some((process_id, secret_key)),
+                BackendMessage::NegotiateProtocolVersion {
+                    newest_minor_version,
+                    unrecognized_options,
+                } => {
+                    connection.protocol_minor_version = connection
+                        .protocol_minor_version
+                        .min(newest_minor_version.try_into().unwrap_or(u16::MAX));
+                    connection.rejected_protocol_extensions = unrecognized_options;
+                }
+                BackendMessage::AuthenticationOk => connection.observer.on_authenticated(),
+                BackendMessage::ReadyForQuery { .. } => {
+                    apply_deadline(connection.stream.get_ref().get_ref().tcp_stream(), None)?;
+                    return Ok(connection);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Ends the session cleanly; the same as dropping the connection.
+    pub fn close(self) {}
+
+    /// Picks a SCRAM mechanism among those offered and sends the client's
+    /// first message. Channel binding is used when the server offers it over
+    /// TLS, unless `config.channel_binding` says otherwise.
+    fn start_scram(
+        &mut self,
+        config: &Config,
+        mechanisms: &[String],
+    ) -> Result<ScramClient, RuntimeError> {
+        let offers = |name: &str| mechanisms.iter().any(|mechanism| mechanism == name);
+
+        let end_point = match config.channel_binding {
+            ChannelBinding::Disable => None,
+            _ => self.stream.get_ref().get_ref().tls_server_end_point(),
+        };
+
+        let channel_binding = match (end_point, config.channel_binding) {
+            (Some(hash), _) if offers(SCRAM_SHA_256_PLUS) => {
+                ChannelBindingData::TlsServerEndPoint(hash)
+            }
+            (_, ChannelBinding::Require) => return Err(RuntimeError::ChannelBindingRequired),
+            (Some(_), _) if offers(SCRAM_SHA_256) => ChannelBindingData::NotOffered,
+            _ if offers(SCRAM_SHA_256) => ChannelBindingData::None,
+            _ => return Err(RuntimeError::AuthenticationFailed),
+        };
+
+        let client = ScramClient::new(&config.password, channel_binding);
+        self.send(FrontendMessage::SaslInitialResponse {
+            mechanism: client.mechanism().to_string(),
+            data: client.client_first().into_bytes(),
+        })?;
+        Ok(client)
+    }
+
+    /// The protocol minor version in use, after negotiating with the server.
+    pub fn protocol_minor_version(&self) -> u16 {
+        self.protocol_minor_version
+    }
+
+    /// The `_pq_.` protocol options the server didn't recognize, with the
+    /// prefix.
+    pub fn rejected_protocol_extensions(&self) -> &[String] {
+        &self.rejected_protocol_extensions
+    }
+
+    /// How the server asked this connection to authenticate.
+    pub fn auth_method(&self) -> &AuthMethod {
+        &self.auth_method
+    }
+
+    /// The latest value the server reported for a run-time parameter, such
+    /// as `server_version` or `search_path`.
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters.get(name).map(|value| value.as_str())
+    }
+
+    /// The `client_encoding` the server reported, if it isn't UTF-8.
+    ///
+    /// Every string is decoded as UTF-8, so while this is set, text values
+    /// and even messages may fail to parse or come out garbled.
+    pub fn unsupported_client_encoding(&self) -> Option<&str> {
+        self.unsupported_client_encoding.as_deref()
+    }
+
+    /// Whether binary timestamps are 64-bit integers rather than floats.
+    /// Only servers built before 10 with `--disable-integer-datetimes` say no.
+    pub fn integer_datetimes(&self) -> bool {
+        self.parameter("integer_datetimes") != Some("off")
+    }
+
+    /// The session's `TimeZone`, as reported by the server.
+    pub fn timezone(&self) -> Option<&str> {
+        self.parameter("TimeZone")
+    }
+
+    /// Decodes a binary `timestamp` or `timestamptz` into microseconds since
+    /// 2000-01-01, in whichever representation the server uses.
+    pub fn decode_timestamp(&self, value: &[u8]) -> Option<Timestamp> {
+        decode_timestamp(value, self.integer_datetimes())
+    }
+
+    /// Decodes a text `money` value into cents, using the session's
+    /// `lc_monetary` if the server reported it. See [`decode_money`] for why
+    /// this is a best effort.
+    pub fn decode_money(&self, value: &str) -> Option<i64> {
+        decode_money(value, self.parameter("lc_monetary"))
+    }
+
+    /// Decodes a text `interval` in the session's `IntervalStyle`.
+    pub fn decode_interval(&self, value: &str) -> Option<Interval> {
+        decode_interval(value, self.parameter("IntervalStyle"))
+    }
+
+    /// Reads every type in `pg_type` into the connection's [`TypeCache`],
+    /// replacing what was loaded before. Types created afterwards need
+    /// another call.
+    pub fn load_type_cache(&mut self) -> Result<(), RuntimeError> {
+        let mut cache = TypeCache::new();
+        for row in &self.query(TYPE_CACHE_QUERY)?.rows {
+            let (oid, info) = parse_type_row(row).ok_or(RuntimeError::ParseMessageError)?;
+            cache.insert(oid, info);
+        }
+        self.type_cache = cache;
+        Ok(())
+    }
+
+    /// The types loaded by [`Connection::load_type_cache`], empty until then.
+    pub fn type_cache(&self) -> &TypeCache {
+        &self.type_cache
+    }
+
+    /// Notices the server sent before the connection was ready, such as a
+    /// password expiry warning. They carry the same fields as errors.
+    pub fn startup_notices(&self) -> &[PgError] {
+        &self.startup_notices
+    }
+
+    /// A number unique to this connection within the process, assigned in
+    /// the order connections are opened. It tags the connection's wire log.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Sends the wire log, from here on, through gzip into a new file at
+    /// `path` instead of to stdout. The file is finalized when the connection
+    /// is closed or dropped.
+    #[cfg(feature = "flate2")]
+    pub fn enable_wire_log_gzip(&mut self, path: &Path) -> Result<(), RuntimeError> {
+        let log = WireLog::gzip(File::create(path)?);
+        std::mem::replace(&mut self.wire_log, log).finish();
+        Ok(())
+    }
+
+    /// Details of the TLS session, or `None` if the connection isn't encrypted.
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+        self.stream.get_ref().get_ref().tls_info()
+    }
+
+    /// Queues a message without writing it to the socket.
+    pub fn send(&mut self, message: FrontendMessage) -> Result<(), RuntimeError> {
+        // Encoded before anything is written, so a message too large for its
+        // length field, or for the limit, leaves the stream untouched. Every
+        // message sent here has a type byte ahead of its length.
+        let bytes = message.to_bytes()?;
+        let length = bytes.len() - 1;
+        if length > self.max_message_length {
+            return Err(RuntimeError::MessageTooLarge {
+                length,
+                max: self.max_message_length,
+            });
+        }
+        // Closes otherwise wait for the next `Sync`, which a caller sticking
+        // to simple queries never sends.
+        if matches!(
+            message,
+            FrontendMessage::SimpleQuery { .. } | FrontendMessage::Parse { .. }
+        ) {
+            self.send_closes()?;
+        }
+        if matches!(
+            message,
+            FrontendMessage::Sync
+                | FrontendMessage::SimpleQuery { .. }
+                | FrontendMessage::FunctionCall { .. }
+        ) {
+            if self.max_pipeline_depth > 0 && self.pending_ready >= self.max_pipeline_depth {
+                self.relieve_pipeline()?;
+            }
+            self.pending_ready += 1;
+        }
+        let result = write_message(
+            &mut self.stream,
+            &mut self.wire_log,
+            self.id,
+            &message,
+            &bytes,
+        );
+        self.track(result)
+    }
+
+    /// Queues a `SimpleQuery`, refusing one with an embedded null byte: the
+    /// server reads the query as a C string and would silently run only the
+    /// part before it.
+    pub(crate) fn send_query(&mut self, query: &str) -> Result<(), RuntimeError> {
+        if query.contains('\0') {
+            return Err(RuntimeError::InvalidQuery("contains null byte"));
+        }
+        self.send(FrontendMessage::SimpleQuery {
+            query: query.to_string(),
+        })
+    }
+
+    /// Writes every queued message to the socket.
+    pub fn flush(&mut self) -> Result<(), RuntimeError> {
+        let result = self.stream.flush();
+        self.track(result)
+    }
+
+    /// Flushes the queued messages, then blocks until the next backend message.
+    ///
+    /// Flushing first matters: reading while the server is still waiting on
+    /// a message sitting in our buffer would deadlock both sides.
+    ///
+    /// Notifications can arrive at any point, so they're set aside for
+    /// [`Connection::poll_notification`] instead of being returned.
+    pub fn receive(&mut self) -> Result<BackendMessage, RuntimeError> {
+        loop {
+            match self.receive_unfiltered()? {
+                BackendMessage::NotificationResponse {
+                    process_id,
+                    channel,
+                    payload,
+                } => self.notifications.push_back(Notification {
+                    process_id,
+                    channel,
+                    payload,
+                }),
+                message => return Ok(message),
+            }
+        }
+    }
+
+    /// Reads replies up to the oldest pending `ReadyForQuery`, setting them
+    /// aside for the read loops to pick up later, so that a pipeline at its
+    /// [`Connection::set_max_pipeline_depth`] has room for one more.
+    fn relieve_pipeline(&mut self) -> Result<(), RuntimeError> {
+        let target = self.pending_ready - 1;
+        while self.pending_ready > target {
+            match self.read_backend_message()? {
+                BackendMessage::NotificationResponse {
+                    process_id,
+                    channel,
+                    payload,
+                } => self.notifications.push_back(Notification {
+                    process_id,
+                    channel,
+                    payload,
+                }),
+                message => self.backlog.push_back(message),
+            }
+        }
+        Ok(())
+    }
+
+    /// Like [`Connection::receive`], but hands back notifications rather
+    /// than queueing them.
+    pub(crate) fn receive_unfiltered(&mut self) -> Result<BackendMessage, RuntimeError> {
+        match self.backlog.pop_front() {
+            Some(message) => Ok(message),
+            None => self.read_backend_message(),
+        }
+    }
+
+    /// Flushes, then reads the next message off the socket.
+    fn read_backend_message(&mut self) -> Result<BackendMessage, RuntimeError> {
+        self.flush()?;
+        let result = self.reader.read_frame(self.stream.get_mut());
+        let (prefix, length) = self.track(result)?;
+        self.time_message(prefix);
+        let message = self.reader.parse_frame(prefix, length)?;
+        self.observe(&message);
+        Ok(message)
+    }
+
+    /// Reports a message just read to the [`MessageTimer`], if one is set.
+    fn time_message(&mut self, prefix: char) {
+        if let Some((timer, previous)) = &mut self.message_timer {
+            let now = Instant::now();
+            timer(prefix, now - *previous);
+            *previous = now;
+        }
+    }
+
+    /// Updates the session state a message reports on, whichever read loop
+    /// it turns up in. A `ParameterStatus` can arrive at any point, even
+    /// between two `DataRow`s after a `SET`; the loops themselves skip it.
+    fn observe(&mut self, message: &BackendMessage) {
+        self.wire_log.log(self.id, "<--", message);
+        match message {
+            BackendMessage::ReadyForQuery { status } => {
+                self.transaction_status = *status;
+                self.pending_ready = self.pending_ready.saturating_sub(1);
+            }
+            BackendMessage::CommandComplete { tag } => {
+                self.last_command_tag = Some(tag.clone());
+            }
+            BackendMessage::ParameterStatus { name, value } => {
+                if name == "client_encoding" {
+                    self.unsupported_client_encoding =
+                        (!is_utf8_encoding(value)).then(|| value.clone());
+                }
+                self.parameters.insert(name.clone(), value.clone());
+            }
+            _ => {}
+        }
+    }
+
+    /// The tag of the last `CommandComplete`, such as `CREATE TABLE` or
+    /// `MERGE 5`, whichever call it came from.
+    pub fn last_command_tag(&self) -> Option<&str> {
+        self.last_command_tag.as_deref()
+    }
+
+    /// The transaction state as of the last `ReadyForQuery`.
+    pub fn transaction_status(&self) -> ReadyForQueryStatus {
+        self.transaction_status
+    }
+
+    /// Like [`Connection::receive`], but returns `None` for a `DataRow`
+    /// without ever decoding its columns.
+    pub fn receive_skipping_rows(&mut self) -> Result<Option<BackendMessage>, RuntimeError> {
+        if let Some(message) = self.backlog.pop_front() {
+            return Ok(match message {
+                BackendMessage::DataRow { .. } => None,
+                message => Some(message),
+            });
+        }
+        self.flush()?;
+        let result = self.reader.read_frame(self.stream.get_mut());
+        let (prefix, length) = self.track(result)?;
+        self.time_message(prefix);
+        if prefix == 'D' {
+            return Ok(None);
+        }
+        let message = self.reader.parse_frame(prefix, length)?;
+        self.observe(&message);
+        match message {
+            BackendMessage::NotificationResponse {
+                process_id,
+                channel,
+                payload,
+            } => {
+                self.notifications.push_back(Notification {
+                    process_id,
+                    channel,
+                    payload,
+                });
+                self.receive_skipping_rows()
+            }
+            message => Ok(Some(message)),
+        }
+    }
+
+    /// Waits up to `timeout` for the server to send something, without
+    /// reading it. Bytes already decrypted by the TLS layer aren't seen, so
+    /// callers should check for messages they've queued first.
+    pub(crate) fn wait_readable(&mut self, timeout: Duration) -> Result<bool, RuntimeError> {
+        self.flush()?;
+        if !self.backlog.is_empty() || self.stream.get_ref().has_buffered() {
+            return Ok(true);
+        }
+        let socket = self.stream.get_ref().get_ref().tcp_stream();
+        socket.set_read_timeout(Some(timeout.max(Duration::from_micros(1))))?;
+        let result = socket.peek(&mut [0u8; 1]);
+        socket.set_read_timeout(None)?;
+        match result {
+            Ok(_) => Ok(true),
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Ok(false)
+            }
+            Err(error) => {
+                self.broken = true;
+                Err(error.into())
+            }
+        }
+    }
+
+    /// Whether a read or write on the socket has failed, after which the
+    /// connection can't be trusted to be in sync with the server.
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    /// Records a socket failure in `result` before passing it on.
+    fn track<T, E: Into<RuntimeError>>(&mut self, result: Result<T, E>) -> Result<T, RuntimeError> {
+        let result = result.map_err(Into::into);
+        if let Err(RuntimeError::IoError(_) | RuntimeError::Desynchronized { .. }) = &result {
+            self.broken = true;
+        }
+        result
+    }
+
+    /// Queues a `Sync`, preceded by a `Close` for every dropped [`Statement`].
+    pub fn sync(&mut self) -> Result<(), RuntimeError> {
+        self.send_closes()?;
+        self.send(FrontendMessage::Sync)
+    }
+
+    /// Queues a `Close` for every [`Statement`] dropped since the last call.
+    fn send_closes(&mut self) -> Result<(), RuntimeError> {
+        let closed: Vec<String> = self.closed_statements.borrow_mut().drain(..).collect();
+        for name in closed {
+            self.send(FrontendMessage::Close {
+                target: Target::Statement,
+                name,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Reads up to the next `ReadyForQuery`, returning the last command tag.
+    /// Rows are skipped without being decoded.
+    ///
+    /// An `ErrorResponse` doesn't stop the read: the server still sends
+    /// `ReadyForQuery` after it, and leaving that unread would desync the
+    /// next exchange.
+    pub(crate) fn read_until_ready(&mut self) -> Result<Option<String>, RuntimeError> {
+        let mut tag = None;
+        let mut error = None;
+
+        loop {
+            match self.receive_skipping_rows()? {
+                Some(BackendMessage::CommandComplete { tag: t }) => tag = Some(t),
+                Some(BackendMessage::ErrorResponse(fields)) => error = Some(PgError { fields }),
+                Some(BackendMessage::ReadyForQuery { .. }) => {
+                    return match error {
+                        Some(error) => Err(RuntimeError::PgError(error)),
+                        None => Ok(tag),
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Reads and discards whatever the server still owes for messages already
+    /// sent, up to their last `ReadyForQuery`, and returns the transaction
+    /// status it reports. Errors among the discarded messages are dropped.
+    ///
+    /// Returns right away when nothing is pending, so it's safe to call before
+    /// reusing a connection whose state is unknown, such as after an error or
+    /// a cancelled query.
+    pub fn drain_to_ready(&mut self) -> Result<ReadyForQueryStatus, RuntimeError> {
+        while self.pending_ready > 0 {
+            self.receive_skipping_rows()?;
+        }
+        self.backlog.clear();
+        Ok(self.transaction_status)
+    }
+
+    /// Runs a simple query and collects its rows.
+    ///
+    /// When the query string holds several statements, the result is that of
+    /// the last one to complete.
+    pub fn query(&mut self, query: &str) -> Result<ResultSet, RuntimeError> {
+        self.with_query_timeout(|connection| connection.run_query(query))
+    }
+
+    fn run_query(&mut self, query: &str) -> Result<ResultSet, RuntimeError> {
+        self.send_query(query)?;
+
+        let mut current = ResultSet::default();
+        let mut last = None;
+        let mut error = None;
+        let mut collected = 0;
+
+        loop {
+            match self.receive()? {
+                BackendMessage::RowDescription { fields } => current.fields = fields,
+                BackendMessage::DataRow { columns } => {
+                    collected += 1;
+                    if self.max_rows > 0 && collected > self.max_rows {
+                        // The server would otherwise send every row anyway. If
+                        // the cancel can't be sent, they're read and dropped.
+                        let cancelled = self.cancel_token().cancel();
+                        self.drain_to_ready()?;
+                        cancelled?;
+                        return Err(RuntimeError::RowLimitExceeded);
+                    }
+                    current.rows.push(Row::new(columns));
+                }
+                BackendMessage::CommandComplete { tag } => {
+                    current.tag = Some(tag);
+                    last = Some(std::mem::take(&mut current));
+                }
+                BackendMessage::ErrorResponse(fields) => error = Some(PgError { fields }),
+                BackendMessage::ReadyForQuery { .. } => {
+                    return match error {
+                        Some(error) => Err(RuntimeError::PgError(error)),
+                        None => Ok(last.unwrap_or(current)),
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Executes the prepared `statement` once per parameter set, and returns
+    /// the rows each execution affected. Every `Bind`/`Execute` pair is sent,
+    /// each with its own `Sync`, before any reply is read.
+    ///
+    /// Since every execution is synced separately, one failing doesn't stop
+    /// the others; outside an explicit transaction, those that succeeded stay
+    /// committed. All replies are read either way, leaving the connection
+    /// ready, and the first error is returned.
+    pub fn execute_batch(
+        &mut self,
+        statement: &str,
+        param_sets: &[Vec<&dyn ToSql>],
+    ) -> Result<Vec<u64>, RuntimeError> {
+        for params in param_sets {
+            self.send(FrontendMessage::Bind {
+                portal: UNNAMED.to_string(),
+                statement: statement.to_string(),
+                param_formats: params.iter().map(|param| param.format()).collect(),
+                params: params.iter().map(|param| param.to_sql()).collect(),
+                result_formats: vec![],
+            })?;
+            self.send(FrontendMessage::Execute {
+                portal: UNNAMED.to_string(),
+                max_rows: 0,
+            })?;
+            self.sync()?;
+        }
+
+        let mut counts = Vec::with_capacity(param_sets.len());
+        let mut first_error = None;
+        for _ in param_sets {
+            match self.read_until_ready() {
+                Ok(tag) => counts.push(tag.as_deref().map(rows_affected).unwrap_or(0)),
+                Err(RuntimeError::PgError(error)) => {
+                    first_error.get_or_insert(error);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        match first_error {
+            Some(error) => Err(RuntimeError::PgError(error)),
+            None => Ok(counts),
+        }
+    }
+
+    /// Runs `query` through the extended protocol, binding each of `params`
+    /// in the format and with the type its [`ToSql`] impl gives, and returns
+    /// the number of rows affected. Byte slices go out as binary `bytea`.
+    pub fn execute_params(
+        &mut self,
+        query: &str,
+        params: &[&dyn ToSql],
+    ) -> Result<u64, RuntimeError> {
+        self.send(FrontendMessage::Parse {
+            name: UNNAMED.to_string(),
+            query: query.to_string(),
+            param_types: params.iter().map(|param| param.type_oid()).collect(),
+        })?;
+        self.send(FrontendMessage::Bind {
+            portal: UNNAMED.to_string(),
+            statement: UNNAMED.to_string(),
+            param_formats: params.iter().map(|param| param.format()).collect(),
+            params: params.iter().map(|param| param.to_sql()).collect(),
+            result_formats: vec![],
+        })?;
+        self.send(FrontendMessage::Execute {
+            portal: UNNAMED.to_string(),
+            max_rows: 0,
+        })?;
+        self.sync()?;
+
+        let tag = self.read_until_ready()?;
+        Ok(tag.as_deref().map(rows_affected).unwrap_or(0))
+    }
+
+    /// Runs `query` through the extended protocol with text `params`, asking
+    /// for each result column in the matching entry of `formats`. A single
+    /// format applies to every column, and none means all text.
+    pub fn query_typed_with_column_formats(
+        &mut self,
+        query: &str,
+        params: &[Option<&str>],
+        formats: &[Format],
+    ) -> Result<ResultSet, RuntimeError> {
+        self.send(FrontendMessage::Parse {
+            name: UNNAMED.to_string(),
+            query: query.to_string(),
+            param_types: vec![],
+        })?;
+        self.send(FrontendMessage::Bind {
+            portal: UNNAMED.to_string(),
+            statement: UNNAMED.to_string(),
+            param_formats: vec![],
+            params: params
+                .iter()
+                .map(|param| param.map(|value| value.as_bytes().to_vec()))
+                .collect(),
+            result_formats: formats.to_vec(),
+        })?;
+        self.send(FrontendMessage::Describe {
+            target: Target::Portal,
+            name: UNNAMED.to_string(),
+        })?;
+        self.send(FrontendMessage::Execute {
+            portal: UNNAMED.to_string(),
+            max_rows: 0,
+        })?;
+        self.sync()?;
+
+        self.read_result_set()
+    }
+
+    /// Runs `sql_template`, which takes the whole of `keys` as its single
+    /// parameter `$1`, such as `SELECT * FROM users WHERE id = ANY($1)`.
+    ///
+    /// Binding one array instead of a placeholder per key keeps the SQL the
+    /// same whatever the number of keys, so the statement is prepared on
+    /// first use and its plan reused for every later key set.
+    pub fn query_by_keys<T: ArrayElement>(
+        &mut self,
+        sql_template: &str,
+        keys: &[T],
+    ) -> Result<ResultSet, RuntimeError> {
+        let cache_key = (sql_template.to_string(), T::ARRAY_OID);
+        if !self.key_statements.contains_key(&cache_key) {
+            let statement = self.prepare_with_types(sql_template, vec![T::ARRAY_OID])?;
+            self.key_statements.insert(cache_key.clone(), statement);
+        }
+        let statement = self.key_statements[&cache_key].name().to_string();
+
+        self.send(FrontendMessage::Bind {
+            portal: UNNAMED.to_string(),
+            statement,
+            param_formats: vec![keys.format()],
+            params: vec![keys.to_sql()],
+            result_formats: vec![],
+        })?;
+        self.send(FrontendMessage::Describe {
+            target: Target::Portal,
+            name: UNNAMED.to_string(),
+        })?;
+        self.send(FrontendMessage::Execute {
+            portal: UNNAMED.to_string(),
+            max_rows: 0,
+        })?;
+        self.sync()?;
+
+        self.read_result_set()
+    }
+
+    /// Collects the rows of a described portal up to `ReadyForQuery`.
+    fn read_result_set(&mut self) -> Result<ResultSet, RuntimeError> {
+        let mut result = ResultSet::default();
+        let mut error = None;
+        loop {
+            match self.receive()? {
+                BackendMessage::RowDescription { fields } => result.fields = fields,
+                BackendMessage::DataRow { columns } => result.rows.push(Row::new(columns)),
+                BackendMessage::CommandComplete { tag } => result.tag = Some(tag),
+                BackendMessage::ErrorResponse(fields) => error = Some(PgError { fields }),
+                BackendMessage::ReadyForQuery { .. } => {
+                    return match error {
+                        Some(error) => Err(RuntimeError::PgError(error)),
+                        None => Ok(result),
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Opens a server-side cursor over `query`. Cursors only live inside a
+    /// transaction, so one is started if none is open, and committed by
+    /// [`Connection::close_cursor`].
+    pub fn declare_cursor(&mut self, name: &str, query: &str) -> Result<(), RuntimeError> {
+        if self.transaction_status == ReadyForQueryStatus::Idle {
+            self.execute("BEGIN")?;
+            self.cursor_transaction = true;
+        }
+        let declared = self.execute(&format!(
+            "DECLARE {} CURSOR FOR {}",
+            quote_identifier(name),
+            query
+        ));
+        if declared.is_err() && std::mem::take(&mut self.cursor_transaction) {
+            self.execute("ROLLBACK")?;
+        }
+        declared.map(|_| ())
+    }
+
+    /// The next `count` rows of a cursor; fewer means it's exhausted.
+    pub fn fetch(&mut self, name: &str, count: usize) -> Result<ResultSet, RuntimeError> {
+        self.query(&format!("FETCH {} FROM {}", count, quote_identifier(name)))
+    }
+
+    pub fn close_cursor(&mut self, name: &str) -> Result<(), RuntimeError> {
+        self.execute(&format!("CLOSE {}", quote_identifier(name)))?;
+        if std::mem::take(&mut self.cursor_transaction) {
+            self.execute("COMMIT")?;
+        }
+        Ok(())
+    }
+
+    /// Changes the `application_name` reported in `pg_stat_activity`.
+    pub fn set_application_name(&mut self, name: &str) -> Result<(), RuntimeError> {
+        self.execute(&format!("SET application_name = {}", quote_literal(name)))?;
+        Ok(())
+    }
+
+    /// Whether the server only accepts read-only transactions, as on a hot
+    /// standby. The answer is cached for the lifetime of the connection.
+    pub fn is_read_only(&mut self) -> Result<bool, RuntimeError> {
+        if let Some(read_only) = self.read_only {
+            return Ok(read_only);
+        }
+
+        let result = self.query("SHOW transaction_read_only")?;
+        let read_only = result
+            .rows
+            .first()
+            .and_then(|row| row.get_str(0))
+            .and_then(parse_bool)
+            .ok_or(RuntimeError::ParseMessageError)?;
+
+        self.read_only = Some(read_only);
+        Ok(read_only)
+    }
+
+    /// Runs a simple query for its side effects, returning the number of rows
+    /// it affected. Any rows it returns are discarded without being decoded,
+    /// which makes this the entry point for write benchmarks.
+    pub fn execute(&mut self, query: &str) -> Result<u64, RuntimeError> {
+        self.with_query_timeout(|connection| connection.run_execute(query))
+    }
+
+    fn run_execute(&mut self, query: &str) -> Result<u64, RuntimeError> {
+        self.send_query(query)?;
+
+        let tag = self.read_until_ready()?;
+        Ok(tag.as_deref().map(rows_affected).unwrap_or(0))
+    }
+
+    /// Runs a statement that returns no rows, such as `CREATE INDEX` or
+    /// `VACUUM`, and returns its command tag. Notices it raises along the way
+    /// are skipped.
+    ///
+    /// A statement that does return rows is run to completion, then reported
+    /// as an unexpected `RowDescription`.
+    pub fn execute_simple(&mut self, sql: &str) -> Result<String, RuntimeError> {
+        self.with_query_timeout(|connection| connection.run_execute_simple(sql))
+    }
+
+    fn run_execute_simple(&mut self, sql: &str) -> Result<String, RuntimeError> {
+        self.send_query(sql)?;
+
+        let mut tag = String::new();
+        let mut returned_rows = false;
+        let mut error = None;
+        loop {
+            match self.receive()? {
+                BackendMessage::CommandComplete { tag: t } => tag = t,
+                BackendMessage::RowDescription { .. } => returned_rows = true,
+                BackendMessage::ErrorResponse(fields) => error = Some(PgError { fields }),
+                BackendMessage::ReadyForQuery { .. } => break,
+                _ => {}
+            }
+        }
+
+        match error {
+            Some(error) => Err(RuntimeError::PgError(error)),
+            None if returned_rows => Err(RuntimeError::UnexpectedMessage { prefix: 'T' }),
+            None => Ok(tag),
+        }
+    }
+
+    /// A statement name not used before on this connection, such as
+    /// `toydrv_1a2b3c4d_0`.
+    fn next_statement_name(&mut self) -> String {
+        let name = format!("{}{}", self.statement_prefix, self.statement_count);
+        self.statement_count += 1;
+        name
+    }
+
+    /// Creates a named prepared statement, closed on the server once the
+    /// returned handle is dropped and the connection next syncs.
+    pub fn prepare(&mut self, query: &str) -> Result<Statement, RuntimeError> {
+        self.prepare_with_types(query, vec![])
+    }
+
+    /// Prepares `query` declaring its parameters as `param_types`, where 0
+    /// leaves one to the server to infer.
+    fn prepare_with_types(
+        &mut self,
+        query: &str,
+        param_types: Vec<u32>,
+    ) -> Result<Statement, RuntimeError> {
+        let name = self.next_statement_name();
+
+        self.send(FrontendMessage::Parse {
+            name: name.clone(),
+            query: query.to_string(),
+            param_types,
+        })?;
+        self.sync()?;
+        self.read_until_ready()?;
+
+        Ok(Statement::new(name, Rc::clone(&self.closed_statements)))
+    }
+
+    /// Like [`Connection::prepare`], but also describes the statement in the
+    /// same round trip, capturing the parameter types the server inferred
+    /// and the result columns.
+    pub fn prepare_inferred(&mut self, query: &str) -> Result<PreparedStatement, RuntimeError> {
+        let name = self.next_statement_name();
+
+        self.send(FrontendMessage::Parse {
+            name: name.clone(),
+            query: query.to_string(),
+            param_types: vec![],
+        })?;
+        self.send(FrontendMessage::Describe {
+            target: Target::Statement,
+            name: name.clone(),
+        })?;
+        self.sync()?;
+
+        let mut param_types = vec![];
+        let mut fields = vec![];
+        let mut error = None;
+        loop {
+            match self.receive()? {
+                BackendMessage::ParameterDescription { types } => param_types = types,
+                BackendMessage::RowDescription { fields: columns } => fields = columns,
+                BackendMessage::ErrorResponse(error_fields) => {
+                    error = Some(PgError {
+                        fields: error_fields,
+                    })
+                }
+                BackendMessage::ReadyForQuery { .. } => break,
+                _ => {}
+            }
+        }
+        if let Some(error) = error {
+            return Err(RuntimeError::PgError(error));
+        }
+
+        let statement = Statement::new(name, Rc::clone(&self.closed_statements));
+        Ok(PreparedStatement::new(statement, param_types, fields))
+    }
+
+    /// Calls a server function by OID through the fast-path interface, with
+    /// arguments and result in binary format.
+    pub fn function_call(
+        &mut self,
+        oid: u32,
+        args: Vec<Option<Vec<u8>>>,
+    ) -> Result<Option<Vec<u8>>, RuntimeError> {
+        self.send(FrontendMessage::FunctionCall { oid, args })?;
+
+        let mut result = None;
+        let mut error = None;
+
+        loop {
+            match self.receive()? {
+                BackendMessage::FunctionCallResponse { value } => result = value,
+                BackendMessage::ErrorResponse(fields) => error = Some(PgError { fields }),
+                BackendMessage::ReadyForQuery { .. } => {
+                    return match error {
+                        Some(error) => Err(RuntimeError::PgError(error)),
+                        None => Ok(result),
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The longest message [`Connection::send`] lets through, as the value
+    /// of its length field.
+    pub fn max_message_length(&self) -> usize {
+        self.max_message_length
+    }
+
+    /// Lowers the limit on outgoing messages, by default the server's own 1 GB
+    /// ([`MAX_MESSAGE_LENGTH`]), for servers or proxies that accept less.
+    pub fn set_max_message_length(&mut self, max_message_length: usize) {
+        self.max_message_length = max_message_length;
+    }
+
+    /// Caps the number of columns accepted in a result, 1600 by default.
+    pub fn set_max_columns(&mut self, max_columns: usize) {
+        self.reader.set_max_columns(max_columns);
+    }
+
+    /// Turns unknown backend messages into errors instead of skipping them.
+    /// See [`MessageReader::set_strict`].
+    pub fn set_strict(&mut self, strict: bool) {
+        self.reader.set_strict(strict);
+    }
+
+    /// Starts or stops splitting the time spent receiving messages into
+    /// waiting on the socket and parsing. See [`MessageReader::set_timed`].
+    pub fn set_read_timing(&mut self, timed: bool) {
+        self.reader.set_timed(timed);
+    }
+
+    /// The read timings accumulated since the last call, or `None` when
+    /// [`Connection::set_read_timing`] is off.
+    pub fn take_read_timings(&mut self) -> Option<ReadTimings> {
+        self.reader.take_timings()
+    }
+
+    /// Calls `timer` for every message read from now on, to profile the gaps
+    /// between them, such as from `RowDescription` to the first `DataRow`.
+    /// The first gap is measured from this call. `None` removes the timer,
+    /// and without one nothing is timed.
+    pub fn set_message_timer(&mut self, timer: Option<MessageTimer>) {
+        self.message_timer = timer.map(|timer| (timer, Instant::now()));
+    }
+
+    /// Makes [`Connection::query`] give up with
+    /// [`RuntimeError::RowLimitExceeded`] once a query returns more than
+    /// `max_rows` rows, cancelling it on the server, where 0 (the default)
+    /// collects any number. Unlike the `max_rows` of an `Execute`, it's a
+    /// guard against running out of memory, not a way to page through rows.
+    pub fn set_max_rows(&mut self, max_rows: usize) {
+        self.max_rows = max_rows;
+    }
+
+    /// Caps how many `Sync`s (or simple queries) may await their reply at
+    /// once, where 0 (the default) leaves the pipeline unbounded.
+    ///
+    /// Once `depth` are outstanding, sending another first reads the replies
+    /// to the oldest and keeps them until they're asked for. Otherwise, with
+    /// the server blocked writing replies nobody reads, and the driver
+    /// blocked writing requests the server no longer reads, both sides could
+    /// wait forever.
+    pub fn set_max_pipeline_depth(&mut self, depth: usize) {
+        self.max_pipeline_depth = depth;
+    }
+
+    /// Cancels any [`Connection::query`] or [`Connection::execute`] still
+    /// running after `timeout`, failing it with [`RuntimeError::Timeout`].
+    /// `None`, the default, waits forever.
+    ///
+    /// Unlike `statement_timeout`, this is enforced by the client, so it
+    /// holds even when the server doesn't check for interrupts in time.
+    pub fn set_query_timeout(&mut self, timeout: Option<Duration>) {
+        self.query_timeout = timeout;
+    }
+
+    /// Sets how many rows [`Connection::query_stream`] fetches per `Execute`,
+    /// where 0 (the default) fetches all of them at once.
+    pub fn set_fetch_size(&mut self, fetch_size: u32) {
+        self.fetch_size = fetch_size;
+    }
+
+    /// Runs a query through the extended protocol, yielding its rows lazily.
+    ///
+    /// No `Sync` is sent until the portal completes, since it would close the
+    /// unnamed portal between chunks; `Flush` pushes each chunk out instead.
+    pub fn query_stream(&mut self, query: &str) -> Result<RowStream<'_>, RuntimeError> {
+        self.send(FrontendMessage::Parse {
+            name: UNNAMED.to_string(),
+            query: query.to_string(),
+            param_types: vec![],
+        })?;
+        self.bind_stream(UNNAMED)
+    }
+
+    /// Binds an already parsed `statement` to the unnamed portal and streams
+    /// its rows, as [`Connection::query_stream`] does.
+    pub(crate) fn bind_stream(&mut self, statement: &str) -> Result<RowStream<'_>, RuntimeError> {
+        self.send(FrontendMessage::Bind {
+            portal: UNNAMED.to_string(),
+            statement: statement.to_string(),
+            param_formats: vec![],
+            params: vec![],
+            result_formats: vec![],
+        })?;
+        self.send(FrontendMessage::Execute {
+            portal: UNNAMED.to_string(),
+            max_rows: self.fetch_size,
+        })?;
+        self.send(FrontendMessage::Flush)?;
+
+        let fetch_size = self.fetch_size;
+        Ok(RowStream::new(self, fetch_size))
+    }
+
+    /// Asks the server for a prepared statement's parameter types and result
+    /// columns.
+    ///
+    /// This uses `Flush` rather than `Sync`, so the answers arrive without
+    /// ending the current extended-protocol exchange, and the statement can
+    /// still be bound and executed in it. Only on error is the connection
+    /// synced to recover. No `Parse` is sent: `name` must already be
+    /// prepared, or parsed earlier in the same exchange.
+    ///
+    /// A statement that returns no rows, such as an `INSERT` without
+    /// `RETURNING`, is answered with `NoData` and described with no fields.
+    pub fn describe_statement(&mut self, name: &str) -> Result<StatementDescription, RuntimeError> {
+        self.send(FrontendMessage::Describe {
+            target: Target::Statement,
+            name: name.to_string(),
+        })?;
+        self.send(FrontendMessage::Flush)?;
+
+        let mut parameters = vec![];
+
+        loop {
+            match self.receive()? {
+                BackendMessage::ParameterDescription { types } => parameters = types,
+                BackendMessage::RowDescription { fields } => {
+                    return Ok(StatementDescription {
+                        param_types: parameters,
+                        fields,
+                        returns_rows: true,
+                    })
+                }
+                BackendMessage::NoData => {
+                    return Ok(StatementDescription {
+                        param_types: parameters,
+                        fields: vec![],
+                        returns_rows: false,
+                    })
+                }
+                BackendMessage::ErrorResponse(fields) => {
+                    self.sync()?;
+                    self.read_until_ready()?;
+                    return Err(RuntimeError::PgError(PgError { fields }));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Sends every query through the extended protocol behind a single `Sync`.
+    ///
+    /// After an `ErrorResponse` the server discards everything up to the
+    /// `Sync`, so the failed query is reported as `Failed`, the ones after it
+    /// as `Skipped`, and the stream is drained to `ReadyForQuery` before
+    /// returning. The queries share an implicit transaction: a failure also
+    /// rolls back the ones reported as `Completed`.
+    pub fn pipeline(&mut self, queries: &[&str]) -> Result<Vec<PipelineResult>, RuntimeError> {
+        for query in queries {
+            self.send(FrontendMessage::Parse {
+                name: UNNAMED.to_string(),
+                query: query.to_string(),
+                param_types: vec![],
+            })?;
+            self.send(FrontendMessage::Bind {
+                portal: UNNAMED.to_string(),
+                statement: UNNAMED.to_string(),
+                param_formats: vec![],
+                params: vec![],
+                result_formats: vec![],
+            })?;
+            self.send(FrontendMessage::Execute {
+                portal: UNNAMED.to_string(),
+                max_rows: 0,
+            })?;
+        }
+        self.sync()?;
+
+        let mut results = Vec::with_capacity(queries.len());
+
+        loop {
+            match self.receive()? {
+                BackendMessage::CommandComplete { tag } => {
+                    results.push(PipelineResult::Completed(rows_affected(&tag)))
+                }
+                BackendMessage::ErrorResponse(fields) => {
+                    results.push(PipelineResult::Failed(PgError { fields }))
+                }
+                BackendMessage::ReadyForQuery { .. } => break,
+                _ => {}
+            }
+        }
+
+        while results.len() < queries.len() {
+            results.push(PipelineResult::Skipped);
+        }
+
+        Ok(results)
+    }
+}
+
+/// The authentication exchange the server chose during the handshake.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthMethod {
+    /// The server let the connection in without asking for credentials.
+    Trust,
+    CleartextPassword,
+    /// SASL, with every mechanism the server offered and the one picked.
+    Sasl {
+        offered: Vec<String>,
+        selected: String,
+    },
+}
+
+/// The outcome of a single query sent through [`Connection::pipeline`].
+#[derive(Debug)]
+pub enum PipelineResult {
+    Completed(u64),
+    Failed(PgError),
+    Skipped,
+}
+
+impl Drop for Connection {
+    /// Tells the server the session is over rather than just hanging up.
+    fn drop(&mut self) {
+        let _ = self.send(FrontendMessage::Terminate);
+        let _ = self.flush();
+        self.wire_log.finish();
+        self.observer.on_close();
+    }
+}
+
+/// The startup packet parameters besides `user` and `database`.
+fn startup_parameters(config: &Config) -> Vec<(String, String)> {
+    let mut parameters = vec![
+        (
+            "application_name".to_string(),
+            config.effective_application_name().to_string(),
+        ),
+        (
+            "client_encoding".to_string(),
+            config.client_encoding.clone(),
+        ),
+    ];
+    if let Some(replication) = &config.replication {
+        parameters.push(("replication".to_string(), replication.clone()));
+    }
+    for (name, value) in &config.protocol_extensions {
+        parameters.push((format!("_pq_.{}", name), value.clone()));
+    }
+    parameters
+}
+
+/// Whether a `client_encoding` names UTF-8, which the server reports as
+/// `UTF8` but also accepts under a few aliases.
+fn is_utf8_encoding(encoding: &str) -> bool {
+    ["UTF8", "UTF-8", "UNICODE"]
+        .iter()
+        .any(|name| encoding.eq_ignore_ascii_case(name))
+}
+
+/// A random number, drawn from the randomly seeded keys std uses for
+/// `HashMap`s, which differ for every `RandomState`.
+fn random_u32() -> u32 {
+    RandomState::new().build_hasher().finish() as u32
+}
+
+/// Opens the TCP connection, trying each address `addr` resolves to in turn
+/// until `deadline`.
+fn connect_tcp(addr: &str, deadline: Option<Instant>) -> Result<TcpStream, RuntimeError> {
+    let Some(deadline) = deadline else {
+        return Ok(TcpStream::connect(addr)?);
+    };
+    let mut last_error = None;
+    for socket_addr in addr.to_socket_addrs()? {
+        let timeout = remaining(deadline)?;
+        match TcpStream::connect_timeout(&socket_addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(error) => last_error = Some(error),
+        }
+    }
+    Err(last_error
+        .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address to connect to"))
+        .into())
+}
+
+/// Limits each blocking read and write on `socket` to the time left until
+/// `deadline`, or lifts the limit without one. TLS runs over the same socket,
+/// so this bounds its handshake too.
+fn apply_deadline(socket: &TcpStream, deadline: Option<Instant>) -> Result<(), RuntimeError> {
+    let timeout = deadline.map(remaining).transpose()?;
+    socket.set_read_timeout(timeout)?;
+    socket.set_write_timeout(timeout)?;
+    Ok(())
+}
+
+/// The time left until `deadline`, or a timeout error once it has passed.
+fn remaining(deadline: Instant) -> Result<Duration, RuntimeError> {
+    deadline
+        .checked_duration_since(Instant::now())
+        .filter(|left| !left.is_zero())
+        .ok_or(RuntimeError::Timeout)
+}
+
+/// Sends `RequestGssEncryption` ahead of any TLS negotiation, as libpq does
+/// when `gssencmode` isn't `disable`. Only a refusal lets the connection go
+/// on, since GSSAPI encryption itself isn't implemented.
+fn request_gss_encryption(client: &mut TcpStream) -> Result<(), RuntimeError> {
+    client.write_all(&FrontendMessage::RequestGssEncryption.to_bytes()?)?;
+
+    let mut buf = [0u8; 1];
+    client.read_exact(&mut buf)?;
+
+    match buf[0] {
+        b'N' => Ok(()),
+        b'G' => Err(RuntimeError::UnsupportedAuth("GSSAPI")),
+        _ => Err(RuntimeError::ParseMessageError),
+    }
+}
+
+/// Upgrades the socket to TLS as `config.ssl_mode` asks.
+#[cfg(feature = "tls")]
+fn negotiate_tls(mut client: TcpStream, config: &Config) -> Result<Transport, RuntimeError> {
+    if config.ssl_mode == SslMode::Disable {
+        return Ok(Transport::Plain(client));
+    }
+
+    client.write_all(&FrontendMessage::RequestSSL.to_bytes()?)?;
+
+    let mut buf = [0u8; 1];
+    client.read_exact(&mut buf)?;
+
+    match (buf[0], config.ssl_mode) {
+        (b'S', _) => {
+            let client = crate::tls::connect(config.tls_host(), client)?;
+            Ok(Transport::Tls(client))
+        }
+        (b'N', SslMode::Prefer) => Ok(Transport::Plain(client)),
+        (b'N', _) => Err(RuntimeError::TlsUnavailable),
+        _ => Err(RuntimeError::ParseMessageError),
+    }
+}
+
+/// Without TLS support only plaintext connections are possible, so requiring
+/// encryption is an error.
+#[cfg(not(feature = "tls"))]
+fn negotiate_tls(client: TcpStream, config: &Config) -> Result<Transport, RuntimeError> {
+    match config.ssl_mode {
+        SslMode::Require => Err(RuntimeError::TlsUnavailable),
+        _ => Ok(Transport::Plain(client)),
+    }
+}
+
+/// An `ErrorResponse` sent by the server.
+#[derive(Debug)]
+pub struct PgError {
+    pub fields: HashMap<ErrorField, String>,
+}
+
+impl PgError {
+    /// The SQLSTATE code of the error.
+    pub fn code(&self) -> Option<&str> {
+        self.fields.get(&ErrorField::Code).map(|s| s.as_str())
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.fields.get(&ErrorField::Message).map(|s| s.as_str())
+    }
+
+    /// Where in the query the error is, as a 1-based character offset.
+    pub fn error_position(&self) -> Option<usize> {
+        self.fields.get(&ErrorField::Position)?.parse().ok()
+    }
+
+    /// The line of `sql` holding the error with a caret under it, as psql
+    /// shows it:
+    ///
+    /// ```text
+    /// LINE 2: FRM users
+    ///         ^
+    /// ```
+    pub fn annotated_query(&self, sql: &str) -> Option<String> {
+        let offset = self.error_position()?.checked_sub(1)?;
+        let mut line_start = 0;
+        for (number, line) in sql.split('\n').enumerate() {
+            let length = line.chars().count();
+            if offset <= line_start + length {
+                let prefix = format!("LINE {}: ", number + 1);
+                let column = prefix.len() + offset - line_start;
+                return Some(format!("{}{}\n{}^", prefix, line, " ".repeat(column)));
+            }
+            line_start += length + 1;
+        }
+        None
+    }
+
+    /// The class of the error, from the first two characters of its
+    /// SQLSTATE.
+    pub fn category(&self) -> ErrorCategory {
+        self.code()
+            .and_then(|code| code.get(..2))
+            .map_or(ErrorCategory::Unknown, ErrorCategory::from_class)
+    }
+}
+
+/// The SQLSTATE classes listed in PostgreSQL's "Error Codes" appendix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+    SuccessfulCompletion,
+    Warning,
+    NoData,
+    SqlStatementNotYetComplete,
+    ConnectionException,
+    TriggeredActionException,
+    FeatureNotSupported,
+    InvalidTransactionInitiation,
+    LocatorException,
+    InvalidGrantor,
+    InvalidRoleSpecification,
+    DiagnosticsException,
+    CaseNotFound,
+    CardinalityViolation,
+    DataException,
+    IntegrityConstraintViolation,
+    InvalidCursorState,
+    InvalidTransactionState,
+    InvalidSqlStatementName,
+    TriggeredDataChangeViolation,
+    InvalidAuthorizationSpecification,
+    DependentPrivilegeDescriptorsStillExist,
+    InvalidTransactionTermination,
+    SqlRoutineException,
+    InvalidCursorName,
+    ExternalRoutineException,
+    ExternalRoutineInvocationException,
+    SavepointException,
+    InvalidCatalogName,
+    InvalidSchemaName,
+    TransactionRollback,
+    SyntaxErrorOrAccessRuleViolation,
+    WithCheckOptionViolation,
+    InsufficientResources,
+    ProgramLimitExceeded,
+    ObjectNotInPrerequisiteState,
+    OperatorIntervention,
+    SystemError,
+    SnapshotTooOld,
+    ConfigFileError,
+    FdwError,
+    PlpgsqlError,
+    InternalError,
+    /// A class not in the list, or an error without a SQLSTATE.
+    Unknown,
+}
+
+impl ErrorCategory {
+    /// The category of a two-character SQLSTATE class such as `23`.
+    pub fn from_class(class: &str) -> Self {
+        match class {
+            "00" => Self::SuccessfulCompletion,
+            "01" => Self::Warning,
+            "02" => Self::NoData,
+            "03" => Self::SqlStatementNotYetComplete,
+            "08" => Self::ConnectionException,
+            "09" => Self::TriggeredActionException,
+            "0A" => Self::FeatureNotSupported,
+            "0B" => Self::InvalidTransactionInitiation,
+            "0F" => Self::LocatorException,
+            "0L" => Self::InvalidGrantor,
+            "0P" => Self::InvalidRoleSpecification,
+            "0Z" => Self::DiagnosticsException,
+            "20" => Self::CaseNotFound,
+            "21" => Self::CardinalityViolation,
+            "22" => Self::DataException,
+            "23" => Self::IntegrityConstraintViolation,
+            "24" => Self::InvalidCursorState,
+            "25" => Self::InvalidTransactionState,
+            "26" => Self::InvalidSqlStatementName,
+            "27" => Self::TriggeredDataChangeViolation,
+            "28" => Self::InvalidAuthorizationSpecification,
+            "2B" => Self::DependentPrivilegeDescriptorsStillExist,
+            "2D" => Self::InvalidTransactionTermination,
+            "2F" => Self::SqlRoutineException,
+            "34" => Self::InvalidCursorName,
+            "38" => Self::ExternalRoutineException,
+            "39" => Self::ExternalRoutineInvocationException,
+            "3B" => Self::SavepointException,
+            "3D" => Self::InvalidCatalogName,
+            "3F" => Self::InvalidSchemaName,
+            "40" => Self::TransactionRollback,
+            "42" => Self::SyntaxErrorOrAccessRuleViolation,
+            "44" => Self::WithCheckOptionViolation,
+            "53" => Self::InsufficientResources,
+            "54" => Self::ProgramLimitExceeded,
+            "55" => Self::ObjectNotInPrerequisiteState,
+            "57" => Self::OperatorIntervention,
+            "58" => Self::SystemError,
+            "72" => Self::SnapshotTooOld,
+            "F0" => Self::ConfigFileError,
+            "HV" => Self::FdwError,
+            "P0" => Self::PlpgsqlError,
+            "XX" => Self::InternalError,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RuntimeError {
+    IoError(std::io::Error),
+    ParseMessageError,
+    /// A failure of the TLS backend, during the handshake or afterwards.
+    #[cfg(feature = "tls")]
+    TlsError(Box<dyn std::error::Error + Send + Sync>),
+    /// TLS was required but the server refused it, or the crate was built
+    /// without the `tls` feature.
+    TlsUnavailable,
+    /// No supported authentication mechanism was offered, or the server
+    /// failed to prove it knows the password.
+    AuthenticationFailed,
+    /// `channel_binding` is `Require` but the server didn't offer
+    /// `SCRAM-SHA-256-PLUS` over TLS.
+    ChannelBindingRequired,
+    /// A message the driver doesn't know, with the reader in strict mode.
+    UnexpectedMessage {
+        prefix: char,
+    },
+    /// The stream lost message framing; the connection is marked broken.
+    /// See [`ReadMessageError::Desynchronized`].
+    Desynchronized {
+        header: [u8; 5],
+    },
+    /// The query outlived the timeout set with
+    /// [`Connection::set_query_timeout`] and was cancelled, or connecting
+    /// took longer than `config.connect_timeout`.
+    Timeout,
+    /// The server asked for an authentication method this driver doesn't
+    /// implement, named here.
+    UnsupportedAuth(&'static str),
+    /// A query the driver refused to send, with the reason.
+    InvalidQuery(&'static str),
+    /// A query returned more rows than [`Connection::set_max_rows`] allows,
+    /// and was cancelled.
+    RowLimitExceeded,
+    /// `COMMIT` was answered with `ROLLBACK`: an error earlier in the
+    /// transaction aborted it without reaching the caller.
+    RolledBack,
+    /// A message longer than the server accepts, which was not sent.
+    MessageTooLarge {
+        length: usize,
+        max: usize,
+    },
+    PgError(PgError),
+}
+
+impl From<MessageTooLarge> for RuntimeError {
+    fn from(error: MessageTooLarge) -> Self {
+        Self::MessageTooLarge {
+            length: error.length,
+            max: u32::MAX as usize,
+        }
+    }
+}
+
+impl From<std::io::Error> for RuntimeError {
+    fn from(error: std::io::Error) -> Self {
+        Self::IoError(error)
+    }
+}
+
+impl From<ReadMessageError> for RuntimeError {
+    fn from(error: ReadMessageError) -> Self {
+        match error {
+            ReadMessageError::IoError(error) => Self::IoError(error),
+            ReadMessageError::ParseError => Self::ParseMessageError,
+            ReadMessageError::UnexpectedMessage { prefix } => Self::UnexpectedMessage { prefix },
+            ReadMessageError::Desynchronized { header } => Self::Desynchronized { header },
+        }
+    }
+}
+
+impl From<ScramError> for RuntimeError {
+    fn from(_error: ScramError) -> Self {
+        Self::AuthenticationFailed
+    }
+}
+
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+impl From<native_tls::HandshakeError<TcpStream>> for RuntimeError {
+    fn from(error: native_tls::HandshakeError<TcpStream>) -> Self {
+        RuntimeError::TlsError(Box::new(error))
+    }
+}
+
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+impl From<native_tls::Error> for RuntimeError {
+    fn from(error: native_tls::Error) -> Self {
+        RuntimeError::TlsError(Box::new(error))
+    }
+}
+
+#[cfg(feature = "rustls")]
+impl From<rustls::Error> for RuntimeError {
+    fn from(error: rustls::Error) -> Self {
+        RuntimeError::TlsError(Box::new(error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::*;
+    use crate::transport::syscalls;
+    use std::thread;
+
+    #[test]
+    fn pipeline_is_written_in_one_syscall() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            for _ in 0..3 {
+                for prefix in [b'P', b'B', b'E'] {
+                    session.expect(prefix);
+                }
+            }
+            session.expect(b'S');
+            let mut reply = vec![];
+            for _ in 0..3 {
+                reply.extend(frame(b'1', b""));
+                reply.extend(frame(b'2', b""));
+                reply.extend(command_complete("INSERT 0 1"));
+            }
+            reply.extend(ready(b'I'));
+            session.send(&reply);
+        });
+        let mut connection = server.connect();
+
+        let before = syscalls::WRITES.with(|count| count.get());
+        let results = connection
+            .pipeline(&["INSERT INTO t VALUES (1)"; 3])
+            .unwrap();
+        assert_eq!(syscalls::WRITES.with(|count| count.get()) - before, 1);
+        assert!(results
+            .iter()
+            .all(|result| matches!(result, PipelineResult::Completed(1))));
+        server.finish();
+    }
+
+    /// Answers a `Describe` of the statement `name`, which must be followed
+    /// by a `Flush`, with `description`.
+    fn serve_describe(session: &mut MockSession, name: &str, description: &[u8]) {
+        assert_eq!(
+            session.expect(b'D'),
+            [b"S", name.as_bytes(), b"\0"].concat()
+        );
+        session.expect(b'H');
+        session.send(description);
+    }
+
+    #[test]
+    fn describes_a_statement_without_syncing() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            let mut description = frame(b't', &[0, 1, 0, 0, 0, 23]);
+            description.extend(row_description(&[("name", 25)]));
+            serve_describe(session, "lookup", &description);
+            serve_describe(session, "purge", &frame(b'n', b""));
+            // The next thing sent is the Terminate from dropping the
+            // connection, not a Sync.
+            assert_eq!(session.receive().0, b'X');
+        });
+        let mut connection = server.connect();
+
+        let lookup = connection.describe_statement("lookup").unwrap();
+        assert_eq!(lookup.param_types, [23]);
+        assert_eq!(lookup.fields.len(), 1);
+        assert_eq!(lookup.fields[0].name, "name");
+        assert_eq!(lookup.fields[0].type_oid, 25);
+        assert!(lookup.returns_rows);
+
+        let purge = connection.describe_statement("purge").unwrap();
+        assert!(purge.param_types.is_empty());
+        assert!(purge.fields.is_empty());
+        assert!(!purge.returns_rows);
+        drop(connection);
+        server.finish();
+    }
+
+    #[test]
+    fn records_how_the_server_asked_to_authenticate() {
+        let trust = MockServer::start(|session| {
+            session.accept();
+        });
+        assert_eq!(trust.connect().auth_method(), &AuthMethod::Trust);
+        trust.finish();
+
+        let cleartext = MockServer::start(|session| {
+            session.read_startup();
+            session.send(&frame(b'R', &3u32.to_be_bytes()));
+            assert_eq!(session.expect(b'p'), b"pencil\0");
+            session.send(&frame(b'R', &0u32.to_be_bytes()));
+            session.send(&ready(b'I'));
+        });
+        assert_eq!(
+            cleartext.connect().auth_method(),
+            &AuthMethod::CleartextPassword
+        );
+        cleartext.finish();
+    }
+
+    #[test]
+    fn small_messages_are_read_in_few_syscalls() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            session.expect_query();
+            let mut reply = row_description(&[("n", 23)]);
+            for n in 0..100 {
+                reply.extend(data_row(&[Some(&n.to_string())]));
+            }
+            reply.extend(command_complete("SELECT 100"));
+            reply.extend(ready(b'I'));
+            session.send(&reply);
+        });
+        let mut connection = server.connect();
+
+        let before = syscalls::READS.with(|count| count.get());
+        let result = connection.query("SELECT n FROM t").unwrap();
+        // Unbuffered, each of the 103 messages would take two reads. Allow
+        // for the reply arriving in more than one piece.
+        assert!(syscalls::READS.with(|count| count.get()) - before <= 3);
+        assert_eq!(result.rows.len(), 100);
+        server.finish();
+    }
+
+    #[test]
+    fn buffers_start_at_the_configured_size() {
+        let server = MockServer::start(|session| {
+            session.accept();
+        });
+        let mut config = server.config();
+        config.initial_buffer_size = 100_000;
+        config.receive_buffer_size = Some(100_000);
+        let connection = Connection::connect(&config).unwrap();
+
+        assert!(connection.reader.capacity() >= 100_000);
+        let socket = connection.stream.get_ref().get_ref().tcp_stream();
+        let receive_buffer = socket2::SockRef::from(socket).recv_buffer_size().unwrap();
+        assert!(receive_buffer >= 100_000);
+        server.finish();
+    }
+
+    #[test]
+    fn binds_a_blob_verbatim_in_binary() {
+        let blob: Vec<u8> = (0..=255).collect();
+        let expected = blob.clone();
+        let server = MockServer::start(move |session| {
+            session.accept();
+            let parse = session.expect(b'P');
+            assert!(parse.ends_with(&[0, 1, 0, 0, 0, 17]));
+            let bind = session.expect(b'B');
+            // Unnamed portal and statement, one binary format, one 256-byte
+            // parameter, no result formats.
+            let mut body = vec![0, 0, 0, 1, 0, 1, 0, 1, 0, 0, 1, 0];
+            body.extend(&expected);
+            body.extend([0, 0]);
+            assert_eq!(bind, body);
+            session.expect(b'E');
+            session.expect(b'S');
+            session.send(&[frame(b'1', b""), frame(b'2', b"")].concat());
+            session.complete("INSERT 0 1", b'I');
+        });
+        let mut connection = server.connect();
+        let count = connection
+            .execute_params("INSERT INTO t VALUES ($1)", &[&blob])
+            .unwrap();
+        assert_eq!(count, 1);
+        server.finish();
+    }
+
+    #[test]
+    fn connecting_to_a_stalled_server_times_out() {
+        let server = MockServer::start(|session| {
+            session.read_startup();
+            thread::sleep(Duration::from_millis(500));
+        });
+        let mut config = server.config();
+        config.connect_timeout = Some(Duration::from_millis(50));
+
+        let started = Instant::now();
+        let result = Connection::connect(&config);
+        assert!(matches!(result, Err(RuntimeError::Timeout)));
+        assert!(started.elapsed() < Duration::from_millis(500));
+        server.finish();
+    }
+
+    #[test]
+    fn rows_past_the_limit_cancel_the_query() {
+        let server = MockServer::start_cancellable(|session, expect_cancel| {
+            session.accept();
+            assert_eq!(session.expect_query(), "SELECT n FROM t");
+            let mut reply = row_description(&[("n", 23)]);
+            for n in ["1", "2", "3", "4", "5"] {
+                reply.extend(data_row(&[Some(n)]));
+            }
+            session.send(&reply);
+            expect_cancel();
+            let mut reply = error_response("57014", "canceling statement due to user request");
+            reply.extend(ready(b'I'));
+            session.send(&reply);
+        });
+        let mut connection = server.connect();
+        connection.set_max_rows(3);
+
+        let result = connection.query("SELECT n FROM t");
+        assert!(matches!(result, Err(RuntimeError::RowLimitExceeded)));
+        assert_eq!(connection.transaction_status(), ReadyForQueryStatus::Idle);
+        server.finish();
+    }
+
+    #[test]
+    fn queries_after_a_failed_one_are_skipped() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            for _ in 0..3 {
+                for prefix in [b'P', b'B', b'E'] {
+                    session.expect(prefix);
+                }
+            }
+            session.expect(b'S');
+            let mut reply = error_response("42P01", "relation \"missing\" does not exist");
+            reply.extend(ready(b'I'));
+            session.send(&reply);
+        });
+        let mut connection = server.connect();
+
+        let results = connection
+            .pipeline(&["SELECT * FROM missing", "SELECT 1", "SELECT 2"])
+            .unwrap();
+        assert!(
+            matches!(&results[0], PipelineResult::Failed(error) if error.code() == Some("42P01"))
+        );
+        assert!(matches!(results[1], PipelineResult::Skipped));
+        assert!(matches!(results[2], PipelineResult::Skipped));
+        assert_eq!(results.len(), 3);
+        server.finish();
+    }
+
+    #[test]
+    fn a_failed_batch_execution_leaves_the_others_running() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            for _ in 0..3 {
+                for prefix in [b'B', b'E', b'S'] {
+                    session.expect(prefix);
+                }
+            }
+            let mut reply = vec![];
+            for outcome in [Ok(()), Err(()), Ok(())] {
+                match outcome {
+                    Ok(()) => {
+                        reply.extend(frame(b'2', b""));
+                        reply.extend(command_complete("INSERT 0 1"));
+                    }
+                    Err(()) => reply.extend(error_response("23505", "duplicate key value")),
+                }
+                reply.extend(ready(b'I'));
+            }
+            session.send(&reply);
+        });
+        let mut connection = server.connect();
+
+        let param_sets: Vec<Vec<&dyn ToSql>> = vec![vec![&"1"], vec![&"1"], vec![&"2"]];
+        let result = connection.execute_batch("insert_n", &param_sets);
+        let Err(RuntimeError::PgError(error)) = result else {
+            panic!("expected the duplicate to fail, got {:?}", result);
+        };
+        assert_eq!(error.code(), Some("23505"));
+        assert_eq!(connection.transaction_status(), ReadyForQueryStatus::Idle);
+        server.finish();
+    }
+
+    #[test]
+    fn a_deep_pipeline_reads_replies_as_it_goes() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            let reply = [
+                frame(b'2', b""),
+                command_complete("INSERT 0 1"),
+                ready(b'I'),
+            ]
+            .concat();
+            for sent in 0..5 {
+                session.expect(b'B');
+                session.expect(b'E');
+                // With two syncs unanswered, the next waits for a reply.
+                if sent >= 2 {
+                    assert!(!session.has_pending(Duration::from_millis(50)));
+                    session.send(&reply);
+                }
+                session.expect(b'S');
+            }
+            session.send(&[reply.clone(), reply].concat());
+        });
+        let mut connection = server.connect();
+        connection.set_max_pipeline_depth(2);
+
+        let param_sets: Vec<Vec<&dyn ToSql>> = (0..5).map(|_| vec![&"1" as &dyn ToSql]).collect();
+        let counts = connection.execute_batch("insert_n", &param_sets).unwrap();
+        assert_eq!(counts, [1; 5]);
+        server.finish();
+    }
+
+    #[test]
+    fn draining_skips_rows_to_ready() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            assert_eq!(session.expect_query(), "SELECT n FROM t");
+            let mut reply = row_description(&[("n", 23)]);
+            for _ in 0..50 {
+                reply.extend(data_row(&[Some("1")]));
+            }
+            reply.extend(command_complete("SELECT 50"));
+            reply.extend(ready(b'I'));
+            session.send(&reply);
+            assert_eq!(session.expect_query(), "SELECT 1");
+            session.complete("SELECT 1", b'I');
+        });
+        let mut connection = server.connect();
+        connection
+            .send(FrontendMessage::SimpleQuery {
+                query: "SELECT n FROM t".to_string(),
+            })
+            .unwrap();
+
+        assert_eq!(
+            connection.drain_to_ready().unwrap(),
+            ReadyForQueryStatus::Idle
+        );
+        // Nothing is left over to confuse the next query.
+        assert_eq!(connection.execute("SELECT 1").unwrap(), 1);
+        server.finish();
+    }
+
+    #[test]
+    fn a_parameter_status_between_rows_is_recorded() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            assert_eq!(session.expect_query(), "SELECT n FROM t");
+            let mut reply = row_description(&[("n", 23)]);
+            reply.extend(data_row(&[Some("1")]));
+            reply.extend(frame(b'S', b"search_path\0app\0"));
+            reply.extend(data_row(&[Some("2")]));
+            reply.extend(command_complete("SELECT 2"));
+            reply.extend(ready(b'I'));
+            session.send(&reply);
+        });
+        let mut connection = server.connect();
+
+        let result = connection.query("SELECT n FROM t").unwrap();
+        let values: Vec<_> = result.rows.iter().map(|row| row.get_str(0)).collect();
+        assert_eq!(values, [Some("1"), Some("2")]);
+        assert_eq!(connection.parameter("search_path"), Some("app"));
+        server.finish();
+    }
+
+    #[test]
+    fn execute_counts_rows_without_collecting_them() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            assert_eq!(
+                session.expect_query(),
+                "INSERT INTO t VALUES (1), (2) RETURNING n"
+            );
+            let mut reply = row_description(&[("n", 23)]);
+            reply.extend(data_row(&[Some("1")]));
+            reply.extend(data_row(&[Some("2")]));
+            reply.extend(command_complete("INSERT 0 2"));
+            reply.extend(ready(b'I'));
+            session.send(&reply);
+        });
+        let mut connection = server.connect();
+
+        let count = connection
+            .execute("INSERT INTO t VALUES (1), (2) RETURNING n")
+            .unwrap();
+        assert_eq!(count, 2);
+        server.finish();
+    }
+
+    #[test]
+    fn a_result_without_row_description_has_no_columns() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            assert_eq!(session.expect_query(), "DELETE FROM t");
+            session.complete("DELETE 3", b'I');
+        });
+        let mut connection = server.connect();
+
+        let result = connection.query("DELETE FROM t").unwrap();
+        assert!(result.fields.is_empty());
+        assert!(result.rows.is_empty());
+        assert_eq!(result.tag.as_deref(), Some("DELETE 3"));
+        server.finish();
+    }
+
+    #[test]
+    fn a_query_matching_no_rows_keeps_its_columns() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            assert_eq!(session.expect_query(), "SELECT n FROM t WHERE false");
+            let mut reply = row_description(&[("n", 23)]);
+            reply.extend(command_complete("SELECT 0"));
+            reply.extend(ready(b'I'));
+            session.send(&reply);
+        });
+        let mut connection = server.connect();
+
+        let result = connection.query("SELECT n FROM t WHERE false").unwrap();
+        assert_eq!(result.fields.len(), 1);
+        assert_eq!(result.fields[0].name, "n");
+        assert!(result.rows.is_empty());
+        server.finish();
+    }
+
+    #[test]
+    fn the_last_command_tag_is_kept_after_execute() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            assert_eq!(session.expect_query(), "MERGE INTO t USING s ON true");
+            session.complete("MERGE 5", b'I');
+        });
+        let mut connection = server.connect();
+        assert_eq!(connection.last_command_tag(), None);
+
+        connection.execute("MERGE INTO t USING s ON true").unwrap();
+        assert_eq!(connection.last_command_tag(), Some("MERGE 5"));
+        server.finish();
+    }
+
+    #[test]
+    fn execute_simple_returns_the_command_tag() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            assert_eq!(session.expect_query(), "CREATE TABLE t (n int)");
+            let mut reply = frame(b'N', b"SNOTICE\0C00000\0Mnothing to see\0\0");
+            reply.extend(command_complete("CREATE TABLE"));
+            reply.extend(ready(b'I'));
+            session.send(&reply);
+        });
+        let mut connection = server.connect();
+
+        let tag = connection.execute_simple("CREATE TABLE t (n int)").unwrap();
+        assert_eq!(tag, "CREATE TABLE");
+        server.finish();
+    }
+
+    #[test]
+    fn keys_are_bound_as_one_array() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            let parse = session.expect(b'P');
+            assert!(parse.ends_with(&[0, 1, 0, 0, 0x03, 0xef]));
+            session.expect(b'S');
+            session.send(&[frame(b'1', b""), ready(b'I')].concat());
+
+            let name = &parse[..parse.iter().position(|&byte| byte == 0).unwrap() + 1];
+            let mut bind = vec![0];
+            bind.extend(name);
+            bind.extend([0, 1, 0, 0, 0, 1, 0, 0, 0, 7]);
+            bind.extend(b"{1,2,3}");
+            bind.extend([0, 0]);
+            assert_eq!(session.expect(b'B'), bind);
+            for prefix in [b'D', b'E', b'S'] {
+                session.expect(prefix);
+            }
+            let mut reply = frame(b'2', b"");
+            reply.extend(row_description(&[("id", 23)]));
+            for id in ["1", "2", "3"] {
+                reply.extend(data_row(&[Some(id)]));
+            }
+            reply.extend(command_complete("SELECT 3"));
+            reply.extend(ready(b'I'));
+            session.send(&reply);
+        });
+        let mut connection = server.connect();
+
+        let result = connection
+            .query_by_keys("SELECT id FROM users WHERE id = ANY($1)", &[1, 2, 3])
+            .unwrap();
+        assert_eq!(result.rows.len(), 3);
+        server.finish();
+    }
+
+    #[test]
+    fn a_cursor_is_fetched_in_batches() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            assert_eq!(session.expect_query(), "BEGIN");
+            session.complete("BEGIN", b'T');
+            assert_eq!(
+                session.expect_query(),
+                "DECLARE \"c\" CURSOR FOR SELECT n FROM t"
+            );
+            session.complete("DECLARE CURSOR", b'T');
+            for batch in [&["1", "2"][..], &["3"]] {
+                assert_eq!(session.expect_query(), "FETCH 2 FROM \"c\"");
+                let mut reply = row_description(&[("n", 23)]);
+                for n in batch {
+                    reply.extend(data_row(&[Some(n)]));
+                }
+                reply.extend(command_complete(&format!("FETCH {}", batch.len())));
+                reply.extend(ready(b'T'));
+                session.send(&reply);
+            }
+            assert_eq!(session.expect_query(), "CLOSE \"c\"");
+            session.complete("CLOSE CURSOR", b'T');
+            assert_eq!(session.expect_query(), "COMMIT");
+            session.complete("COMMIT", b'I');
+        });
+        let mut connection = server.connect();
+
+        connection.declare_cursor("c", "SELECT n FROM t").unwrap();
+        let first = connection.fetch("c", 2).unwrap();
+        let second = connection.fetch("c", 2).unwrap();
+        connection.close_cursor("c").unwrap();
+
+        let values = |result: &ResultSet| -> Vec<String> {
+            result
+                .rows
+                .iter()
+                .map(|row| row.get_str(0).unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(values(&first), ["1", "2"]);
+        assert_eq!(values(&second), ["3"]);
+        assert_eq!(connection.transaction_status(), ReadyForQueryStatus::Idle);
+        server.finish();
+    }
+
+    #[test]
+    fn every_message_is_held_to_the_length_limit() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            session.expect(b'X');
+        });
+        let mut connection = server.connect();
+        connection.set_max_message_length(100);
+
+        // A Bind carries no SQL, but its parameters count all the same.
+        let result = connection.send(FrontendMessage::Bind {
+            portal: UNNAMED.to_string(),
+            statement: UNNAMED.to_string(),
+            param_formats: vec![],
+            params: vec![Some(vec![0; 100])],
+            result_formats: vec![],
+        });
+        assert!(matches!(
+            result,
+            Err(RuntimeError::MessageTooLarge {
+                length: 116,
+                max: 100
+            })
+        ));
+        let result = connection.send(FrontendMessage::CopyData { data: vec![0; 97] });
+        assert!(matches!(
+            result,
+            Err(RuntimeError::MessageTooLarge {
+                length: 101,
+                max: 100
+            })
+        ));
+        // Nothing was queued: the next message the server sees is Terminate.
+        drop(connection);
+        server.finish();
+    }
+
+    #[test]
+    fn the_message_timer_sees_each_message_once() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            session.expect_query();
+            let mut reply = row_description(&[("n", 23)]);
+            reply.extend(data_row(&[Some("1")]));
+            session.send(&reply);
+            thread::sleep(Duration::from_millis(20));
+            let mut reply = data_row(&[Some("2")]);
+            reply.extend(command_complete("SELECT 2"));
+            reply.extend(ready(b'I'));
+            session.send(&reply);
+        });
+        let mut connection = server.connect();
+        let timed = Rc::new(RefCell::new(vec![]));
+        let record = Rc::clone(&timed);
+        let started = Instant::now();
+        connection.set_message_timer(Some(Box::new(move |prefix, elapsed| {
+            record.borrow_mut().push((prefix, elapsed))
+        })));
+
+        connection.query("SELECT n FROM t").unwrap();
+        let timed = timed.borrow();
+        let prefixes: String = timed.iter().map(|(prefix, _)| prefix).collect();
+        assert_eq!(prefixes, "TDDCZ");
+        // Each gap runs from the message before, so they add up to no more
+        // than the time since the timer was set.
+        assert!(timed[2].1 >= Duration::from_millis(20));
+        let total: Duration = timed.iter().map(|(_, elapsed)| *elapsed).sum();
+        assert!(total <= started.elapsed());
+        server.finish();
+    }
+
+    #[test]
+    fn connections_get_distinct_ids_in_opening_order() {
+        let server = MockServer::start_many(2, |session| {
+            session.accept();
+        });
+        let first = server.connect();
+        let second = server.connect();
+        // Other tests open connections concurrently, so ids may skip.
+        assert!(second.id() > first.id());
+        drop((first, second));
+        server.finish();
+    }
+
+    #[test]
+    fn type_cache_resolves_domains_from_pg_type() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            assert_eq!(session.expect_query(), TYPE_CACHE_QUERY);
+            let mut reply = row_description(&[
+                ("oid", 26),
+                ("typname", 19),
+                ("typcategory", 18),
+                ("typbasetype", 26),
+            ]);
+            // As recorded after `CREATE DOMAIN positive AS int4` and
+            // `CREATE DOMAIN small_positive AS positive`.
+            reply.extend(data_row(&[Some("23"), Some("int4"), Some("N"), Some("0")]));
+            reply.extend(data_row(&[Some("25"), Some("text"), Some("S"), Some("0")]));
+            reply.extend(data_row(&[
+                Some("16385"),
+                Some("positive"),
+                Some("N"),
+                Some("23"),
+            ]));
+            reply.extend(data_row(&[
+                Some("16387"),
+                Some("small_positive"),
+                Some("N"),
+                Some("16385"),
+            ]));
+            reply.extend(data_row(&[
+                Some("16390"),
+                Some("mood"),
+                Some("E"),
+                Some("0"),
+            ]));
+            session.send(&reply);
+            session.complete("SELECT 5", b'I');
+        });
+        let mut connection = server.connect();
+        assert!(connection.type_cache().is_empty());
+
+        connection.load_type_cache().unwrap();
+        let cache = connection.type_cache();
+        assert_eq!(cache.len(), 5);
+        assert_eq!(cache.oid_of("positive"), Some(16385));
+        assert_eq!(cache.resolve(16385), 23);
+        assert_eq!(cache.resolve(16387), 23);
+        assert_eq!(cache.resolve(16390), TEXT_OID);
+        assert_eq!(cache.resolve(23), 23);
+        assert_eq!(cache.resolve(99999), 99999);
+        drop(connection);
+        server.finish();
+    }
+
+    /// Connects to a server reporting `parameters` during startup.
+    fn connect_reporting(parameters: &'static [(&str, &str)]) -> (MockServer, Connection) {
+        let server = MockServer::start(move |session| {
+            session.read_startup();
+            let mut reply = frame(b'R', &0u32.to_be_bytes());
+            for (name, value) in parameters {
+                reply.extend(frame(b'S', format!("{}\0{}\0", name, value).as_bytes()));
+            }
+            reply.extend(frame(b'K', &[0, 0, 0, 42, 0, 0, 0, 7]));
+            reply.extend(ready(b'I'));
+            session.send(&reply);
+        });
+        let connection = server.connect();
+        (server, connection)
+    }
+
+    #[test]
+    fn timestamps_follow_the_reported_datetime_format() {
+        let (server, connection) =
+            connect_reporting(&[("integer_datetimes", "on"), ("TimeZone", "Europe/Paris")]);
+        assert!(connection.integer_datetimes());
+        assert_eq!(connection.timezone(), Some("Europe/Paris"));
+        assert_eq!(
+            connection.decode_timestamp(&1_500_000i64.to_be_bytes()),
+            Some(Timestamp::Micros(1_500_000))
+        );
+        drop(connection);
+        server.finish();
+
+        let (server, connection) = connect_reporting(&[("integer_datetimes", "off")]);
+        assert!(!connection.integer_datetimes());
+        assert_eq!(connection.timezone(), None);
+        assert_eq!(
+            connection.decode_timestamp(&1.5f64.to_be_bytes()),
+            Some(Timestamp::Micros(1_500_000))
+        );
+        drop(connection);
+        server.finish();
+    }
+
+    #[test]
+    fn categorizes_errors_by_sqlstate_class() {
+        for (class, category) in [
+            ("08", ErrorCategory::ConnectionException),
+            ("0A", ErrorCategory::FeatureNotSupported),
+            ("23", ErrorCategory::IntegrityConstraintViolation),
+            ("40", ErrorCategory::TransactionRollback),
+            ("42", ErrorCategory::SyntaxErrorOrAccessRuleViolation),
+            ("53", ErrorCategory::InsufficientResources),
+            ("XX", ErrorCategory::InternalError),
+            ("ZZ", ErrorCategory::Unknown),
+        ] {
+            assert_eq!(ErrorCategory::from_class(class), category, "{class}");
+        }
+
+        let error = |code: Option<&str>| PgError {
+            fields: code
+                .map(|code| (ErrorField::Code, code.to_string()))
+                .into_iter()
+                .collect(),
+        };
+        assert_eq!(
+            error(Some("40001")).category(),
+            ErrorCategory::TransactionRollback
+        );
+        assert_eq!(error(Some("4")).category(), ErrorCategory::Unknown);
+        assert_eq!(error(None).category(), ErrorCategory::Unknown);
+    }
+
+    #[test]
+    fn points_at_the_position_of_a_syntax_error() {
+        let sql = "SELECT id\nFRM users";
+        let server = MockServer::start(move |session| {
+            session.accept();
+            assert_eq!(session.expect_query(), sql);
+            // As the server reports it, at the 1-based offset of `FRM`.
+            let mut reply = frame(
+                b'E',
+                b"SERROR\0VERROR\0C42601\0Msyntax error at or near \"FRM\"\0P11\0\0",
+            );
+            reply.extend(ready(b'I'));
+            session.send(&reply);
+        });
+        let mut connection = server.connect();
+
+        let Err(RuntimeError::PgError(error)) = connection.query(sql) else {
+            panic!("expected a syntax error");
+        };
+        assert_eq!(error.error_position(), Some(11));
+        assert_eq!(
+            error.annotated_query(sql).as_deref(),
+            Some("LINE 2: FRM users\n        ^")
+        );
+        drop(connection);
+        server.finish();
+    }
+
+    #[test]
+    fn gssapi_authentication_fails_promptly() {
+        let server = MockServer::start(|session| {
+            session.read_startup();
+            session.send(&frame(b'R', &7u32.to_be_bytes()));
+            // Hold the socket open: the client must give up on its own.
+            session.has_pending(Duration::from_secs(10));
+        });
+        let started = Instant::now();
+        let result = Connection::connect(&server.config());
+        assert!(
+            matches!(result, Err(RuntimeError::UnsupportedAuth("GSSAPI"))),
+            "{:?}",
+            result.err()
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+        server.finish();
+    }
+
+    #[test]
+    fn keeps_notices_sent_during_startup() {
+        let server = MockServer::start(|session| {
+            session.read_startup();
+            let mut reply = frame(b'R', &0u32.to_be_bytes());
+            reply.extend(frame(
+                b'N',
+                b"SWARNING\0VWARNING\0C01000\0Mpassword expires in 3 days\0\0",
+            ));
+            reply.extend(frame(b'K', &[0, 0, 0, 42, 0, 0, 0, 7]));
+            reply.extend(ready(b'I'));
+            session.send(&reply);
+        });
+        let connection = server.connect();
+
+        let notices = connection.startup_notices();
+        assert_eq!(notices.len(), 1);
+        assert_eq!(notices[0].code(), Some("01000"));
+        assert_eq!(notices[0].message(), Some("password expires in 3 days"));
+        drop(connection);
+        server.finish();
+    }
+
+    #[test]
+    fn flags_a_client_encoding_other_than_utf8() {
+        let (server, connection) = connect_reporting(&[("client_encoding", "LATIN1")]);
+        assert_eq!(connection.unsupported_client_encoding(), Some("LATIN1"));
+        drop(connection);
+        server.finish();
+
+        let (server, connection) = connect_reporting(&[("client_encoding", "UTF8")]);
+        assert_eq!(connection.unsupported_client_encoding(), None);
+        drop(connection);
+        server.finish();
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn asks_for_gss_encryption_before_ssl() {
+        let server = MockServer::start(|session| {
+            assert_eq!(session.read_packet(), 80877104u32.to_be_bytes());
+            session.send(b"N");
+            assert_eq!(session.read_packet(), 80877103u32.to_be_bytes());
+            session.send(b"N");
+            session.accept();
+        });
+        let mut config = server.config();
+        config.ssl_mode = SslMode::Prefer;
+        config.negotiation_order = NegotiationOrder::GssThenSsl;
+
+        drop(Connection::connect(&config).unwrap());
+        server.finish();
+    }
+
+    /// An `AuthenticationSASL` request offering `mechanisms`.
+    fn sasl_request(mechanisms: &[&str]) -> Vec<u8> {
+        let mut body = 10u32.to_be_bytes().to_vec();
+        for mechanism in mechanisms {
+            body.extend_from_slice(mechanism.as_bytes());
+            body.push(0);
+        }
+        body.push(0);
+        frame(b'R', &body)
+    }
+
+    #[test]
+    fn requiring_channel_binding_fails_without_plus() {
+        let server = MockServer::start(|session| {
+            session.read_startup();
+            session.send(&sasl_request(&["SCRAM-SHA-256"]));
+            session.has_pending(Duration::from_secs(10));
+        });
+        let mut config = server.config();
+        config.channel_binding = ChannelBinding::Require;
+
+        let result = Connection::connect(&config);
+        assert!(
+            matches!(result, Err(RuntimeError::ChannelBindingRequired)),
+            "{:?}",
+            result.err()
+        );
+        server.finish();
+    }
+
+    #[test]
+    fn disabling_channel_binding_selects_plain_scram() {
+        let server = MockServer::start(|session| {
+            session.read_startup();
+            session.send(&sasl_request(&["SCRAM-SHA-256-PLUS", "SCRAM-SHA-256"]));
+            let initial = session.expect(b'p');
+            assert!(initial.starts_with(b"SCRAM-SHA-256\0"), "{:?}", initial);
+            let client_first = &initial[b"SCRAM-SHA-256\0".len() + 4..];
+            assert!(client_first.starts_with(b"n,,"), "{:?}", client_first);
+            session.send(&error_response("28P01", "password authentication failed"));
+        });
+        let mut config = server.config();
+        config.channel_binding = ChannelBinding::Disable;
+
+        let Err(RuntimeError::PgError(error)) = Connection::connect(&config) else {
+            panic!("expected the mock to reject the password");
+        };
+        assert_eq!(error.code(), Some("28P01"));
+        server.finish();
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn the_tls_handshake_names_the_sni_host() {
+        let server = MockServer::start(|session| {
+            assert_eq!(session.read_packet(), 80877103u32.to_be_bytes());
+            session.send(b"S");
+            let header = session.read_bytes(5);
+            assert_eq!(header[0], 22, "not a handshake record");
+            let length = u16::from_be_bytes([header[3], header[4]]) as usize;
+            let client_hello = session.read_bytes(length);
+            assert!(client_hello
+                .windows(b"db.example.com".len())
+                .any(|window| window == b"db.example.com"));
+        });
+        let mut config = server.config();
+        config.ssl_mode = SslMode::Require;
+        config.ssl_sni_host = Some("db.example.com".to_string());
+        assert_ne!(config.host, config.tls_host());
+
+        // The mock can't finish the handshake, only check who it was for.
+        assert!(Connection::connect(&config).is_err());
+        server.finish();
+    }
+
+    #[cfg(not(feature = "tls"))]
+    #[test]
+    fn without_tls_support_connects_in_plaintext() {
+        let server = MockServer::start(|session| {
+            // The startup message comes first, with no `SSLRequest`.
+            session.accept();
+            assert_eq!(session.expect_query(), "SELECT 1");
+            session.complete("SELECT 0", b'I');
+        });
+        let mut config = server.config();
+        config.ssl_mode = SslMode::Prefer;
+        let mut connection = Connection::connect(&config).unwrap();
+        connection.execute("SELECT 1").unwrap();
+        drop(connection);
+        server.finish();
+
+        let server = MockServer::start(|_| {});
+        config = server.config();
+        config.ssl_mode = SslMode::Require;
+        assert!(matches!(
+            Connection::connect(&config),
+            Err(RuntimeError::TlsUnavailable)
+        ));
+        server.finish();
+    }
+
+    #[test]
+    fn a_query_with_a_null_byte_is_never_sent() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            // The rejected query left nothing behind: the next message is
+            // the query after it.
+            assert_eq!(session.expect_query(), "SELECT 1");
+            session.complete("SELECT 0", b'I');
+            session.expect(b'X');
+        });
+        let mut connection = server.connect();
+
+        assert!(matches!(
+            connection.query("SELECT 1\0; DROP TABLE t"),
+            Err(RuntimeError::InvalidQuery(_))
+        ));
+        connection.query("SELECT 1").unwrap();
+        drop(connection);
+        server.finish();
+    }
+}
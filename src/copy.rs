@@ -0,0 +1,292 @@
+use crate::connection::*;
+use crate::message::*;
+use crate::sql::*;
+use crate::statement::*;
+
+use std::io;
+use std::io::prelude::*;
+
+/// Rows are batched into `CopyData` messages of about this many bytes.
+const COPY_CHUNK_SIZE: usize = 64 * 1024;
+
+impl Connection {
+    /// Runs a `COPY ... FROM STDIN` statement, streaming `data` to the server,
+    /// and returns the number of rows loaded.
+    ///
+    /// The chunks are sent as-is, so they must already be in the format the
+    /// statement asks for; message boundaries don't need to match rows.
+    pub fn copy_in(
+        &mut self,
+        query: &str,
+        data: impl Iterator<Item = Vec<u8>>,
+    ) -> Result<u64, RuntimeError> {
+        self.send_query(query)?;
+
+        loop {
+            match self.receive()? {
+                BackendMessage::CopyInResponse { .. } => break,
+                BackendMessage::ErrorResponse(fields) => {
+                    self.read_until_ready()?;
+                    return Err(RuntimeError::PgError(PgError { fields }));
+                }
+                BackendMessage::ReadyForQuery { .. } => {
+                    return Err(RuntimeError::ParseMessageError)
+                }
+                _ => {}
+            }
+        }
+
+        for chunk in data {
+            self.send(FrontendMessage::CopyData { data: chunk })?;
+        }
+        self.send(FrontendMessage::CopyDone)?;
+
+        let tag = self.read_until_ready()?;
+        Ok(tag.as_deref().map(rows_affected).unwrap_or(0))
+    }
+
+    /// Runs a `COPY ... TO STDOUT` statement and returns everything it wrote.
+    pub fn copy_out(&mut self, query: &str) -> Result<Vec<u8>, RuntimeError> {
+        self.send_query(query)?;
+
+        let mut data = vec![];
+        loop {
+            match self.receive()? {
+                BackendMessage::CopyData { data: chunk } => data.extend_from_slice(&chunk),
+                BackendMessage::CopyDone => break,
+                BackendMessage::ErrorResponse(fields) => {
+                    self.read_until_ready()?;
+                    return Err(RuntimeError::PgError(PgError { fields }));
+                }
+                BackendMessage::ReadyForQuery { .. } => {
+                    return Err(RuntimeError::ParseMessageError)
+                }
+                _ => {}
+            }
+        }
+
+        self.read_until_ready()?;
+        Ok(data)
+    }
+
+    /// Runs a `COPY ... TO STDOUT` statement, handing back its output as a
+    /// [`Read`] that pulls `CopyData` messages off the socket as it goes, so
+    /// a table can be piped to a file without holding it all in memory.
+    pub fn copy_out_stream(&mut self, query: &str) -> Result<CopyOutStream<'_>, RuntimeError> {
+        self.send_query(query)?;
+
+        loop {
+            match self.receive()? {
+                BackendMessage::CopyOutResponse { .. } => break,
+                BackendMessage::ErrorResponse(fields) => {
+                    self.read_until_ready()?;
+                    return Err(RuntimeError::PgError(PgError { fields }));
+                }
+                BackendMessage::ReadyForQuery { .. } => {
+                    return Err(RuntimeError::ParseMessageError)
+                }
+                _ => {}
+            }
+        }
+
+        Ok(CopyOutStream {
+            connection: self,
+            chunk: vec![],
+            offset: 0,
+            done: false,
+        })
+    }
+
+    /// Bulk-loads `rows` into `table` with `COPY ... FROM STDIN` in CSV format,
+    /// returning the number of rows loaded.
+    ///
+    /// Column names are quoted; `table` is used as-is so it can be
+    /// schema-qualified.
+    pub fn copy_in_csv(
+        &mut self,
+        table: &str,
+        columns: &[&str],
+        rows: impl Iterator<Item = Vec<String>>,
+    ) -> Result<u64, RuntimeError> {
+        let columns: Vec<String> = columns.iter().map(|c| quote_identifier(c)).collect();
+        let query = format!(
+            "COPY {} ({}) FROM STDIN WITH (FORMAT csv)",
+            table,
+            columns.join(", ")
+        );
+
+        let mut rows = rows.peekable();
+        let chunks = std::iter::from_fn(move || {
+            rows.peek()?;
+            let mut chunk = String::new();
+            while chunk.len() < COPY_CHUNK_SIZE {
+                let Some(row) = rows.next() else { break };
+                chunk.push_str(&csv_line(&row));
+            }
+            Some(chunk.into_bytes())
+        });
+
+        self.copy_in(&query, chunks)
+    }
+}
+
+/// The output of a `COPY ... TO STDOUT`, from
+/// [`Connection::copy_out_stream`].
+///
+/// Reads end at the `CopyDone`, after which the connection is ready for the
+/// next query. Failures, including an `ErrorResponse` partway through, come
+/// out as `io::Error`s describing the [`RuntimeError`]. Dropping the stream
+/// early reads and discards the rest of the output.
+pub struct CopyOutStream<'a> {
+    connection: &'a mut Connection,
+    chunk: Vec<u8>,
+    offset: usize,
+    done: bool,
+}
+
+impl CopyOutStream<'_> {
+    /// Fetches the next `CopyData`, returning `false` at the end of the copy.
+    fn next_chunk(&mut self) -> Result<bool, RuntimeError> {
+        loop {
+            match self.connection.receive() {
+                Ok(BackendMessage::CopyData { data }) => {
+                    self.chunk = data;
+                    self.offset = 0;
+                    return Ok(true);
+                }
+                Ok(BackendMessage::CopyDone) => {
+                    self.done = true;
+                    self.connection.read_until_ready()?;
+                    return Ok(false);
+                }
+                Ok(BackendMessage::ErrorResponse(fields)) => {
+                    self.done = true;
+                    self.connection.read_until_ready()?;
+                    return Err(RuntimeError::PgError(PgError { fields }));
+                }
+                Ok(_) => {}
+                Err(error) => {
+                    self.done = true;
+                    return Err(error);
+                }
+            }
+        }
+    }
+}
+
+impl Read for CopyOutStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.offset == self.chunk.len() {
+            if self.done || !self.next_chunk().map_err(into_io_error)? {
+                return Ok(0);
+            }
+        }
+        let count = buf.len().min(self.chunk.len() - self.offset);
+        buf[..count].copy_from_slice(&self.chunk[self.offset..self.offset + count]);
+        self.offset += count;
+        Ok(count)
+    }
+}
+
+impl Drop for CopyOutStream<'_> {
+    fn drop(&mut self) {
+        while !self.done && matches!(self.next_chunk(), Ok(true)) {}
+    }
+}
+
+fn into_io_error(error: RuntimeError) -> io::Error {
+    match error {
+        RuntimeError::IoError(error) => error,
+        error => io::Error::other(format!("{:?}", error)),
+    }
+}
+
+/// Encodes one CSV record, newline included.
+pub fn csv_line(fields: &[String]) -> String {
+    let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+    format!("{}\n", fields.join(","))
+}
+
+/// Quotes a field when it holds a delimiter, quote or line break. Empty
+/// strings are quoted too, since an unquoted empty field means NULL.
+fn csv_field(field: &str) -> String {
+    if field.is_empty() || field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::*;
+
+    /// The whole reply to a text `COPY ... TO STDOUT` of one column, with
+    /// each of `chunks` in its own `CopyData`.
+    fn copy_out_reply(chunks: &[&[u8]]) -> Vec<u8> {
+        let mut reply = frame(b'H', &[0, 0, 1, 0, 0]);
+        for chunk in chunks {
+            reply.extend(frame(b'd', chunk));
+        }
+        reply.extend(frame(b'c', b""));
+        reply.extend(command_complete(&format!("COPY {}", chunks.len())));
+        reply.extend(ready(b'I'));
+        reply
+    }
+
+    #[test]
+    fn copy_out_collects_every_chunk() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            assert_eq!(session.expect_query(), "COPY t TO STDOUT");
+            session.send(&copy_out_reply(&[b"1\n", b"2\n", b"3\n"]));
+            assert_eq!(session.expect_query(), "SELECT 1");
+            session.complete("SELECT 1", b'I');
+        });
+        let mut connection = server.connect();
+
+        assert_eq!(
+            connection.copy_out("COPY t TO STDOUT").unwrap(),
+            b"1\n2\n3\n"
+        );
+        assert_eq!(connection.last_command_tag(), Some("COPY 3"));
+        // With everything read up to ReadyForQuery, the next query goes through.
+        connection.execute("SELECT 1").unwrap();
+        server.finish();
+    }
+
+    #[test]
+    fn copy_out_stream_reads_across_chunks() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            assert_eq!(session.expect_query(), "COPY t TO STDOUT");
+            session.send(&copy_out_reply(&[b"alpha\n", b"beta\n", b"gamma\n"]));
+        });
+        let mut connection = server.connect();
+
+        let mut stream = connection.copy_out_stream("COPY t TO STDOUT").unwrap();
+        let mut output = vec![];
+        let mut buf = [0; 4];
+        loop {
+            let count = stream.read(&mut buf).unwrap();
+            if count == 0 {
+                break;
+            }
+            output.extend_from_slice(&buf[..count]);
+        }
+        assert_eq!(output, b"alpha\nbeta\ngamma\n");
+        drop(stream);
+        assert_eq!(connection.transaction_status(), ReadyForQueryStatus::Idle);
+        server.finish();
+    }
+
+    #[test]
+    fn quotes_only_the_fields_that_need_it() {
+        let fields = ["1", "plain", "a,b", "say \"hi\"", "two\nlines", ""].map(String::from);
+        assert_eq!(
+            csv_line(&fields),
+            "1,plain,\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\",\"\"\n"
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use crate::connection::*;
+
+use std::time::Duration;
+
+/// The outcome of [`Connection::explain_analyze`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExplainResult {
+    /// The plan as the server printed it, in JSON.
+    pub plan: String,
+    pub planning_time: Option<Duration>,
+    pub execution_time: Option<Duration>,
+}
+
+impl Connection {
+    /// Runs `sql` under `EXPLAIN (ANALYZE, FORMAT JSON)` and reads the
+    /// server-side timings out of the plan.
+    ///
+    /// The statement really is executed, so wrap writes in a transaction that
+    /// gets rolled back if they shouldn't stick.
+    pub fn explain_analyze(&mut self, sql: &str) -> Result<ExplainResult, RuntimeError> {
+        let result = self.query(&format!("EXPLAIN (ANALYZE, FORMAT JSON) {}", sql))?;
+        let plan = result
+            .rows
+            .iter()
+            .map(|row| row.get_str(0).ok_or(RuntimeError::ParseMessageError))
+            .collect::<Result<Vec<_>, _>>()?
+            .join("\n");
+
+        Ok(parse_explain(plan))
+    }
+}
+
+/// Extracts the timings from a JSON plan. They are top-level keys of the
+/// plan's single object, in milliseconds.
+pub fn parse_explain(plan: String) -> ExplainResult {
+    ExplainResult {
+        planning_time: json_milliseconds(&plan, "Planning Time"),
+        execution_time: json_milliseconds(&plan, "Execution Time"),
+        plan,
+    }
+}
+
+/// Finds the number following `"key":`. Plan node keys never repeat these
+/// names, so the first match is the one wanted.
+fn json_milliseconds(json: &str, key: &str) -> Option<Duration> {
+    let start = json.find(&format!("\"{}\"", key))? + key.len() + 2;
+    let value = json[start..].trim_start().strip_prefix(':')?.trim_start();
+    let end = value
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E')))
+        .unwrap_or(value.len());
+    let milliseconds: f64 = value[..end].parse().ok()?;
+    Duration::try_from_secs_f64(milliseconds / 1000.0).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_timings_out_of_a_json_plan() {
+        let plan = r#"[
+  {
+    "Plan": {
+      "Node Type": "Result",
+      "Actual Total Time": 0.002
+    },
+    "Planning Time": 0.025,
+    "Triggers": [],
+    "Execution Time": 1.5e1
+  }
+]"#;
+        let result = parse_explain(plan.to_string());
+        assert_eq!(result.planning_time, Some(Duration::from_micros(25)));
+        assert_eq!(result.execution_time, Some(Duration::from_millis(15)));
+        assert_eq!(result.plan, plan);
+
+        let without = parse_explain("[]".to_string());
+        assert_eq!(without.planning_time, None);
+        assert_eq!(without.execution_time, None);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::connection::*;
+use crate::message::*;
+
+/// Mode flag for `lo_open`, from `libpq/libpq-fs.h`.
+const INV_READ: i32 = 0x40000;
+
+/// How many bytes each `loread` call asks for.
+const CHUNK_SIZE: i32 = 64 * 1024;
+
+/// OIDs of the server functions backing the large-object API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LargeObjectFunctions {
+    pub lo_open: u32,
+    pub loread: u32,
+    pub lo_close: u32,
+}
+
+impl LargeObjectFunctions {
+    /// Looks the functions up in `pg_proc`.
+    pub fn lookup(connection: &mut Connection) -> Result<Self, RuntimeError> {
+        let result = connection.query(
+            "SELECT proname, oid FROM pg_catalog.pg_proc \
+             WHERE proname IN ('lo_open', 'loread', 'lo_close') \
+             AND pronamespace = 'pg_catalog'::regnamespace",
+        )?;
+
+        let find = |name: &str| {
+            result
+                .rows
+                .iter()
+                .find(|row| row.get_str(0) == Some(name))
+                .and_then(|row| row.get_str(1)?.parse().ok())
+                .ok_or(RuntimeError::ParseMessageError)
+        };
+
+        Ok(Self {
+            lo_open: find("lo_open")?,
+            loread: find("loread")?,
+            lo_close: find("lo_close")?,
+        })
+    }
+}
+
+impl Connection {
+    /// Reads a whole large object through fast-path calls to `lo_open`,
+    /// `loread` and `lo_close`.
+    ///
+    /// Descriptors only live as long as a transaction, so the calls are
+    /// wrapped in one unless the caller already has one open, which is then
+    /// left for them to end. The function OIDs are looked up on first use.
+    pub fn lo_read(&mut self, oid: u32) -> Result<Vec<u8>, RuntimeError> {
+        let functions = match self.large_object_functions {
+            Some(functions) => functions,
+            None => {
+                let functions = LargeObjectFunctions::lookup(self)?;
+                self.large_object_functions = Some(functions);
+                functions
+            }
+        };
+
+        if self.transaction_status() != ReadyForQueryStatus::Idle {
+            return self.lo_read_in_transaction(functions, oid);
+        }
+
+        self.execute("BEGIN")?;
+        match self.lo_read_in_transaction(functions, oid) {
+            Ok(data) => {
+                self.execute("COMMIT")?;
+                Ok(data)
+            }
+            Err(error) => {
+                self.execute("ROLLBACK")?;
+                Err(error)
+            }
+        }
+    }
+
+    fn lo_read_in_transaction(
+        &mut self,
+        functions: LargeObjectFunctions,
+        oid: u32,
+    ) -> Result<Vec<u8>, RuntimeError> {
+        let fd = self
+            .function_call(
+                functions.lo_open,
+                vec![
+                    Some(oid.to_be_bytes().to_vec()),
+                    Some(INV_READ.to_be_bytes().to_vec()),
+                ],
+            )?
+            .ok_or(RuntimeError::ParseMessageError)?;
+
+        let mut data = vec![];
+        loop {
+            let chunk = self
+                .function_call(
+                    functions.loread,
+                    vec![Some(fd.clone()), Some(CHUNK_SIZE.to_be_bytes().to_vec())],
+                )?
+                .unwrap_or_default();
+            data.extend_from_slice(&chunk);
+            if chunk.len() < CHUNK_SIZE as usize {
+                break;
+            }
+        }
+
+        self.function_call(functions.lo_close, vec![Some(fd)])?;
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::*;
+
+    /// Answers the `pg_proc` lookup of the function OIDs.
+    fn serve_lookup(session: &mut MockSession, status: u8) {
+        assert!(session.expect_query().contains("pg_proc"));
+        let mut reply = row_description(&[("proname", 19), ("oid", 26)]);
+        for (name, oid) in [("lo_open", "952"), ("loread", "954"), ("lo_close", "953")] {
+            reply.extend(data_row(&[Some(name), Some(oid)]));
+        }
+        reply.extend(command_complete("SELECT 3"));
+        reply.extend(ready(status));
+        session.send(&reply);
+    }
+
+    /// Answers `lo_open`, `loread` and `lo_close` for a 5-byte object.
+    fn serve_calls(session: &mut MockSession) {
+        for value in [&[0, 0, 0, 0][..], b"hello", &[0, 0, 0, 0]] {
+            session.expect(b'F');
+            session.send(&[function_result(Some(value)), ready(b'T')].concat());
+        }
+    }
+
+    #[test]
+    fn wraps_the_read_in_a_transaction_when_idle() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            serve_lookup(session, b'I');
+            assert_eq!(session.expect_query(), "BEGIN");
+            session.complete("BEGIN", b'T');
+            serve_calls(session);
+            assert_eq!(session.expect_query(), "COMMIT");
+            session.complete("COMMIT", b'I');
+        });
+        let mut connection = server.connect();
+        assert_eq!(connection.lo_read(16384).unwrap(), b"hello");
+        server.finish();
+    }
+
+    #[test]
+    fn reads_inside_the_callers_transaction() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            assert_eq!(session.expect_query(), "BEGIN");
+            session.complete("BEGIN", b'T');
+            serve_lookup(session, b'T');
+            serve_calls(session);
+            // Left for the caller to end.
+            assert_eq!(session.expect_query(), "COMMIT");
+            session.complete("COMMIT", b'I');
+        });
+        let mut connection = server.connect();
+        connection.execute("BEGIN").unwrap();
+        assert_eq!(connection.lo_read(16384).unwrap(), b"hello");
+        connection.execute("COMMIT").unwrap();
+        server.finish();
+    }
+}
@@ -0,0 +1,29 @@
+pub mod bench;
+pub mod cancel;
+pub mod config;
+pub mod connection;
+pub mod copy;
+pub mod explain;
+pub mod large_object;
+pub mod message;
+pub mod notify;
+pub mod observer;
+pub mod pool;
+pub mod replication;
+pub mod row;
+pub mod schema;
+pub mod scram;
+pub mod script;
+pub mod session;
+pub mod sql;
+pub mod statement;
+pub mod stream;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod transaction;
+pub mod transport;
+pub mod types;
+pub mod wire_log;
+
+#[cfg(test)]
+mod mock;
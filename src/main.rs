@@ -1,11 +1,7 @@
-mod message;
-use crate::message::*;
-
-mod config;
-use crate::config::*;
-
-use std::io::prelude::*;
-use std::net::TcpStream;
+use xata_benchmark::bench::*;
+use xata_benchmark::config::*;
+use xata_benchmark::connection::*;
+use xata_benchmark::message::*;
 
 fn main() {
     let config = load_config_from_env().unwrap();
@@ -13,8 +9,34 @@ fn main() {
         let message = match error {
             RuntimeError::IoError(error) => format!("{:?}", error),
             RuntimeError::ParseMessageError => "ParseMessageError".to_string(),
-            RuntimeError::TlsHandshakeError(error) => format!("{:?}", error),
+            #[cfg(feature = "tls")]
             RuntimeError::TlsError(error) => format!("{:?}", error),
+            RuntimeError::TlsUnavailable => "TlsUnavailable".to_string(),
+            RuntimeError::AuthenticationFailed => "AuthenticationFailed".to_string(),
+            RuntimeError::ChannelBindingRequired => "ChannelBindingRequired".to_string(),
+            RuntimeError::UnexpectedMessage { prefix } => {
+                format!(
+                    "UnexpectedMessage: {:?} ({})",
+                    prefix,
+                    message_name(prefix).unwrap_or("unknown")
+                )
+            }
+            RuntimeError::Desynchronized { header } => {
+                format!("Desynchronized: header {:02x?}", header)
+            }
+            RuntimeError::Timeout => "Timeout".to_string(),
+            RuntimeError::UnsupportedAuth(method) => format!(
+                "the server requires {} authentication, which isn't supported; \
+                 configure it to use password or SCRAM authentication instead",
+                method
+            ),
+            RuntimeError::InvalidQuery(reason) => format!("InvalidQuery: {}", reason),
+            RuntimeError::RowLimitExceeded => "RowLimitExceeded".to_string(),
+            RuntimeError::RolledBack => "RolledBack".to_string(),
+            RuntimeError::MessageTooLarge { length, max } => {
+                format!("MessageTooLarge: {} bytes, at most {}", length, max)
+            }
+            RuntimeError::PgError(error) => format!("{:?}", error),
         };
         println!("Error: {}", message);
         std::process::exit(1);
@@ -22,89 +44,26 @@ fn main() {
 }
 
 fn run(config: Config) -> Result<(), RuntimeError> {
-    let addr = format!("{}:{}", config.host, config.port);
-
-    let mut client = TcpStream::connect(addr.clone())?;
-
-    client.write_all(&FrontendMessage::RequestSSL.to_bytes())?;
-
-    let mut buf = [0u8; 1];
-    client.read(&mut buf)?;
-
-    assert!(buf == "S".as_bytes());
-
-    let mut client = native_tls::TlsConnector::new()?.connect(&config.host, client)?;
-
-    write_message(
-        &mut client,
-        FrontendMessage::StartupMessage {
-            user: config.user.clone(),
-            database: config.database.clone(),
-        },
-    )?;
-
-    let mut requested = false;
-
-    loop {
-        let message = read_message(&mut client)?;
-
-        match message {
-            BackendMessage::AuthenticationCleartextPassword => write_message(
-                &mut client,
-                FrontendMessage::PasswordMessage {
-                    password: config.password.clone(),
-                },
-            )?,
-            BackendMessage::ReadyForQuery {
-                status: ReadyForQueryStatus::Idle,
-            } => {
-                if !requested {
-                    write_message(
-                        &mut client,
-                        FrontendMessage::SimpleQuery {
-                            query: "SELECT * FROM my_table LIMIT 3;".to_string(),
-                        },
-                    )?;
-                    requested = true;
-                } else {
-                    return Ok(());
-                }
-            }
-            _ => {}
-        }
+    let mut connection = Connection::connect(&config)?;
+    if let Some(encoding) = connection.unsupported_client_encoding() {
+        println!(
+            "Warning: the server uses client_encoding {}, but only UTF8 is supported",
+            encoding
+        );
     }
-}
 
-enum RuntimeError {
-    IoError(std::io::Error),
-    ParseMessageError,
-    TlsHandshakeError(native_tls::HandshakeError<TcpStream>),
-    TlsError(native_tls::Error),
-}
-
-impl From<std::io::Error> for RuntimeError {
-    fn from(error: std::io::Error) -> Self {
-        Self::IoError(error)
+    if let Some(warning) = config.empty_query_warning() {
+        eprintln!("Warning: {}", warning);
     }
-}
 
-impl From<ReadMessageError> for RuntimeError {
-    fn from(error: ReadMessageError) -> Self {
-        match error {
-            ReadMessageError::IoError(error) => Self::IoError(error),
-            ReadMessageError::ParseError => Self::ParseMessageError,
-        }
-    }
-}
+    let report = run_benchmark(
+        &mut connection,
+        &config.query,
+        config.iterations,
+        config.query_mode,
+    )?;
 
-impl From<native_tls::HandshakeError<TcpStream>> for RuntimeError {
-    fn from(error: native_tls::HandshakeError<TcpStream>) -> Self {
-        RuntimeError::TlsHandshakeError(error)
-    }
-}
+    println!("{:#?}", report);
 
-impl From<native_tls::Error> for RuntimeError {
-    fn from(error: native_tls::Error) -> Self {
-        RuntimeError::TlsError(error)
-    }
+    Ok(())
 }
@@ -2,71 +2,408 @@ use std::array::TryFromSliceError;
 use std::collections::HashMap;
 use std::io::prelude::*;
 use std::str;
+use std::time::{Duration, Instant};
 
+use crate::wire_log::*;
+
+/// Appends a value to a message body in its wire representation.
 trait Encoder {
-    fn process(self) -> Vec<u8>;
+    fn encode(self, buf: &mut Vec<u8>);
+}
+
+impl Encoder for u8 {
+    fn encode(self, buf: &mut Vec<u8>) {
+        buf.push(self);
+    }
 }
 
 impl Encoder for u16 {
-    fn process(self) -> Vec<u8> {
-        self.to_be_bytes().to_vec()
+    fn encode(self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_be_bytes());
     }
 }
 
 impl Encoder for u32 {
-    fn process(self) -> Vec<u8> {
-        self.to_be_bytes().to_vec()
+    fn encode(self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_be_bytes());
+    }
+}
+
+impl Encoder for i32 {
+    fn encode(self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_be_bytes());
+    }
+}
+
+/// Raw bytes, without a terminator or length.
+impl Encoder for &[u8] {
+    fn encode(self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
     }
 }
 
 impl Encoder for &str {
-    fn process(self) -> Vec<u8> {
-        let mut bytes = self.as_bytes().to_vec();
-        bytes.push(0);
-        bytes
+    fn encode(self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_bytes());
+        buf.push(0);
+    }
+}
+
+/// Length-prefixed values, with -1 for NULL.
+impl Encoder for &[Option<Vec<u8>>] {
+    fn encode(self, buf: &mut Vec<u8>) {
+        for value in self {
+            match value {
+                Some(bytes) => {
+                    (bytes.len() as i32).encode(buf);
+                    bytes.as_slice().encode(buf);
+                }
+                None => (-1i32).encode(buf),
+            }
+        }
+    }
+}
+
+impl Encoder for &[u32] {
+    fn encode(self, buf: &mut Vec<u8>) {
+        for value in self {
+            value.encode(buf);
+        }
+    }
+}
+
+impl Encoder for &[Format] {
+    fn encode(self, buf: &mut Vec<u8>) {
+        for format in self {
+            format.code().encode(buf);
+        }
     }
 }
 
-impl Encoder for String {
-    fn process(self) -> Vec<u8> {
-        let mut bytes = (self).as_bytes().to_vec();
-        bytes.push(0);
-        bytes
+/// Name and value pairs, as in the startup packet.
+impl Encoder for &[(String, String)] {
+    fn encode(self, buf: &mut Vec<u8>) {
+        for (name, value) in self {
+            name.as_str().encode(buf);
+            value.as_str().encode(buf);
+        }
     }
 }
 
+/// Appends a whole message to `buf`: the prefix (empty for the startup-phase
+/// messages that have none), a length placeholder, the body, and then the
+/// length patched in. A length that doesn't fit in a `u32` returns
+/// [`MessageTooLarge`] from the enclosing function.
 macro_rules! encode {
-    ($prefix:expr, $($args:expr),*) => {{
-        let mut result = vec![];
-        $(result.extend(Encoder::process($args));)*
-        // result.push(0);
-        let size = Encoder::process(result.len() as u32 + 4);
-        [$prefix.as_bytes().to_vec(), size, result].concat()
+    ($buf:expr, $prefix:expr $(, $args:expr)*) => {{
+        let buf: &mut Vec<u8> = $buf;
+        buf.extend_from_slice($prefix.as_bytes());
+        let start = buf.len();
+        buf.extend_from_slice(&[0; 4]);
+        $(Encoder::encode($args, buf);)*
+        let length = length_field(buf.len() - start)?;
+        buf[start..start + 4].copy_from_slice(&length.to_be_bytes());
     }};
 }
 
+/// The value of a length field counting `length` bytes, if it fits.
+fn length_field(length: usize) -> Result<u32, MessageTooLarge> {
+    u32::try_from(length).map_err(|_| MessageTooLarge { length })
+}
+
+/// A message whose body doesn't fit the `u32` length field, `length` being
+/// what the field would have held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageTooLarge {
+    pub length: usize,
+}
+
+impl From<MessageTooLarge> for std::io::Error {
+    fn from(error: MessageTooLarge) -> Self {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("message of {} bytes is too large", error.length),
+        )
+    }
+}
+
 #[derive(Debug)]
 pub enum FrontendMessage {
     RequestSSL,
-    StartupMessage { user: String, database: String },
-    PasswordMessage { password: String },
-    SimpleQuery { query: String },
+    /// Asks for GSSAPI encryption, answered like `RequestSSL` with `G` or `N`.
+    RequestGssEncryption,
+    CancelRequest {
+        process_id: u32,
+        secret_key: i32,
+    },
+    StartupMessage {
+        user: String,
+        database: String,
+        /// The protocol version is always 3, but the minor version may be
+        /// raised to probe what the server supports.
+        minor_version: u16,
+        parameters: Vec<(String, String)>,
+    },
+    PasswordMessage {
+        password: String,
+    },
+    SaslInitialResponse {
+        mechanism: String,
+        data: Vec<u8>,
+    },
+    SaslResponse {
+        data: Vec<u8>,
+    },
+    SimpleQuery {
+        query: String,
+    },
+    Parse {
+        name: String,
+        query: String,
+        param_types: Vec<u32>,
+    },
+    Bind {
+        portal: String,
+        statement: String,
+        /// One format for every parameter, or none for all text.
+        param_formats: Vec<Format>,
+        params: Vec<Option<Vec<u8>>>,
+        /// One format for every result column, or none for all text.
+        result_formats: Vec<Format>,
+    },
+    Execute {
+        portal: String,
+        max_rows: u32,
+    },
+    Close {
+        target: Target,
+        name: String,
+    },
+    Describe {
+        target: Target,
+        name: String,
+    },
+    CopyData {
+        data: Vec<u8>,
+    },
+    CopyDone,
+    CopyFail {
+        message: String,
+    },
+    FunctionCall {
+        oid: u32,
+        args: Vec<Option<Vec<u8>>>,
+    },
+    Flush,
+    Sync,
+    Terminate,
 }
 
-impl FrontendMessage {
-    pub fn to_bytes(self) -> Vec<u8> {
+/// The name of the unnamed prepared statement and of the unnamed portal.
+///
+/// Like any name it is sent as a C string, so it encodes as a single `0`
+/// byte rather than being left out of the message.
+pub const UNNAMED: &str = "";
+
+/// Whether a `Close` (or `Describe`) refers to a prepared statement or a portal.
+#[derive(Debug, Clone, Copy)]
+pub enum Target {
+    Statement,
+    Portal,
+}
+
+impl Target {
+    fn code(self) -> u8 {
+        match self {
+            Self::Statement => b'S',
+            Self::Portal => b'P',
+        }
+    }
+}
+
+/// The wire format of a value, as given by a field's `format_code`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Text,
+    Binary,
+}
+
+impl Format {
+    pub fn code(self) -> u16 {
         match self {
-            Self::RequestSSL => encode!("", 1234u16, 5679u16, ""),
-            Self::StartupMessage { user, database } => {
-                encode!("", 3u16, 0u16, "user", user, "database", database, "")
+            Self::Text => 0,
+            Self::Binary => 1,
+        }
+    }
+
+    pub fn from_code(code: i16) -> Self {
+        match code {
+            1 => Self::Binary,
+            _ => Self::Text,
+        }
+    }
+}
+
+impl FrontendMessage {
+    /// Parses the body of a `StartupMessage`, everything after its length:
+    /// the protocol version, then `name`, `value` pairs of null-terminated
+    /// strings, ending with an empty name.
+    ///
+    /// `user` and `database` are taken out of the parameters, the database
+    /// defaulting to the user as the server does. Any protocol version other
+    /// than 3 is rejected, which includes the magic numbers of `SSLRequest`,
+    /// `GSSENCRequest` and `CancelRequest`.
+    pub fn parse_startup(body: &[u8]) -> Result<FrontendMessage, ReadMessageError> {
+        let major_version = u16::from_be_bytes(body.get(..2).unwrap_or_default().try_into()?);
+        let minor_version = u16::from_be_bytes(body.get(2..4).unwrap_or_default().try_into()?);
+        if major_version != 3 {
+            return Err(ReadMessageError::ParseError);
+        }
+
+        let mut strings = body[4..].split(|&b| b == 0);
+        let mut user = None;
+        let mut database = None;
+        let mut parameters = vec![];
+        loop {
+            let name = strings.next().ok_or(ReadMessageError::ParseError)?;
+            if name.is_empty() {
+                break;
             }
-            Self::PasswordMessage { password } => encode!("p", password, ""),
-            Self::SimpleQuery { query } => encode!("Q", query),
+            let value = strings.next().ok_or(ReadMessageError::ParseError)?;
+            let name = str::from_utf8(name).map_err(|_| ReadMessageError::ParseError)?;
+            let value = str::from_utf8(value)
+                .map_err(|_| ReadMessageError::ParseError)?
+                .to_string();
+            match name {
+                "user" => user = Some(value),
+                "database" => database = Some(value),
+                _ => parameters.push((name.to_string(), value)),
+            }
+        }
+        // Only the empty slice after the terminator may remain.
+        if strings.next() != Some(&[]) || strings.next().is_some() {
+            return Err(ReadMessageError::ParseError);
         }
+
+        let user = user.ok_or(ReadMessageError::ParseError)?;
+        Ok(FrontendMessage::StartupMessage {
+            database: database.unwrap_or_else(|| user.clone()),
+            user,
+            minor_version,
+            parameters,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MessageTooLarge> {
+        let mut buf = vec![];
+        self.write_to(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Appends the encoded message to `buf`, which can be reused across
+    /// messages to avoid allocating for each one. On error `buf` is left as
+    /// it was.
+    pub fn write_to(&self, buf: &mut Vec<u8>) -> Result<(), MessageTooLarge> {
+        let start = buf.len();
+        let result = self.encode_into(buf);
+        if result.is_err() {
+            buf.truncate(start);
+        }
+        result
+    }
+
+    fn encode_into(&self, buf: &mut Vec<u8>) -> Result<(), MessageTooLarge> {
+        match self {
+            Self::RequestSSL => encode!(buf, "", 1234u16, 5679u16),
+            Self::RequestGssEncryption => encode!(buf, "", 1234u16, 5680u16),
+            Self::CancelRequest {
+                process_id,
+                secret_key,
+            } => encode!(buf, "", 1234u16, 5678u16, *process_id, *secret_key),
+            Self::StartupMessage {
+                user,
+                database,
+                minor_version,
+                parameters,
+            } => encode!(
+                buf,
+                "",
+                3u16,
+                *minor_version,
+                "user",
+                user.as_str(),
+                "database",
+                database.as_str(),
+                parameters.as_slice(),
+                ""
+            ),
+            Self::PasswordMessage { password } => encode!(buf, "p", password.as_str()),
+            Self::SaslInitialResponse { mechanism, data } => encode!(
+                buf,
+                "p",
+                mechanism.as_str(),
+                data.len() as i32,
+                data.as_slice()
+            ),
+            Self::SaslResponse { data } => encode!(buf, "p", data.as_slice()),
+            Self::SimpleQuery { query } => encode!(buf, "Q", query.as_str()),
+            Self::Parse {
+                name,
+                query,
+                param_types,
+            } => encode!(
+                buf,
+                "P",
+                name.as_str(),
+                query.as_str(),
+                param_types.len() as u16,
+                param_types.as_slice()
+            ),
+            Self::Bind {
+                portal,
+                statement,
+                param_formats,
+                params,
+                result_formats,
+            } => encode!(
+                buf,
+                "B",
+                portal.as_str(),
+                statement.as_str(),
+                param_formats.len() as u16,
+                param_formats.as_slice(),
+                params.len() as u16,
+                params.as_slice(),
+                result_formats.len() as u16,
+                result_formats.as_slice()
+            ),
+            Self::Execute { portal, max_rows } => encode!(buf, "E", portal.as_str(), *max_rows),
+            Self::Close { target, name } => encode!(buf, "C", target.code(), name.as_str()),
+            Self::Describe { target, name } => encode!(buf, "D", target.code(), name.as_str()),
+            Self::CopyData { data } => encode!(buf, "d", data.as_slice()),
+            Self::CopyDone => encode!(buf, "c"),
+            Self::CopyFail { message } => encode!(buf, "f", message.as_str()),
+            // Arguments and result are all in binary format.
+            Self::FunctionCall { oid, args } => encode!(
+                buf,
+                "F",
+                *oid,
+                1u16,
+                1u16,
+                args.len() as u16,
+                args.as_slice(),
+                1u16
+            ),
+            Self::Flush => encode!(buf, "H"),
+            Self::Sync => encode!(buf, "S"),
+            Self::Terminate => encode!(buf, "X"),
+        }
+        Ok(())
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ReadyForQueryStatus {
     Idle,
     Transaction,
@@ -100,74 +437,401 @@ pub enum ErrorField {
 pub enum BackendMessage {
     AuthenticationOk,
     AuthenticationCleartextPassword,
-    AuthenticationSasl { mechanisms: Vec<String> },
+    AuthenticationGss,
+    AuthenticationSspi,
+    AuthenticationSasl {
+        mechanisms: Vec<String>,
+    },
+    AuthenticationSaslContinue {
+        data: Vec<u8>,
+    },
+    AuthenticationSaslFinal {
+        data: Vec<u8>,
+    },
     ErrorResponse(HashMap<ErrorField, String>),
-    BackendKeyData { process_id: u32, secret_key: i32 },
-    ReadyForQuery { status: ReadyForQueryStatus },
-    ParameterStatus { name: String, value: String },
-    Unknown { prefix: char, payload: Vec<u8> },
+    NoticeResponse(HashMap<ErrorField, String>),
+    BackendKeyData {
+        process_id: u32,
+        secret_key: i32,
+    },
+    ReadyForQuery {
+        status: ReadyForQueryStatus,
+    },
+    NotificationResponse {
+        process_id: u32,
+        channel: String,
+        payload: String,
+    },
+    /// The server doesn't support the requested minor version or some of the
+    /// `_pq_.` protocol options, and lists what it settled on.
+    NegotiateProtocolVersion {
+        newest_minor_version: u32,
+        unrecognized_options: Vec<String>,
+    },
+    ParameterStatus {
+        name: String,
+        value: String,
+    },
+    ParseComplete,
+    BindComplete,
+    CloseComplete,
+    CommandComplete {
+        tag: String,
+    },
+    /// Sent instead of `CommandComplete` for a query with no statement in it.
+    EmptyQueryResponse,
+    ParameterDescription {
+        types: Vec<u32>,
+    },
+    RowDescription {
+        fields: Vec<FieldDescription>,
+    },
+    /// Sent instead of `RowDescription` for a statement that returns no rows.
+    NoData,
+    DataRow {
+        columns: Vec<Option<Vec<u8>>>,
+    },
+    PortalSuspended,
+    FunctionCallResponse {
+        value: Option<Vec<u8>>,
+    },
+    CopyInResponse {
+        format: u8,
+        column_formats: Vec<u16>,
+    },
+    CopyOutResponse {
+        format: u8,
+        column_formats: Vec<u16>,
+    },
+    CopyBothResponse {
+        format: u8,
+        column_formats: Vec<u16>,
+    },
+    CopyData {
+        data: Vec<u8>,
+    },
+    CopyDone,
+    Unknown {
+        prefix: char,
+        payload: Vec<u8>,
+    },
+}
+
+impl BackendMessage {
+    /// For an `Unknown` message, the name of the protocol message its prefix
+    /// stands for, if any. This is usually a message the parser doesn't
+    /// implement, or one that arrived where it wasn't expected, such as
+    /// `CopyData` outside of a copy.
+    pub fn likely_name(&self) -> Option<&'static str> {
+        match self {
+            Self::Unknown { prefix, .. } => message_name(*prefix),
+            _ => None,
+        }
+    }
+}
+
+/// The name of the backend message sent with `prefix`.
+pub fn message_name(prefix: char) -> Option<&'static str> {
+    Some(match prefix {
+        'R' => "Authentication",
+        'K' => "BackendKeyData",
+        '2' => "BindComplete",
+        '3' => "CloseComplete",
+        'C' => "CommandComplete",
+        'd' => "CopyData",
+        'c' => "CopyDone",
+        'G' => "CopyInResponse",
+        'H' => "CopyOutResponse",
+        'W' => "CopyBothResponse",
+        'D' => "DataRow",
+        'I' => "EmptyQueryResponse",
+        'E' => "ErrorResponse",
+        'V' => "FunctionCallResponse",
+        'v' => "NegotiateProtocolVersion",
+        'n' => "NoData",
+        'N' => "NoticeResponse",
+        'A' => "NotificationResponse",
+        't' => "ParameterDescription",
+        'S' => "ParameterStatus",
+        '1' => "ParseComplete",
+        's' => "PortalSuspended",
+        'Z' => "ReadyForQuery",
+        'T' => "RowDescription",
+        _ => return None,
+    })
+}
+
+/// A column of a `RowDescription`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDescription {
+    pub name: String,
+    pub table_oid: u32,
+    pub column_id: i16,
+    pub type_oid: u32,
+    pub type_size: i16,
+    pub type_modifier: i32,
+    pub format_code: i16,
+}
+
+/// Reads backend messages off a stream, reusing a single body buffer.
+///
+/// A fresh `Vec` per message body used to be the main source of allocations
+/// in the read loop, one per message even for a 1-byte `ReadyForQuery`. The
+/// buffer is now `resize`d in place, so it only reallocates when a message is
+/// larger than any seen before, and a steady-state loop allocates nothing
+/// beyond the owned `String`/`Vec` fields of the variants that keep data.
+#[derive(Debug)]
+pub struct MessageReader {
+    buffer: Vec<u8>,
+    max_columns: usize,
+    strict: bool,
+    in_copy: bool,
+    timings: Option<ReadTimings>,
+}
+
+/// Where a [`MessageReader`] spent its time: blocked reading messages off
+/// the stream, or turning them into [`BackendMessage`]s.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadTimings {
+    pub network_wait: Duration,
+    pub parse_time: Duration,
+}
+
+impl ReadTimings {
+    pub fn merge(&mut self, other: ReadTimings) {
+        self.network_wait += other.network_wait;
+        self.parse_time += other.parse_time;
+    }
+}
+
+/// The longest message accepted, matching the server's 1 GB cap on a single
+/// allocation; any longer length can only come from a misread header.
+pub const MAX_MESSAGE_LENGTH: u32 = 0x4000_0000;
+
+/// PostgreSQL's own limit on the number of columns in a table or result.
+pub const DEFAULT_MAX_COLUMNS: usize = 1600;
+
+impl Default for MessageReader {
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
+}
+
+impl MessageReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pre-allocates the body buffer, so messages up to `capacity` bytes
+    /// never cause an allocation.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            buffer: Vec::with_capacity(capacity),
+            max_columns: DEFAULT_MAX_COLUMNS,
+            strict: false,
+            in_copy: false,
+            timings: None,
+        }
+    }
+
+    /// Caps the column count a `RowDescription` or `DataRow` may claim, so a
+    /// corrupt one fails to parse instead of driving a huge allocation.
+    pub fn set_max_columns(&mut self, max_columns: usize) {
+        self.max_columns = max_columns;
+    }
+
+    /// In strict mode, messages the parser doesn't know are an
+    /// [`ReadMessageError::UnexpectedMessage`] rather than a
+    /// [`BackendMessage::Unknown`].
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Starts or stops accumulating [`ReadTimings`]. Off by default, since
+    /// it reads the clock around every message.
+    pub fn set_timed(&mut self, timed: bool) {
+        self.timings = timed.then(ReadTimings::default);
+    }
+
+    /// Returns the timings accumulated since the last call and starts over,
+    /// or `None` when timing is off.
+    pub fn take_timings(&mut self) -> Option<ReadTimings> {
+        self.timings.as_mut().map(std::mem::take)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+
+    pub fn read_message(
+        &mut self,
+        reader: &mut dyn Read,
+    ) -> Result<BackendMessage, ReadMessageError> {
+        let (prefix, length) = self.read_frame(reader)?;
+        self.parse_frame(prefix, length)
+    }
+
+    /// Reads the next message into the internal buffer without parsing it,
+    /// returning its prefix and length for [`MessageReader::parse_frame`].
+    ///
+    /// This lets callers that don't need a message's contents, like a write
+    /// benchmark ignoring `DataRow`s, skip building it altogether.
+    pub fn read_frame(&mut self, reader: &mut dyn Read) -> Result<(char, u32), ReadMessageError> {
+        let started = self.timings.is_some().then(Instant::now);
+        let mut header = [0u8; 5];
+        reader.read_exact(&mut header)?;
+        let Some((prefix, length)) = frame_header(&header) else {
+            return Err(ReadMessageError::Desynchronized { header });
+        };
+
+        self.buffer.resize((length - 4).try_into()?, 0);
+        reader.read_exact(&mut self.buffer)?;
+
+        if let (Some(timings), Some(started)) = (&mut self.timings, started) {
+            timings.network_wait += started.elapsed();
+        }
+        Ok((prefix, length))
+    }
+
+    /// Parses the message last read by [`MessageReader::read_frame`].
+    ///
+    /// `CopyData` and `CopyDone` are only recognized between a copy response
+    /// and the end of the COPY, so a stray `'c'` or `'d'` can't be mistaken
+    /// for one; the `CommandComplete` and `ReadyForQuery` after `CopyDone`
+    /// then parse as usual.
+    pub fn parse_frame(
+        &mut self,
+        prefix: char,
+        length: u32,
+    ) -> Result<BackendMessage, ReadMessageError> {
+        let started = self.timings.is_some().then(Instant::now);
+        let message = parse_message(prefix, length, &self.buffer, self.max_columns, self.in_copy);
+        if let (Some(timings), Some(started)) = (&mut self.timings, started) {
+            timings.parse_time += started.elapsed();
+        }
+        let message = message?;
+        match message {
+            BackendMessage::CopyInResponse { .. }
+            | BackendMessage::CopyOutResponse { .. }
+            | BackendMessage::CopyBothResponse { .. } => self.in_copy = true,
+            BackendMessage::CopyDone
+            | BackendMessage::ErrorResponse(_)
+            | BackendMessage::ReadyForQuery { .. } => self.in_copy = false,
+            _ => {}
+        }
+        if self.strict {
+            if let BackendMessage::Unknown { prefix, .. } = message {
+                return Err(ReadMessageError::UnexpectedMessage { prefix });
+            }
+        }
+
+        Ok(message)
+    }
 }
 
-pub fn read_message(reader: &mut dyn Read) -> Result<BackendMessage, ReadMessageError> {
-    let mut prefix = [0u8; 1];
-    reader.read_exact(&mut prefix)?;
-    let prefix = char::from(prefix[0]);
+/// The prefix and length of a message header, or `None` when it's out of
+/// frame: then the header is likely part of a previous message body.
+fn frame_header(header: &[u8; 5]) -> Option<(char, u32)> {
+    let prefix = char::from(header[0]);
+    let length = u32::from_be_bytes([header[1], header[2], header[3], header[4]]);
+    (prefix.is_ascii_graphic() && (4..=MAX_MESSAGE_LENGTH).contains(&length))
+        .then_some((prefix, length))
+}
 
-    let mut length = [0u8; 4];
-    reader.read_exact(&mut length)?;
-    let length = u32::from_be_bytes(length);
+/// Splits backend messages out of bytes pushed in as they arrive, for when
+/// something other than a blocking `Read` drives the I/O, such as an event
+/// loop or a proxy.
+///
+/// Partial messages are kept until the rest is fed. Parsing goes through a
+/// [`MessageReader`], so the same rules apply, and an out-of-frame header is
+/// reported as soon as its 5 bytes are in.
+#[derive(Debug, Default)]
+pub struct MessageFramer {
+    reader: MessageReader,
+    buffer: Vec<u8>,
+    /// Where the first message not yet returned starts in `buffer`.
+    start: usize,
+}
 
-    let mut body = vec![0u8; (length - 4).try_into()?];
-    reader.read_exact(&mut body)?;
+impl MessageFramer {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
+    /// The reader messages are parsed with, to configure it.
+    pub fn reader_mut(&mut self) -> &mut MessageReader {
+        &mut self.reader
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        if self.start > 0 {
+            self.buffer.drain(..self.start);
+            self.start = 0;
+        }
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// The next complete message, or `None` until more bytes are fed.
+    pub fn poll(&mut self) -> Option<Result<BackendMessage, ReadMessageError>> {
+        let pending = &self.buffer[self.start..];
+        let header: &[u8; 5] = pending.get(..5)?.try_into().ok()?;
+        let frame_length = match frame_header(header) {
+            Some((_, length)) => 1 + length as usize,
+            // The reader rejects the header without reading any further.
+            None => 5,
+        };
+        let mut frame = pending.get(..frame_length)?;
+        self.start += frame_length;
+        Some(self.reader.read_message(&mut frame))
+    }
+
+    /// Bytes fed but not yet returned as a message.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len() - self.start
+    }
+}
+
+fn parse_message(
+    prefix: char,
+    length: u32,
+    body: &[u8],
+    max_columns: usize,
+    in_copy: bool,
+) -> Result<BackendMessage, ReadMessageError> {
     let message = match (prefix, length, body) {
+        // Every authentication message starts with its 4-byte code.
+        ('R', _, payload) if payload.len() < 4 => return Err(ReadMessageError::ParseError),
         ('R', 8, payload) if payload == [0, 0, 0, 3] => {
             BackendMessage::AuthenticationCleartextPassword
         }
-        ('R', 8, payload) if payload[0..4] == [0, 0, 0, 0] => BackendMessage::AuthenticationOk,
+        // Some proxies pad the body, so only the code is checked.
+        ('R', _, payload) if payload[0..4] == [0, 0, 0, 0] => BackendMessage::AuthenticationOk,
+        ('R', 8, payload) if payload == [0, 0, 0, 7] => BackendMessage::AuthenticationGss,
+        ('R', 8, payload) if payload == [0, 0, 0, 9] => BackendMessage::AuthenticationSspi,
         ('R', _, payload) if payload[0..4] == [0, 0, 0, 10] => {
-            let mechanisms = str::from_utf8(&payload[4..payload.len() - 2])?
+            // The mechanism list ends with an empty name, i.e. two NULs.
+            let list = payload
+                .get(4..)
+                .and_then(|list| list.strip_suffix(&[0, 0]))
+                .ok_or(ReadMessageError::ParseError)?;
+            let mechanisms = str::from_utf8(list)?
                 .split('\0')
                 .map(|s| s.to_string())
                 .collect();
             BackendMessage::AuthenticationSasl { mechanisms }
         }
-        ('E', _, payload) => {
-            let error = str::from_utf8(&payload[..payload.len() - 2])?
-                .split('\0')
-                .map(|s| {
-                    (
-                        if let Some(char) = s.chars().nth(0) {
-                            match char {
-                                'S' => ErrorField::LocalizedSeverity,
-                                'V' => ErrorField::Severity,
-                                'C' => ErrorField::Code,
-                                'M' => ErrorField::Message,
-                                'D' => ErrorField::Detail,
-                                'H' => ErrorField::Hint,
-                                'P' => ErrorField::Position,
-                                'p' => ErrorField::InternalPosition,
-                                'q' => ErrorField::InternalQuery,
-                                'W' => ErrorField::Where,
-                                's' => ErrorField::SchemaName,
-                                't' => ErrorField::TableName,
-                                'c' => ErrorField::ColumnName,
-                                'd' => ErrorField::DataTypeName,
-                                'n' => ErrorField::ConstraintName,
-                                'F' => ErrorField::File,
-                                'L' => ErrorField::Line,
-                                'R' => ErrorField::Routine,
-                                c => ErrorField::Unknown(c),
-                            }
-                        } else {
-                            ErrorField::Unknown('\0')
-                        },
-                        s[1..].to_string(),
-                    )
-                })
-                .collect();
-            BackendMessage::ErrorResponse(error)
+        ('R', _, payload) if payload[0..4] == [0, 0, 0, 11] => {
+            BackendMessage::AuthenticationSaslContinue {
+                data: payload[4..].to_vec(),
+            }
+        }
+        ('R', _, payload) if payload[0..4] == [0, 0, 0, 12] => {
+            BackendMessage::AuthenticationSaslFinal {
+                data: payload[4..].to_vec(),
+            }
         }
+        ('E', _, payload) => BackendMessage::ErrorResponse(parse_fields(payload)?),
+        ('N', _, payload) => BackendMessage::NoticeResponse(parse_fields(payload)?),
         ('K', 12, payload) => {
             let process_id = u32::from_be_bytes(payload[..4].try_into()?);
             let secret_key = i32::from_be_bytes(payload[4..].try_into()?);
@@ -176,6 +840,19 @@ pub fn read_message(reader: &mut dyn Read) -> Result<BackendMessage, ReadMessage
                 secret_key,
             }
         }
+        ('A', _, payload) => {
+            let (process_id, rest) = split_u32(payload)?;
+            let mut strings = rest.split(|&b| b == 0);
+            let mut next = || -> Result<String, ReadMessageError> {
+                let bytes = strings.next().ok_or(ReadMessageError::ParseError)?;
+                Ok(str::from_utf8(bytes)?.to_string())
+            };
+            BackendMessage::NotificationResponse {
+                process_id,
+                channel: next()?,
+                payload: next()?,
+            }
+        }
         ('Z', 5, payload) => {
             let status = match char::from(payload[0]) {
                 'I' => ReadyForQueryStatus::Idle,
@@ -191,23 +868,250 @@ pub fn read_message(reader: &mut dyn Read) -> Result<BackendMessage, ReadMessage
                 .position(|&x| x == 0)
                 .ok_or(ReadMessageError::ParseError)?;
 
+            let value = payload[index + 1..]
+                .split_last()
+                .ok_or(ReadMessageError::ParseError)?
+                .1;
             BackendMessage::ParameterStatus {
                 name: str::from_utf8(&payload[..index])?.to_string(),
-                value: str::from_utf8(&payload[index + 1..payload.len() - 1])?.to_string(),
+                value: str::from_utf8(value)?.to_string(),
+            }
+        }
+        ('v', _, payload) => {
+            let (newest_minor_version, rest) = split_u32(payload)?;
+            let (count, rest) = split_u32(rest)?;
+            let unrecognized_options = rest
+                .split(|&b| b == 0)
+                .take(count as usize)
+                .map(|name| Ok(str::from_utf8(name)?.to_string()))
+                .collect::<Result<_, ReadMessageError>>()?;
+            BackendMessage::NegotiateProtocolVersion {
+                newest_minor_version,
+                unrecognized_options,
+            }
+        }
+        ('1', 4, _) => BackendMessage::ParseComplete,
+        ('2', 4, _) => BackendMessage::BindComplete,
+        ('3', 4, _) => BackendMessage::CloseComplete,
+        ('C', _, payload) => {
+            let (_, tag) = payload.split_last().ok_or(ReadMessageError::ParseError)?;
+            BackendMessage::CommandComplete {
+                tag: str::from_utf8(tag)?.to_string(),
+            }
+        }
+        ('t', _, payload) => {
+            let (count, rest) = split_u16(payload)?;
+            let types = rest
+                .chunks(4)
+                .map(|chunk| Ok(u32::from_be_bytes(chunk.try_into()?)))
+                .collect::<Result<Vec<u32>, ReadMessageError>>()?;
+            if types.len() != usize::from(count) {
+                return Err(ReadMessageError::ParseError);
+            }
+            BackendMessage::ParameterDescription { types }
+        }
+        ('T', _, payload) => {
+            let (count, _) = split_u16(payload)?;
+            if usize::from(count) > max_columns {
+                return Err(ReadMessageError::ParseError);
+            }
+            let mut fields = Vec::with_capacity(count.into());
+            let mut offset = 2;
+            for _ in 0..count {
+                let end = offset
+                    + payload[offset..]
+                        .iter()
+                        .position(|&x| x == 0)
+                        .ok_or(ReadMessageError::ParseError)?;
+                let name = str::from_utf8(&payload[offset..end])?.to_string();
+                let field = payload
+                    .get(end + 1..end + 19)
+                    .ok_or(ReadMessageError::ParseError)?;
+                fields.push(FieldDescription {
+                    name,
+                    table_oid: u32::from_be_bytes(field[0..4].try_into()?),
+                    column_id: i16::from_be_bytes(field[4..6].try_into()?),
+                    type_oid: u32::from_be_bytes(field[6..10].try_into()?),
+                    type_size: i16::from_be_bytes(field[10..12].try_into()?),
+                    type_modifier: i32::from_be_bytes(field[12..16].try_into()?),
+                    format_code: i16::from_be_bytes(field[16..18].try_into()?),
+                });
+                offset = end + 19;
+            }
+            BackendMessage::RowDescription { fields }
+        }
+        ('D', _, payload) => {
+            let (count, _) = split_u16(payload)?;
+            if usize::from(count) > max_columns {
+                return Err(ReadMessageError::ParseError);
+            }
+            let mut columns = Vec::with_capacity(count.into());
+            let mut offset = 2;
+            for _ in 0..count {
+                let length = i32::from_be_bytes(
+                    payload
+                        .get(offset..offset + 4)
+                        .ok_or(ReadMessageError::ParseError)?
+                        .try_into()?,
+                );
+                offset += 4;
+                if length < 0 {
+                    columns.push(None);
+                } else {
+                    let end = offset + usize::try_from(length)?;
+                    columns.push(Some(
+                        payload
+                            .get(offset..end)
+                            .ok_or(ReadMessageError::ParseError)?
+                            .to_vec(),
+                    ));
+                    offset = end;
+                }
+            }
+            BackendMessage::DataRow { columns }
+        }
+        ('n', 4, _) => BackendMessage::NoData,
+        ('I', 4, _) => BackendMessage::EmptyQueryResponse,
+        ('s', 4, _) => BackendMessage::PortalSuspended,
+        ('G', _, payload) => {
+            let (format, column_formats) = parse_copy_response(payload)?;
+            BackendMessage::CopyInResponse {
+                format,
+                column_formats,
             }
         }
-        (prefix, _, payload) => BackendMessage::Unknown { prefix, payload },
+        ('H', _, payload) => {
+            let (format, column_formats) = parse_copy_response(payload)?;
+            BackendMessage::CopyOutResponse {
+                format,
+                column_formats,
+            }
+        }
+        ('W', _, payload) => {
+            let (format, column_formats) = parse_copy_response(payload)?;
+            BackendMessage::CopyBothResponse {
+                format,
+                column_formats,
+            }
+        }
+        ('d', _, payload) if in_copy => BackendMessage::CopyData {
+            data: payload.to_vec(),
+        },
+        ('c', 4, _) if in_copy => BackendMessage::CopyDone,
+        ('V', _, payload) => {
+            let (length, rest) = payload
+                .split_first_chunk()
+                .ok_or(ReadMessageError::ParseError)?;
+            let value = if i32::from_be_bytes(*length) < 0 {
+                None
+            } else {
+                Some(rest.to_vec())
+            };
+            BackendMessage::FunctionCallResponse { value }
+        }
+        (prefix, _, payload) => BackendMessage::Unknown {
+            prefix,
+            payload: payload.to_vec(),
+        },
     };
 
-    println!("<-- {:?}", message);
-
     Ok(message)
 }
 
+/// Splits a big-endian `u16` off the front of `payload`.
+fn split_u16(payload: &[u8]) -> Result<(u16, &[u8]), ReadMessageError> {
+    let (bytes, rest) = payload
+        .split_first_chunk()
+        .ok_or(ReadMessageError::ParseError)?;
+    Ok((u16::from_be_bytes(*bytes), rest))
+}
+
+/// Splits a big-endian `u32` off the front of `payload`.
+fn split_u32(payload: &[u8]) -> Result<(u32, &[u8]), ReadMessageError> {
+    let (bytes, rest) = payload
+        .split_first_chunk()
+        .ok_or(ReadMessageError::ParseError)?;
+    Ok((u32::from_be_bytes(*bytes), rest))
+}
+
+/// Parses the overall and per-column formats shared by the copy responses.
+fn parse_copy_response(payload: &[u8]) -> Result<(u8, Vec<u16>), ReadMessageError> {
+    let format = *payload.first().ok_or(ReadMessageError::ParseError)?;
+    let count = u16::from_be_bytes(
+        payload
+            .get(1..3)
+            .ok_or(ReadMessageError::ParseError)?
+            .try_into()?,
+    );
+    let column_formats = payload[3..]
+        .chunks(2)
+        .take(count.into())
+        .map(|chunk| Ok(u16::from_be_bytes(chunk.try_into()?)))
+        .collect::<Result<Vec<u16>, ReadMessageError>>()?;
+    if column_formats.len() != usize::from(count) {
+        return Err(ReadMessageError::ParseError);
+    }
+    Ok((format, column_formats))
+}
+
+/// Parses the fields shared by `ErrorResponse` and `NoticeResponse`.
+fn parse_fields(payload: &[u8]) -> Result<HashMap<ErrorField, String>, ReadMessageError> {
+    if payload == [0] {
+        return Ok(HashMap::new());
+    }
+    // Each field ends with a NUL, and the list with one more.
+    let fields = payload
+        .strip_suffix(&[0, 0])
+        .ok_or(ReadMessageError::ParseError)?;
+    Ok(str::from_utf8(fields)?
+        .split('\0')
+        .map(|s| {
+            (
+                if let Some(char) = s.chars().next() {
+                    match char {
+                        'S' => ErrorField::LocalizedSeverity,
+                        'V' => ErrorField::Severity,
+                        'C' => ErrorField::Code,
+                        'M' => ErrorField::Message,
+                        'D' => ErrorField::Detail,
+                        'H' => ErrorField::Hint,
+                        'P' => ErrorField::Position,
+                        'p' => ErrorField::InternalPosition,
+                        'q' => ErrorField::InternalQuery,
+                        'W' => ErrorField::Where,
+                        's' => ErrorField::SchemaName,
+                        't' => ErrorField::TableName,
+                        'c' => ErrorField::ColumnName,
+                        'd' => ErrorField::DataTypeName,
+                        'n' => ErrorField::ConstraintName,
+                        'F' => ErrorField::File,
+                        'L' => ErrorField::Line,
+                        'R' => ErrorField::Routine,
+                        c => ErrorField::Unknown(c),
+                    }
+                } else {
+                    ErrorField::Unknown('\0')
+                },
+                s.chars().skip(1).collect(),
+            )
+        })
+        .collect())
+}
+
 #[derive(Debug)]
 pub enum ReadMessageError {
     IoError(std::io::Error),
     ParseError,
+    /// A message the parser doesn't know, only raised in strict mode.
+    UnexpectedMessage {
+        prefix: char,
+    },
+    /// A header whose prefix isn't a printable character or whose length is
+    /// out of bounds, meaning the reader has lost track of where messages
+    /// start. Nothing after it can be trusted.
+    Desynchronized {
+        header: [u8; 5],
+    },
 }
 
 impl From<std::io::Error> for ReadMessageError {
@@ -234,10 +1138,454 @@ impl From<TryFromSliceError> for ReadMessageError {
     }
 }
 
+/// Writes `message`, already encoded as `bytes`, logging it to `log` tagged
+/// with `connection_id`.
 pub fn write_message(
     writer: &mut dyn Write,
-    message: FrontendMessage,
+    log: &mut WireLog,
+    connection_id: u64,
+    message: &FrontendMessage,
+    bytes: &[u8],
 ) -> Result<(), std::io::Error> {
-    println!("--> {:?}", message);
-    writer.write_all(&message.to_bytes())
+    log.log(connection_id, "-->", message);
+    writer.write_all(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::frame;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    /// Counts the allocations made by each thread, so tests running in
+    /// parallel don't see each other's.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            ALLOCATIONS.with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            ALLOCATIONS.with(|count| count.set(count.get() + 1));
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations() -> usize {
+        ALLOCATIONS.with(Cell::get)
+    }
+
+    fn parse(prefix: u8, body: &[u8]) -> Result<BackendMessage, ReadMessageError> {
+        MessageReader::new().read_message(&mut &frame(prefix, body)[..])
+    }
+
+    #[test]
+    fn parses_sasl_mechanisms() {
+        let message = parse(b'R', b"\0\0\0\x0aSCRAM-SHA-256\0SCRAM-SHA-256-PLUS\0\0").unwrap();
+        assert!(matches!(
+            message,
+            BackendMessage::AuthenticationSasl { mechanisms }
+                if mechanisms == ["SCRAM-SHA-256", "SCRAM-SHA-256-PLUS"]
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_sasl_mechanisms() {
+        for body in [
+            &b"\0\0\0\x0a"[..],
+            b"\0\0\0\x0a\0",
+            b"\0\0\0\x0aSCRAM-SHA-256",
+        ] {
+            assert!(matches!(
+                parse(b'R', body),
+                Err(ReadMessageError::ParseError)
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_short_bodies_without_panicking() {
+        let cases: &[(u8, &[u8])] = &[
+            (b'C', b""),
+            (b'A', b"\0"),
+            (b'V', b"\0"),
+            (b'v', b"\0"),
+            (b't', b""),
+            (b'E', b"\0S"),
+            (b'N', b""),
+            (b'S', b"name\0"),
+            (b'D', b"\0\x01\0"),
+            (b'T', b"\0"),
+            // Too short for the authentication code.
+            (b'R', b"\0\0"),
+        ];
+        for &(prefix, body) in cases {
+            let mut framer = MessageFramer::new();
+            framer.feed(&frame(prefix, body));
+            assert!(
+                matches!(framer.poll(), Some(Err(ReadMessageError::ParseError))),
+                "{}",
+                char::from(prefix)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_a_column_count_over_the_limit() {
+        // Only the count: rejected before any of the fields is looked for.
+        assert!(matches!(
+            parse(b'T', &[0xff, 0xff]),
+            Err(ReadMessageError::ParseError)
+        ));
+
+        let mut reader = MessageReader::new();
+        reader.set_max_columns(1);
+        let row = frame(b'D', b"\0\x02\0\0\0\x011\0\0\0\x012");
+        assert!(matches!(
+            reader.read_message(&mut &row[..]),
+            Err(ReadMessageError::ParseError)
+        ));
+        reader.set_max_columns(2);
+        assert!(reader.read_message(&mut &row[..]).is_ok());
+    }
+
+    #[test]
+    fn unknown_messages_are_only_errors_in_strict_mode() {
+        let message = frame(b'?', b"payload");
+        let mut reader = MessageReader::new();
+        assert!(matches!(
+            reader.read_message(&mut &message[..]),
+            Ok(BackendMessage::Unknown { prefix: '?', payload }) if payload == b"payload"
+        ));
+        reader.set_strict(true);
+        assert!(matches!(
+            reader.read_message(&mut &message[..]),
+            Err(ReadMessageError::UnexpectedMessage { prefix: '?' })
+        ));
+    }
+
+    #[test]
+    fn a_misaligned_stream_is_desynchronized() {
+        // A reader one byte late lands inside the length of a
+        // `CommandComplete`. A length past the 1 GB limit is no better.
+        let message = frame(b'C', b"SELECT 1\0");
+        for stream in [&message[1..], b"Cxxxxxxx"] {
+            let header: [u8; 5] = stream[..5].try_into().unwrap();
+            assert!(matches!(
+                MessageReader::new().read_message(&mut &stream[..]),
+                Err(ReadMessageError::Desynchronized { header: read }) if read == header
+            ));
+        }
+    }
+
+    #[test]
+    fn names_messages_by_prefix() {
+        assert_eq!(message_name('Z'), Some("ReadyForQuery"));
+        assert_eq!(message_name('T'), Some("RowDescription"));
+        assert_eq!(message_name('W'), Some("CopyBothResponse"));
+        assert_eq!(message_name('?'), None);
+
+        // `CopyData` outside of a copy is left unparsed, but still named.
+        let message = parse(b'd', b"data").unwrap();
+        assert_eq!(message.likely_name(), Some("CopyData"));
+        let message = parse(b'?', b"").unwrap();
+        assert_eq!(message.likely_name(), None);
+        assert_eq!(BackendMessage::NoData.likely_name(), None);
+    }
+
+    #[test]
+    fn startup_message_round_trips() {
+        let message = FrontendMessage::StartupMessage {
+            user: "postgres".to_string(),
+            database: "bench".to_string(),
+            minor_version: 2,
+            parameters: vec![
+                ("application_name".to_string(), "toy".to_string()),
+                ("client_encoding".to_string(), "UTF8".to_string()),
+            ],
+        };
+        let bytes = message.to_bytes().unwrap();
+        let parsed = FrontendMessage::parse_startup(&bytes[4..]).unwrap();
+        assert_eq!(parsed.to_bytes().unwrap(), bytes);
+        assert!(matches!(
+            parsed,
+            FrontendMessage::StartupMessage { user, database, minor_version: 2, parameters }
+                if user == "postgres" && database == "bench" && parameters.len() == 2
+        ));
+
+        // An encryption request carries a magic number, not version 3.
+        let request = FrontendMessage::RequestSSL.to_bytes().unwrap();
+        assert!(FrontendMessage::parse_startup(&request[4..]).is_err());
+    }
+
+    #[test]
+    fn truncated_messages_never_panic() {
+        let messages: &[(u8, &[u8])] = &[
+            (b'C', b"INSERT 0 1\0"),
+            (b'A', b"\0\0\0\x07channel\0payload\0"),
+            (b'V', b"\0\0\0\x02hi"),
+            (b'v', b"\0\0\0\0\0\0\0\x01_pq_.opt\0"),
+            (b't', b"\0\x01\0\0\0\x17"),
+            (b'E', b"SERROR\0C42601\0Msyntax error\0\0"),
+            (b'S', b"TimeZone\0UTC\0"),
+            (b'D', b"\0\x02\0\0\0\x011\xff\xff\xff\xff"),
+            (
+                b'T',
+                b"\0\x01id\0\0\0\0\0\0\0\0\0\0\x17\0\x04\xff\xff\xff\xff\0\0",
+            ),
+            (b'G', b"\0\0\x01\0\0"),
+            (b'R', b"\0\0\0\x0aSCRAM-SHA-256\0\0"),
+        ];
+        for &(prefix, body) in messages {
+            assert!(parse(prefix, body).is_ok(), "{}", char::from(prefix));
+            for end in 0..body.len() {
+                let _ = parse(prefix, &body[..end]);
+            }
+        }
+    }
+
+    #[test]
+    fn framer_reassembles_split_messages() {
+        let mut bytes = frame(b'C', b"SELECT 1\0");
+        bytes.extend(frame(b'Z', b"I"));
+        let mut framer = MessageFramer::new();
+        for chunk in bytes.chunks(3) {
+            framer.feed(chunk);
+        }
+        assert!(matches!(
+            framer.poll(),
+            Some(Ok(BackendMessage::CommandComplete { tag })) if tag == "SELECT 1"
+        ));
+        assert!(matches!(
+            framer.poll(),
+            Some(Ok(BackendMessage::ReadyForQuery {
+                status: ReadyForQueryStatus::Idle
+            }))
+        ));
+        assert!(framer.poll().is_none());
+        assert_eq!(framer.buffered(), 0);
+    }
+
+    #[test]
+    fn reading_in_steady_state_does_not_allocate() {
+        let mut bytes = vec![];
+        for _ in 0..100 {
+            bytes.extend(frame(b'2', b""));
+            bytes.extend(frame(b'Z', b"I"));
+        }
+        let mut reader = MessageReader::with_capacity(64);
+        let mut stream = &bytes[..];
+
+        let before = allocations();
+        while !stream.is_empty() {
+            reader.read_message(&mut stream).unwrap();
+        }
+        assert_eq!(allocations() - before, 0);
+
+        // A message that keeps data still allocates for it.
+        reader
+            .read_message(&mut &frame(b'C', b"SELECT 1\0")[..])
+            .unwrap();
+        assert!(allocations() > before);
+    }
+
+    #[test]
+    fn reused_buffer_parses_like_a_fresh_one() {
+        let messages = [
+            frame(b'D', b"\0\x01\0\0\0\x0bhello world"),
+            frame(b'C', b"SELECT 1\0"),
+            frame(b'D', b"\0\x02\0\0\0\x011\xff\xff\xff\xff"),
+            frame(b'S', b"application_name\0toy-driver\0"),
+            frame(b'Z', b"T"),
+        ];
+        let mut reused = MessageReader::new();
+        for bytes in messages.iter().chain(messages.iter().rev()) {
+            let expected = MessageReader::new().read_message(&mut &bytes[..]).unwrap();
+            let parsed = reused.read_message(&mut &bytes[..]).unwrap();
+            assert_eq!(format!("{parsed:?}"), format!("{expected:?}"));
+        }
+    }
+
+    #[test]
+    fn unnamed_portal_and_statement_encode_as_empty_strings() {
+        let bind = FrontendMessage::Bind {
+            portal: UNNAMED.to_string(),
+            statement: UNNAMED.to_string(),
+            param_formats: vec![],
+            params: vec![Some(b"42".to_vec())],
+            result_formats: vec![],
+        };
+        let expected = [
+            &b"B"[..],
+            &18u32.to_be_bytes(),
+            b"\0\0",
+            &0u16.to_be_bytes(),
+            &1u16.to_be_bytes(),
+            &2i32.to_be_bytes(),
+            b"42",
+            &0u16.to_be_bytes(),
+        ]
+        .concat();
+        assert_eq!(bind.to_bytes().unwrap(), expected);
+
+        let execute = FrontendMessage::Execute {
+            portal: UNNAMED.to_string(),
+            max_rows: 0,
+        };
+        assert_eq!(execute.to_bytes().unwrap(), b"E\0\0\0\x09\0\0\0\0\0");
+    }
+
+    #[test]
+    fn unnamed_statement_encodes_as_an_empty_string() {
+        let parse = FrontendMessage::Parse {
+            name: UNNAMED.to_string(),
+            query: "SELECT 1".to_string(),
+            param_types: vec![],
+        };
+        assert_eq!(parse.to_bytes().unwrap(), b"P\0\0\0\x10\0SELECT 1\0\0\0");
+
+        for (target, code) in [(Target::Statement, b'S'), (Target::Portal, b'P')] {
+            let describe = FrontendMessage::Describe {
+                target,
+                name: UNNAMED.to_string(),
+            };
+            assert_eq!(describe.to_bytes().unwrap(), [b'D', 0, 0, 0, 6, code, 0]);
+            let close = FrontendMessage::Close {
+                target,
+                name: UNNAMED.to_string(),
+            };
+            assert_eq!(close.to_bytes().unwrap(), [b'C', 0, 0, 0, 6, code, 0]);
+        }
+    }
+
+    #[test]
+    fn encryption_requests_are_eight_bytes() {
+        assert_eq!(
+            FrontendMessage::RequestSSL.to_bytes().unwrap(),
+            [0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x2f]
+        );
+        assert_eq!(
+            FrontendMessage::RequestGssEncryption.to_bytes().unwrap(),
+            [0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x30]
+        );
+    }
+
+    /// One of every frontend message.
+    fn every_frontend_message() -> Vec<FrontendMessage> {
+        vec![
+            FrontendMessage::RequestSSL,
+            FrontendMessage::RequestGssEncryption,
+            FrontendMessage::CancelRequest {
+                process_id: 42,
+                secret_key: 7,
+            },
+            FrontendMessage::StartupMessage {
+                user: "postgres".to_string(),
+                database: "postgres".to_string(),
+                minor_version: 0,
+                parameters: vec![("application_name".to_string(), "bench".to_string())],
+            },
+            FrontendMessage::PasswordMessage {
+                password: "pencil".to_string(),
+            },
+            FrontendMessage::SaslInitialResponse {
+                mechanism: "SCRAM-SHA-256".to_string(),
+                data: b"n,,n=,r=nonce".to_vec(),
+            },
+            FrontendMessage::SaslResponse {
+                data: b"c=biws".to_vec(),
+            },
+            FrontendMessage::SimpleQuery {
+                query: "SELECT 1".to_string(),
+            },
+            FrontendMessage::Parse {
+                name: "s1".to_string(),
+                query: "SELECT $1".to_string(),
+                param_types: vec![23],
+            },
+            FrontendMessage::Bind {
+                portal: UNNAMED.to_string(),
+                statement: "s1".to_string(),
+                param_formats: vec![Format::Binary],
+                params: vec![Some(vec![0, 0, 0, 1]), None],
+                result_formats: vec![Format::Text],
+            },
+            FrontendMessage::Execute {
+                portal: UNNAMED.to_string(),
+                max_rows: 10,
+            },
+            FrontendMessage::Close {
+                target: Target::Statement,
+                name: "s1".to_string(),
+            },
+            FrontendMessage::Describe {
+                target: Target::Portal,
+                name: UNNAMED.to_string(),
+            },
+            FrontendMessage::CopyData {
+                data: b"1,a\n".to_vec(),
+            },
+            FrontendMessage::CopyDone,
+            FrontendMessage::CopyFail {
+                message: "stop".to_string(),
+            },
+            FrontendMessage::FunctionCall {
+                oid: 954,
+                args: vec![Some(vec![0, 0, 0, 0]), None],
+            },
+            FrontendMessage::Flush,
+            FrontendMessage::Sync,
+            FrontendMessage::Terminate,
+        ]
+    }
+
+    #[test]
+    fn length_field_stops_at_u32_max() {
+        // The lengths alone, as a 4 GiB message is too much to build here.
+        assert_eq!(length_field(u32::MAX as usize), Ok(u32::MAX));
+        assert_eq!(
+            length_field(u32::MAX as usize + 1),
+            Err(MessageTooLarge {
+                length: u32::MAX as usize + 1
+            })
+        );
+    }
+
+    #[test]
+    fn write_to_matches_to_bytes() {
+        let mut buf = b"earlier".to_vec();
+        for message in every_frontend_message() {
+            let expected = message.to_bytes().unwrap();
+            let start = buf.len();
+            message.write_to(&mut buf).unwrap();
+            assert_eq!(buf[start..], expected, "{:?}", message);
+
+            // The length covers everything but the type byte, which the
+            // startup-phase messages don't have.
+            let prefix = usize::from(!matches!(
+                message,
+                FrontendMessage::RequestSSL
+                    | FrontendMessage::RequestGssEncryption
+                    | FrontendMessage::CancelRequest { .. }
+                    | FrontendMessage::StartupMessage { .. }
+            ));
+            let length = u32::from_be_bytes(expected[prefix..prefix + 4].try_into().unwrap());
+            assert_eq!(length as usize, expected.len() - prefix, "{:?}", message);
+        }
+        assert!(buf.starts_with(b"earlier"));
+    }
 }
@@ -0,0 +1,278 @@
+//! A scripted stand-in for a PostgreSQL server, for tests to drive a
+//! [`Connection`] through the real protocol over a local socket.
+
+use crate::config::*;
+use crate::connection::*;
+use crate::message::*;
+
+use std::io::prelude::*;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A server accepting a single connection on a local port and running a
+/// script against it on its own thread.
+pub struct MockServer {
+    addr: SocketAddr,
+    handle: JoinHandle<()>,
+}
+
+impl MockServer {
+    pub fn start(script: impl FnOnce(&mut MockSession) + Send + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            script(&mut MockSession::new(stream));
+        });
+        Self { addr, handle }
+    }
+
+    /// A server for one session and the `CancelRequest` sent against it.
+    /// `script` is handed a function that waits for the request's connection
+    /// and checks it carries the backend key [`MockSession::accept`] sends.
+    pub fn start_cancellable(
+        script: impl FnOnce(&mut MockSession, &dyn Fn()) + Send + 'static,
+    ) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let expect_cancel = || {
+                let (stream, _) = listener.accept().unwrap();
+                let request = FrontendMessage::CancelRequest {
+                    process_id: 42,
+                    secret_key: 7,
+                }
+                .to_bytes()
+                .unwrap();
+                assert_eq!(MockSession::new(stream).read_packet(), request[4..]);
+            };
+            script(&mut MockSession::new(stream), &expect_cancel);
+        });
+        Self { addr, handle }
+    }
+
+    /// A server accepting `connections` connections, each running `script`
+    /// on its own thread.
+    pub fn start_many(
+        connections: usize,
+        script: impl Fn(&mut MockSession) + Send + Sync + 'static,
+    ) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let script = &script;
+            thread::scope(|scope| {
+                for _ in 0..connections {
+                    let (stream, _) = listener.accept().unwrap();
+                    scope.spawn(move || script(&mut MockSession::new(stream)));
+                }
+            });
+        });
+        Self { addr, handle }
+    }
+
+    /// A plaintext configuration pointing at the server.
+    pub fn config(&self) -> Config {
+        Config {
+            host: self.addr.ip().to_string(),
+            port: self.addr.port(),
+            user: "postgres".to_string(),
+            database: "postgres".to_string(),
+            password: "pencil".to_string(),
+            connect_timeout: None,
+            application_name: None,
+            fallback_application_name: None,
+            client_encoding: "UTF8".to_string(),
+            statement_prefix: DEFAULT_STATEMENT_PREFIX.to_string(),
+            query: "SELECT 1".to_string(),
+            iterations: 1,
+            query_mode: QueryMode::default(),
+            ssl_mode: SslMode::Disable,
+            negotiation_order: NegotiationOrder::default(),
+            ssl_sni_host: None,
+            channel_binding: ChannelBinding::default(),
+            initial_buffer_size: DEFAULT_BUFFER_SIZE,
+            receive_buffer_size: None,
+            replication: None,
+            protocol_minor_version: 0,
+            protocol_extensions: vec![],
+        }
+    }
+
+    pub fn connect(&self) -> Connection {
+        Connection::connect(&self.config()).unwrap()
+    }
+
+    /// Waits for the script to end, failing the test if it panicked.
+    pub fn finish(self) {
+        if let Err(panic) = self.handle.join() {
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+/// The server's end of the connection.
+pub struct MockSession {
+    stream: TcpStream,
+}
+
+impl MockSession {
+    /// Writes go out right away, so the pauses a script makes between them
+    /// are the ones the client sees.
+    fn new(stream: TcpStream) -> Self {
+        stream.set_nodelay(true).unwrap();
+        Self { stream }
+    }
+
+    /// Reads the startup message and lets the client in without a password.
+    pub fn accept(&mut self) -> FrontendMessage {
+        let startup = self.read_startup();
+        self.admit();
+        startup
+    }
+
+    /// Lets the client in without a password, its startup message read.
+    pub fn admit(&mut self) {
+        self.send(&frame(b'R', &0u32.to_be_bytes()));
+        self.send(&frame(b'K', &[0, 0, 0, 42, 0, 0, 0, 7]));
+        self.send(&ready(b'I'));
+    }
+
+    pub fn read_startup(&mut self) -> FrontendMessage {
+        FrontendMessage::parse_startup(&self.read_packet()).unwrap()
+    }
+
+    /// The body of the next message without a prefix, as sent before
+    /// startup: a startup message, or a request for encryption or a cancel.
+    pub fn read_packet(&mut self) -> Vec<u8> {
+        let mut length = [0u8; 4];
+        self.stream.read_exact(&mut length).unwrap();
+        let mut body = vec![0u8; u32::from_be_bytes(length) as usize - 4];
+        self.stream.read_exact(&mut body).unwrap();
+        body
+    }
+
+    /// The next `length` bytes, unframed, such as a TLS record.
+    #[cfg(feature = "tls")]
+    pub fn read_bytes(&mut self, length: usize) -> Vec<u8> {
+        let mut bytes = vec![0u8; length];
+        self.stream.read_exact(&mut bytes).unwrap();
+        bytes
+    }
+
+    /// The next message from the client, as its prefix and body.
+    pub fn receive(&mut self) -> (u8, Vec<u8>) {
+        let mut header = [0u8; 5];
+        self.stream.read_exact(&mut header).unwrap();
+        let length = u32::from_be_bytes(header[1..].try_into().unwrap());
+        let mut body = vec![0u8; length as usize - 4];
+        self.stream.read_exact(&mut body).unwrap();
+        (header[0], body)
+    }
+
+    /// Whether the client sent anything not read yet, waiting up to `wait`
+    /// for it to arrive.
+    pub fn has_pending(&mut self, wait: Duration) -> bool {
+        self.stream.set_read_timeout(Some(wait)).unwrap();
+        let pending = self.stream.peek(&mut [0]).is_ok();
+        self.stream.set_read_timeout(None).unwrap();
+        pending
+    }
+
+    /// The body of the next message, which must have `prefix`.
+    pub fn expect(&mut self, prefix: u8) -> Vec<u8> {
+        let (received, body) = self.receive();
+        assert_eq!(
+            char::from(received),
+            char::from(prefix),
+            "unexpected message {:?}",
+            body
+        );
+        body
+    }
+
+    /// The text of the next `SimpleQuery`.
+    pub fn expect_query(&mut self) -> String {
+        let body = self.expect(b'Q');
+        String::from_utf8(body[..body.len() - 1].to_vec()).unwrap()
+    }
+
+    pub fn send(&mut self, bytes: &[u8]) {
+        self.stream.write_all(bytes).unwrap();
+    }
+
+    /// Answers a simple query that returns no rows with `tag`.
+    pub fn complete(&mut self, tag: &str, status: u8) {
+        let mut bytes = command_complete(tag);
+        bytes.extend(ready(status));
+        self.send(&bytes);
+    }
+}
+
+/// A backend message with `prefix` and `body`.
+pub fn frame(prefix: u8, body: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![prefix];
+    bytes.extend_from_slice(&(body.len() as u32 + 4).to_be_bytes());
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+pub fn ready(status: u8) -> Vec<u8> {
+    frame(b'Z', &[status])
+}
+
+pub fn command_complete(tag: &str) -> Vec<u8> {
+    frame(b'C', format!("{}\0", tag).as_bytes())
+}
+
+/// An `ErrorResponse` with the SQLSTATE `code` and `message`.
+pub fn error_response(code: &str, message: &str) -> Vec<u8> {
+    frame(
+        b'E',
+        format!("SERROR\0VERROR\0C{}\0M{}\0\0", code, message).as_bytes(),
+    )
+}
+
+/// A `RowDescription` of text columns, each a name and type OID.
+pub fn row_description(columns: &[(&str, u32)]) -> Vec<u8> {
+    let mut body = (columns.len() as u16).to_be_bytes().to_vec();
+    for (name, type_oid) in columns {
+        body.extend_from_slice(name.as_bytes());
+        body.push(0);
+        body.extend_from_slice(&[0; 6]);
+        body.extend_from_slice(&type_oid.to_be_bytes());
+        body.extend_from_slice(&(-1i16).to_be_bytes());
+        body.extend_from_slice(&(-1i32).to_be_bytes());
+        body.extend_from_slice(&0i16.to_be_bytes());
+    }
+    frame(b'T', &body)
+}
+
+pub fn data_row(values: &[Option<&str>]) -> Vec<u8> {
+    let mut body = (values.len() as u16).to_be_bytes().to_vec();
+    for value in values {
+        match value {
+            Some(value) => {
+                body.extend_from_slice(&(value.len() as i32).to_be_bytes());
+                body.extend_from_slice(value.as_bytes());
+            }
+            None => body.extend_from_slice(&(-1i32).to_be_bytes()),
+        }
+    }
+    frame(b'D', &body)
+}
+
+/// A `FunctionCallResponse` returning `value`.
+pub fn function_result(value: Option<&[u8]>) -> Vec<u8> {
+    let mut body = vec![];
+    match value {
+        Some(value) => {
+            body.extend_from_slice(&(value.len() as i32).to_be_bytes());
+            body.extend_from_slice(value);
+        }
+        None => body.extend_from_slice(&(-1i32).to_be_bytes()),
+    }
+    frame(b'V', &body)
+}
@@ -0,0 +1,160 @@
+use crate::connection::*;
+use crate::message::*;
+use crate::sql::*;
+
+use std::time::{Duration, Instant};
+
+/// A `NOTIFY` delivered on a channel this connection is listening on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    /// The backend that sent it, which is this one for a self-notify.
+    pub process_id: u32,
+    pub channel: String,
+    pub payload: String,
+}
+
+impl Connection {
+    pub fn listen(&mut self, channel: &str) -> Result<(), RuntimeError> {
+        self.execute(&format!("LISTEN {}", quote_identifier(channel)))?;
+        Ok(())
+    }
+
+    pub fn notify(&mut self, channel: &str, payload: &str) -> Result<(), RuntimeError> {
+        self.execute(&format!(
+            "NOTIFY {}, {}",
+            quote_identifier(channel),
+            quote_literal(payload)
+        ))?;
+        Ok(())
+    }
+
+    /// Blocks until at least one notification arrives, then returns it along
+    /// with any others already readable. Unlike
+    /// [`Connection::poll_notification`], no read timeout is set: this sleeps
+    /// in a plain blocking read on the socket.
+    ///
+    /// Other messages arriving meanwhile, such as notices or a stray
+    /// `ReadyForQuery`, are consumed and otherwise ignored.
+    pub fn wait_notification(&mut self) -> Result<Vec<Notification>, RuntimeError> {
+        let mut notifications: Vec<Notification> = self.notifications.drain(..).collect();
+        loop {
+            let readable = notifications.is_empty() || self.wait_readable(Duration::ZERO)?;
+            if !readable {
+                return Ok(notifications);
+            }
+            if let BackendMessage::NotificationResponse {
+                process_id,
+                channel,
+                payload,
+            } = self.receive_unfiltered()?
+            {
+                notifications.push(Notification {
+                    process_id,
+                    channel,
+                    payload,
+                });
+            }
+        }
+    }
+
+    /// The next notification, waiting up to `timeout` for one to arrive.
+    ///
+    /// Notifications that came in while other queries ran are queued and
+    /// returned first.
+    pub fn poll_notification(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<Notification>, RuntimeError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(notification) = self.notifications.pop_front() {
+                return Ok(Some(notification));
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if !self.wait_readable(remaining)? {
+                return Ok(None);
+            }
+            // Anything else that shows up outside a query, like a notice or a
+            // parameter change, is skipped.
+            if let BackendMessage::NotificationResponse {
+                process_id,
+                channel,
+                payload,
+            } = self.receive_unfiltered()?
+            {
+                return Ok(Some(Notification {
+                    process_id,
+                    channel,
+                    payload,
+                }));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::*;
+    use std::thread;
+
+    /// A `NotificationResponse` from the mock's own backend.
+    fn notification(channel: &str, payload: &str) -> Vec<u8> {
+        let mut body = 42u32.to_be_bytes().to_vec();
+        body.extend(format!("{}\0{}\0", channel, payload).as_bytes());
+        frame(b'A', &body)
+    }
+
+    #[test]
+    fn receives_its_own_notification() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            assert_eq!(session.expect_query(), "LISTEN \"jobs\"");
+            session.complete("LISTEN", b'I');
+            assert_eq!(session.expect_query(), "NOTIFY \"jobs\", 'it''s done'");
+            // Delivered as the notifying transaction commits.
+            let mut reply = command_complete("NOTIFY");
+            reply.extend(notification("jobs", "it's done"));
+            reply.extend(ready(b'I'));
+            session.send(&reply);
+        });
+        let mut connection = server.connect();
+
+        connection.listen("jobs").unwrap();
+        connection.notify("jobs", "it's done").unwrap();
+        let received = connection.poll_notification(Duration::ZERO).unwrap();
+        assert_eq!(
+            received,
+            Some(Notification {
+                process_id: connection.backend_key.unwrap().0,
+                channel: "jobs".to_string(),
+                payload: "it's done".to_string(),
+            })
+        );
+        server.finish();
+    }
+
+    #[test]
+    fn polling_wakes_up_for_a_notification() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            thread::sleep(Duration::from_millis(50));
+            session.send(&notification("jobs", "ready"));
+            session.expect(b'X');
+        });
+        let mut connection = server.connect();
+
+        let started = Instant::now();
+        let received = connection
+            .poll_notification(Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(
+            received.map(|notification| notification.payload).as_deref(),
+            Some("ready")
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+        drop(connection);
+        server.finish();
+    }
+}
@@ -0,0 +1,95 @@
+use crate::connection::*;
+
+/// Callbacks on the lifecycle of a [`Connection`], in the order they fire.
+///
+/// Every method does nothing by default, so implementors only override the
+/// events they care about.
+pub trait ConnectionObserver {
+    /// About to open the TCP connection to `addr`.
+    fn on_connect_start(&self, _addr: &str) {}
+
+    /// The TCP connection is established.
+    fn on_tcp_connected(&self) {}
+
+    /// The socket was upgraded to TLS.
+    fn on_tls_upgrade(&self) {}
+
+    /// The server accepted the credentials.
+    fn on_authenticated(&self) {}
+
+    /// Connecting failed; no connection is returned.
+    fn on_error(&self, _error: &RuntimeError) {}
+
+    /// The connection was closed or dropped.
+    fn on_close(&self) {}
+}
+
+/// The observer used when none is given.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopObserver;
+
+impl ConnectionObserver for NoopObserver {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Writes down every event as it fires.
+    #[derive(Default)]
+    struct Recorder {
+        events: RefCell<Vec<String>>,
+    }
+
+    impl ConnectionObserver for Recorder {
+        fn on_connect_start(&self, addr: &str) {
+            self.events.borrow_mut().push(format!("connect {}", addr));
+        }
+
+        fn on_tcp_connected(&self) {
+            self.events.borrow_mut().push("tcp".to_string());
+        }
+
+        fn on_tls_upgrade(&self) {
+            self.events.borrow_mut().push("tls".to_string());
+        }
+
+        fn on_authenticated(&self) {
+            self.events.borrow_mut().push("authenticated".to_string());
+        }
+
+        fn on_error(&self, error: &RuntimeError) {
+            self.events.borrow_mut().push(format!("error {:?}", error));
+        }
+
+        fn on_close(&self) {
+            self.events.borrow_mut().push("close".to_string());
+        }
+    }
+
+    #[test]
+    fn reports_a_successful_handshake_in_order() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            session.expect(b'X');
+        });
+        let config = server.config();
+        let recorder = Rc::new(Recorder::default());
+
+        let connection = Connection::connect_with_observer(&config, recorder.clone()).unwrap();
+        drop(connection);
+        server.finish();
+
+        assert_eq!(
+            *recorder.events.borrow(),
+            [
+                format!("connect {}:{}", config.host, config.port),
+                "tcp".to_string(),
+                "authenticated".to_string(),
+                "close".to_string(),
+            ]
+        );
+    }
+}
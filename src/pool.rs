@@ -0,0 +1,138 @@
+use crate::config::*;
+use crate::connection::*;
+
+/// When a [`Pool`] may replace a connection that died.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// How many times to try connecting before giving up.
+    pub max_attempts: u32,
+    /// Whether to keep trying after the server rejects the credentials, which
+    /// usually just fails the same way again.
+    pub retry_auth_errors: bool,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            retry_auth_errors: false,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Whether another attempt is worth making after `error`.
+    pub fn should_retry(&self, error: &RuntimeError) -> bool {
+        match error {
+            RuntimeError::IoError(_) => true,
+            RuntimeError::AuthenticationFailed => self.retry_auth_errors,
+            // Class 28: invalid authorization specification.
+            RuntimeError::PgError(error) if error.code().is_some_and(|c| c.starts_with("28")) => {
+                self.retry_auth_errors
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A fixed set of connections handed out in turn.
+pub struct Pool {
+    config: Config,
+    connections: Vec<Connection>,
+    next: usize,
+    reconnect: Option<ReconnectPolicy>,
+}
+
+impl Pool {
+    /// Opens `size` connections up front.
+    pub fn new(config: Config, size: usize) -> Result<Self, RuntimeError> {
+        let connections = (0..size.max(1))
+            .map(|_| Connection::connect(&config))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            config,
+            connections,
+            next: 0,
+            reconnect: None,
+        })
+    }
+
+    /// Replaces broken connections on [`Pool::get`] instead of handing them
+    /// out to fail on their next use.
+    pub fn with_reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Some(policy);
+        self
+    }
+
+    pub fn size(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// The next connection in turn, drained of any replies still pending, and
+    /// reopened first if it's broken and the pool has a [`ReconnectPolicy`].
+    pub fn get(&mut self) -> Result<&mut Connection, RuntimeError> {
+        let index = self.next;
+        self.next = (self.next + 1) % self.connections.len();
+
+        let connection = &mut self.connections[index];
+        if !connection.is_broken() {
+            // A connection handed back mid-query still has replies in flight.
+            if let Err(error) = connection.drain_to_ready() {
+                if self.reconnect.is_none() || !connection.is_broken() {
+                    return Err(error);
+                }
+            }
+        }
+
+        if let Some(policy) = self.reconnect {
+            if self.connections[index].is_broken() {
+                self.connections[index] = self.reconnect(&policy)?;
+            }
+        }
+
+        Ok(&mut self.connections[index])
+    }
+
+    fn reconnect(&self, policy: &ReconnectPolicy) -> Result<Connection, RuntimeError> {
+        let mut attempt = 1;
+        loop {
+            match Connection::connect(&self.config) {
+                Ok(connection) => return Ok(connection),
+                Err(error) if attempt < policy.max_attempts && policy.should_retry(&error) => {
+                    attempt += 1;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::ErrorField;
+
+    fn pg_error(code: &str) -> RuntimeError {
+        RuntimeError::PgError(PgError {
+            fields: [(ErrorField::Code, code.to_string())].into(),
+        })
+    }
+
+    #[test]
+    fn retries_auth_errors_only_when_asked_to() {
+        let policy = ReconnectPolicy::default();
+        let io_error = RuntimeError::IoError(std::io::ErrorKind::ConnectionRefused.into());
+        assert!(policy.should_retry(&io_error));
+        assert!(!policy.should_retry(&RuntimeError::AuthenticationFailed));
+        assert!(!policy.should_retry(&pg_error("28P01")));
+        assert!(!policy.should_retry(&pg_error("42601")));
+
+        let lenient = ReconnectPolicy {
+            retry_auth_errors: true,
+            ..policy
+        };
+        assert!(lenient.should_retry(&RuntimeError::AuthenticationFailed));
+        assert!(lenient.should_retry(&pg_error("28P01")));
+        assert!(!lenient.should_retry(&pg_error("42601")));
+    }
+}
@@ -0,0 +1,281 @@
+use crate::connection::*;
+use crate::message::*;
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Seconds between the Unix epoch and PostgreSQL's, 2000-01-01.
+const POSTGRES_EPOCH: Duration = Duration::from_secs(946_684_800);
+
+/// A message from the server inside a replication `CopyData`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplicationMessage {
+    /// A chunk of WAL, starting at `wal_start`.
+    XLogData {
+        wal_start: u64,
+        wal_end: u64,
+        timestamp: i64,
+        data: Vec<u8>,
+    },
+    /// Sent periodically, and with `reply_requested` when the server wants a
+    /// standby status update right away to keep the connection.
+    PrimaryKeepalive {
+        wal_end: u64,
+        timestamp: i64,
+        reply_requested: bool,
+    },
+}
+
+impl ReplicationMessage {
+    /// Parses a `CopyData` payload, whose first byte is the sub-message type.
+    /// Timestamps are microseconds since 2000-01-01.
+    pub fn parse(data: &[u8]) -> Result<Self, ReadMessageError> {
+        let field = |range: std::ops::Range<usize>| -> Result<[u8; 8], ReadMessageError> {
+            Ok(data
+                .get(range)
+                .ok_or(ReadMessageError::ParseError)?
+                .try_into()?)
+        };
+
+        match data.first() {
+            Some(b'w') => Ok(Self::XLogData {
+                wal_start: u64::from_be_bytes(field(1..9)?),
+                wal_end: u64::from_be_bytes(field(9..17)?),
+                timestamp: i64::from_be_bytes(field(17..25)?),
+                data: data[25..].to_vec(),
+            }),
+            Some(b'k') => Ok(Self::PrimaryKeepalive {
+                wal_end: u64::from_be_bytes(field(1..9)?),
+                timestamp: i64::from_be_bytes(field(9..17)?),
+                reply_requested: *data.get(17).ok_or(ReadMessageError::ParseError)? != 0,
+            }),
+            _ => Err(ReadMessageError::ParseError),
+        }
+    }
+}
+
+/// Encodes the 34-byte `'r'` standby status update: the three LSNs, the
+/// current time and whether the server should reply immediately.
+pub fn standby_status_update(
+    written: u64,
+    flushed: u64,
+    applied: u64,
+    timestamp: i64,
+    reply: bool,
+) -> Vec<u8> {
+    let mut data = Vec::with_capacity(34);
+    data.push(b'r');
+    data.extend_from_slice(&written.to_be_bytes());
+    data.extend_from_slice(&flushed.to_be_bytes());
+    data.extend_from_slice(&applied.to_be_bytes());
+    data.extend_from_slice(&timestamp.to_be_bytes());
+    data.push(reply.into());
+    data
+}
+
+/// The current time in microseconds since 2000-01-01, as replication
+/// messages expect.
+pub fn postgres_timestamp_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH + POSTGRES_EPOCH)
+        .map_or(0, |since| since.as_micros() as i64)
+}
+
+/// A connection streaming in COPY BOTH mode after `START_REPLICATION`.
+///
+/// Each `CopyData` from the server carries a WAL record or a keepalive, and
+/// the client answers with its own `CopyData` status updates.
+pub struct ReplicationStream<'a> {
+    connection: &'a mut Connection,
+    done: bool,
+}
+
+impl<'a> ReplicationStream<'a> {
+    /// The next `CopyData` payload, or `None` once the server ends the stream.
+    pub fn read_copy_data(&mut self) -> Result<Option<Vec<u8>>, RuntimeError> {
+        if self.done {
+            return Ok(None);
+        }
+
+        loop {
+            match self.connection.receive()? {
+                BackendMessage::CopyData { data } => return Ok(Some(data)),
+                BackendMessage::CopyDone => {
+                    self.done = true;
+                    self.connection.send(FrontendMessage::CopyDone)?;
+                    self.connection.read_until_ready()?;
+                    return Ok(None);
+                }
+                BackendMessage::ErrorResponse(fields) => {
+                    self.done = true;
+                    self.connection.read_until_ready()?;
+                    return Err(RuntimeError::PgError(PgError { fields }));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The next WAL record or keepalive, or `None` once the stream ends.
+    pub fn read_message(&mut self) -> Result<Option<ReplicationMessage>, RuntimeError> {
+        match self.read_copy_data()? {
+            Some(data) => Ok(Some(ReplicationMessage::parse(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Sends a `CopyData` message to the server.
+    pub fn send_copy_data(&mut self, data: Vec<u8>) -> Result<(), RuntimeError> {
+        self.connection.send(FrontendMessage::CopyData { data })?;
+        self.connection.flush()
+    }
+
+    /// See [`Connection::send_standby_status`].
+    pub fn send_standby_status(
+        &mut self,
+        written: u64,
+        flushed: u64,
+        applied: u64,
+        reply: bool,
+    ) -> Result<(), RuntimeError> {
+        self.connection
+            .send_standby_status(written, flushed, applied, reply)
+    }
+}
+
+impl Connection {
+    /// Reports the standby's progress to the server, which drops replication
+    /// connections that stay silent past `wal_sender_timeout`.
+    pub fn send_standby_status(
+        &mut self,
+        written: u64,
+        flushed: u64,
+        applied: u64,
+        reply: bool,
+    ) -> Result<(), RuntimeError> {
+        let data =
+            standby_status_update(written, flushed, applied, postgres_timestamp_now(), reply);
+        self.send(FrontendMessage::CopyData { data })?;
+        self.flush()
+    }
+
+    /// Issues a `START_REPLICATION` (or any other command entering COPY BOTH
+    /// mode) and returns a handle over the resulting stream.
+    ///
+    /// The connection must have been opened with the `replication` startup
+    /// parameter set.
+    pub fn start_replication(
+        &mut self,
+        command: &str,
+    ) -> Result<ReplicationStream<'_>, RuntimeError> {
+        self.send_query(command)?;
+
+        loop {
+            match self.receive()? {
+                BackendMessage::CopyBothResponse { .. } => {
+                    return Ok(ReplicationStream {
+                        connection: self,
+                        done: false,
+                    })
+                }
+                BackendMessage::ErrorResponse(fields) => {
+                    self.read_until_ready()?;
+                    return Err(RuntimeError::PgError(PgError { fields }));
+                }
+                BackendMessage::ReadyForQuery { .. } => {
+                    return Err(RuntimeError::ParseMessageError)
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::*;
+
+    #[test]
+    fn start_replication_enters_copy_both_mode() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            assert_eq!(session.expect_query(), "START_REPLICATION 0/0");
+            let mut keepalive = vec![b'k'];
+            keepalive.extend_from_slice(&0x20u64.to_be_bytes());
+            keepalive.extend_from_slice(&30i64.to_be_bytes());
+            keepalive.push(0);
+            let mut reply = frame(b'W', &[0, 0, 0]);
+            reply.extend(frame(b'd', &keepalive));
+            reply.extend(frame(b'c', b""));
+            session.send(&reply);
+            session.expect(b'c');
+            session.complete("START_REPLICATION", b'I');
+        });
+        let mut connection = server.connect();
+
+        let mut stream = connection
+            .start_replication("START_REPLICATION 0/0")
+            .unwrap();
+        assert_eq!(
+            stream.read_message().unwrap(),
+            Some(ReplicationMessage::PrimaryKeepalive {
+                wal_end: 0x20,
+                timestamp: 30,
+                reply_requested: false,
+            })
+        );
+        assert_eq!(stream.read_message().unwrap(), None);
+        assert_eq!(connection.transaction_status(), ReadyForQueryStatus::Idle);
+        server.finish();
+    }
+
+    #[test]
+    fn parses_wal_data_and_keepalives() {
+        let mut xlog = vec![b'w'];
+        for value in [0x10u64, 0x20, 30] {
+            xlog.extend_from_slice(&value.to_be_bytes());
+        }
+        xlog.extend_from_slice(b"wal");
+        assert_eq!(
+            ReplicationMessage::parse(&xlog).unwrap(),
+            ReplicationMessage::XLogData {
+                wal_start: 0x10,
+                wal_end: 0x20,
+                timestamp: 30,
+                data: b"wal".to_vec(),
+            }
+        );
+
+        let mut keepalive = vec![b'k'];
+        keepalive.extend_from_slice(&0x20u64.to_be_bytes());
+        keepalive.extend_from_slice(&30i64.to_be_bytes());
+        keepalive.push(1);
+        assert_eq!(
+            ReplicationMessage::parse(&keepalive).unwrap(),
+            ReplicationMessage::PrimaryKeepalive {
+                wal_end: 0x20,
+                timestamp: 30,
+                reply_requested: true,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_messages() {
+        for data in [&b""[..], b"w", &[b'w'; 24], &[b'k'; 17], b"x"] {
+            assert!(ReplicationMessage::parse(data).is_err());
+        }
+    }
+
+    #[test]
+    fn encodes_a_standby_status_update() {
+        let update = standby_status_update(1, 2, 3, 4, true);
+        assert_eq!(update.len(), 34);
+        assert_eq!(update[0], b'r');
+        assert_eq!(update[1..9], 1u64.to_be_bytes());
+        assert_eq!(update[9..17], 2u64.to_be_bytes());
+        assert_eq!(update[17..25], 3u64.to_be_bytes());
+        assert_eq!(update[25..33], 4i64.to_be_bytes());
+        assert_eq!(update[33], 1);
+    }
+}
@@ -0,0 +1,215 @@
+use crate::message::*;
+use crate::types::*;
+
+use std::str;
+
+/// A single row of a result set, with each column still in its wire format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    columns: Vec<Option<Vec<u8>>>,
+}
+
+impl Row {
+    pub fn new(columns: Vec<Option<Vec<u8>>>) -> Self {
+        Self { columns }
+    }
+
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    /// The raw bytes of a column, or `None` if it is NULL or out of range.
+    pub fn get(&self, index: usize) -> Option<&[u8]> {
+        self.columns.get(index)?.as_deref()
+    }
+
+    /// A text-format column as a string slice.
+    pub fn get_str(&self, index: usize) -> Option<&str> {
+        str::from_utf8(self.get(index)?).ok()
+    }
+
+    /// Decodes a column in the format its `field` says it was sent in, so
+    /// rows mixing text and binary columns decode correctly.
+    pub fn get_typed<T: FromSql>(&self, index: usize, field: &FieldDescription) -> Option<T> {
+        let value = self.get(index)?;
+        match Format::from_code(field.format_code) {
+            Format::Text => T::from_text(str::from_utf8(value).ok()?),
+            Format::Binary => T::from_binary(value),
+        }
+    }
+}
+
+/// The rows returned by a query, along with the columns describing them.
+///
+/// A statement that returns no rows at all, like an `INSERT` without
+/// `RETURNING`, produces an empty set with no fields.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResultSet {
+    pub fields: Vec<FieldDescription>,
+    pub rows: Vec<Row>,
+    pub tag: Option<String>,
+}
+
+impl ResultSet {
+    /// Renders the rows as an aligned table, like psql's default output.
+    ///
+    /// Headers are centered and numeric columns right-aligned. NULLs show as
+    /// `NULL`, and binary or non-UTF-8 values as `\x` hex.
+    pub fn to_table_string(&self) -> String {
+        let headers: Vec<String> = self.fields.iter().map(|f| f.name.clone()).collect();
+        let cells: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| {
+                (0..self.fields.len())
+                    .map(|i| display_value(&self.fields[i], row.get(i)))
+                    .collect()
+            })
+            .collect();
+
+        let widths: Vec<usize> = headers
+            .iter()
+            .enumerate()
+            .map(|(i, header)| {
+                cells
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .chain([header.chars().count()])
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        let mut table = String::new();
+        let line: Vec<String> = headers
+            .iter()
+            .zip(&widths)
+            .map(|(header, &width)| format!(" {:^width$} ", header))
+            .collect();
+        table.push_str(line.join("|").trim_end());
+        table.push('\n');
+        let rule: Vec<String> = widths.iter().map(|width| "-".repeat(width + 2)).collect();
+        table.push_str(&rule.join("+"));
+        table.push('\n');
+
+        for row in &cells {
+            let line: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .zip(&self.fields)
+                .map(|((value, &width), field)| {
+                    if is_numeric(field.type_oid) {
+                        format!(" {:>width$} ", value)
+                    } else {
+                        format!(" {:<width$} ", value)
+                    }
+                })
+                .collect();
+            table.push_str(line.join("|").trim_end());
+            table.push('\n');
+        }
+
+        let count = self.rows.len();
+        table.push_str(&format!(
+            "({} {})\n",
+            count,
+            if count == 1 { "row" } else { "rows" }
+        ));
+        table
+    }
+}
+
+fn display_value(field: &FieldDescription, value: Option<&[u8]>) -> String {
+    match value {
+        None => "NULL".to_string(),
+        Some(bytes) => match str::from_utf8(bytes) {
+            Ok(text) if field.format_code == 0 => text.to_string(),
+            _ => {
+                let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+                format!("\\x{}", hex)
+            }
+        },
+    }
+}
+
+/// int8, int2, int4, oid, float4, float8 and numeric.
+fn is_numeric(type_oid: u32) -> bool {
+    matches!(type_oid, 20 | 21 | 23 | 26 | 700 | 701 | 1700)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str, type_oid: u32, format_code: i16) -> FieldDescription {
+        FieldDescription {
+            name: name.to_string(),
+            table_oid: 0,
+            column_id: 0,
+            type_oid,
+            type_size: -1,
+            type_modifier: -1,
+            format_code,
+        }
+    }
+
+    fn row(values: &[Option<&[u8]>]) -> Row {
+        Row::new(
+            values
+                .iter()
+                .map(|value| value.map(<[u8]>::to_vec))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn renders_an_aligned_table() {
+        let result = ResultSet {
+            fields: vec![field("id", 23, 0), field("name", 25, 0)],
+            rows: vec![
+                row(&[Some(b"1"), Some(b"alice")]),
+                row(&[Some(b"10"), None]),
+            ],
+            tag: Some("SELECT 2".to_string()),
+        };
+        assert_eq!(
+            result.to_table_string(),
+            " id | name\n\
+             ----+-------\n  \
+             1 | alice\n \
+             10 | NULL\n\
+             (2 rows)\n"
+        );
+    }
+
+    #[test]
+    fn shows_binary_values_as_hex() {
+        let result = ResultSet {
+            fields: vec![field("data", 17, 1)],
+            rows: vec![row(&[Some(&[0xde, 0xad])])],
+            tag: None,
+        };
+        assert_eq!(
+            result.to_table_string(),
+            "  data\n\
+             --------\n \
+             \\xdead\n\
+             (1 row)\n"
+        );
+    }
+
+    #[test]
+    fn decodes_each_column_in_its_own_format() {
+        let fields = [field("id", 23, 1), field("total", 23, 0)];
+        let row = row(&[Some(&[0, 0, 1, 0]), Some(b"-42")]);
+        assert_eq!(row.get_typed::<i32>(0, &fields[0]), Some(256));
+        assert_eq!(row.get_typed::<i32>(1, &fields[1]), Some(-42));
+        // Decoded in the wrong format, neither value makes sense.
+        assert_eq!(row.get_typed::<i32>(0, &fields[1]), None);
+        assert_eq!(row.get_typed::<i32>(1, &fields[0]), None);
+    }
+}
@@ -0,0 +1,110 @@
+use crate::connection::*;
+use crate::row::*;
+use crate::sql::*;
+
+/// A column of a table, from [`Connection::describe_table`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnInfo {
+    pub name: String,
+    pub type_oid: u32,
+    /// The type as SQL spells it, with any modifier, such as
+    /// `character varying(20)`.
+    pub type_name: String,
+    pub nullable: bool,
+    /// The default expression, such as `nextval('users_id_seq'::regclass)`.
+    pub default: Option<String>,
+}
+
+impl Connection {
+    /// The columns of `table`, in order, to build statements against it.
+    ///
+    /// `table` is resolved like in SQL, through `regclass`: it may be
+    /// schema-qualified, is looked up along the `search_path` otherwise, and
+    /// needs double quotes around mixed-case names. Dropped and system
+    /// columns are left out.
+    pub fn describe_table(&mut self, table: &str) -> Result<Vec<ColumnInfo>, RuntimeError> {
+        let query = format!(
+            "SELECT a.attname, a.atttypid, format_type(a.atttypid, a.atttypmod), \
+             a.attnotnull, pg_get_expr(d.adbin, d.adrelid) \
+             FROM pg_attribute a \
+             LEFT JOIN pg_attrdef d ON d.adrelid = a.attrelid AND d.adnum = a.attnum \
+             WHERE a.attrelid = {}::regclass AND a.attnum > 0 AND NOT a.attisdropped \
+             ORDER BY a.attnum",
+            quote_literal(table)
+        );
+        self.query(&query)?
+            .rows
+            .iter()
+            .map(|row| parse_column_row(row).ok_or(RuntimeError::ParseMessageError))
+            .collect()
+    }
+}
+
+/// Parses a row of the query [`Connection::describe_table`] runs.
+pub fn parse_column_row(row: &Row) -> Option<ColumnInfo> {
+    Some(ColumnInfo {
+        name: row.get_str(0)?.to_string(),
+        type_oid: row.get_str(1)?.parse().ok()?,
+        type_name: row.get_str(2)?.to_string(),
+        nullable: match row.get_str(3)? {
+            "t" => false,
+            "f" => true,
+            _ => return None,
+        },
+        default: row.get_str(4).map(str::to_string),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(values: &[Option<&str>]) -> Row {
+        Row::new(
+            values
+                .iter()
+                .map(|value| value.map(|value| value.as_bytes().to_vec()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn parses_a_column_row() {
+        let id = row(&[
+            Some("id"),
+            Some("23"),
+            Some("integer"),
+            Some("t"),
+            Some("nextval('users_id_seq'::regclass)"),
+        ]);
+        assert_eq!(
+            parse_column_row(&id),
+            Some(ColumnInfo {
+                name: "id".to_string(),
+                type_oid: 23,
+                type_name: "integer".to_string(),
+                nullable: false,
+                default: Some("nextval('users_id_seq'::regclass)".to_string()),
+            })
+        );
+
+        let name = row(&[
+            Some("name"),
+            Some("1043"),
+            Some("character varying(20)"),
+            Some("f"),
+            None,
+        ]);
+        let name = parse_column_row(&name).unwrap();
+        assert!(name.nullable);
+        assert_eq!(name.default, None);
+    }
+
+    #[test]
+    fn rejects_a_malformed_row() {
+        let bad_oid = row(&[Some("id"), Some("int"), Some("integer"), Some("t"), None]);
+        assert_eq!(parse_column_row(&bad_oid), None);
+        let bad_flag = row(&[Some("id"), Some("23"), Some("integer"), Some("x"), None]);
+        assert_eq!(parse_column_row(&bad_flag), None);
+    }
+}
@@ -0,0 +1,444 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+pub const SCRAM_SHA_256: &str = "SCRAM-SHA-256";
+pub const SCRAM_SHA_256_PLUS: &str = "SCRAM-SHA-256-PLUS";
+
+/// The channel binding data to tie a SCRAM exchange to its TLS session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChannelBindingData {
+    /// Not on TLS, or binding is disabled: plain `SCRAM-SHA-256`.
+    None,
+    /// On TLS, but the server didn't offer `SCRAM-SHA-256-PLUS`. Plain
+    /// `SCRAM-SHA-256` too, but flagged so that a server which does support
+    /// binding can tell its offer was stripped on the way (RFC 5802, section
+    /// 6).
+    NotOffered,
+    /// `tls-server-end-point`, the hash of the server's certificate.
+    TlsServerEndPoint(Vec<u8>),
+}
+
+/// The client side of a SCRAM-SHA-256(-PLUS) exchange (RFC 5802, RFC 7677).
+///
+/// The username is left empty since the server takes it from the startup
+/// packet, and the password isn't SASLprep-normalized, which only matters
+/// for non-ASCII passwords.
+#[derive(Debug)]
+pub struct ScramClient {
+    password: String,
+    channel_binding: ChannelBindingData,
+    nonce: String,
+    client_first_bare: String,
+    server_signature: Option<Vec<u8>>,
+}
+
+impl ScramClient {
+    pub fn new(password: &str, channel_binding: ChannelBindingData) -> Self {
+        Self::with_nonce(password, channel_binding, &generate_nonce())
+    }
+
+    pub fn with_nonce(password: &str, channel_binding: ChannelBindingData, nonce: &str) -> Self {
+        Self {
+            password: password.to_string(),
+            channel_binding,
+            nonce: nonce.to_string(),
+            client_first_bare: format!("n=,r={}", nonce),
+            server_signature: None,
+        }
+    }
+
+    pub fn mechanism(&self) -> &'static str {
+        match self.channel_binding {
+            ChannelBindingData::None | ChannelBindingData::NotOffered => SCRAM_SHA_256,
+            ChannelBindingData::TlsServerEndPoint(_) => SCRAM_SHA_256_PLUS,
+        }
+    }
+
+    /// The GS2 header announcing whether channel binding is in use.
+    pub fn gs2_header(&self) -> &'static str {
+        match self.channel_binding {
+            ChannelBindingData::None => "n,,",
+            ChannelBindingData::NotOffered => "y,,",
+            ChannelBindingData::TlsServerEndPoint(_) => "p=tls-server-end-point,,",
+        }
+    }
+
+    /// The `c=` attribute: the GS2 header followed by the binding data.
+    pub fn channel_binding_attribute(&self) -> String {
+        let mut input = self.gs2_header().as_bytes().to_vec();
+        if let ChannelBindingData::TlsServerEndPoint(hash) = &self.channel_binding {
+            input.extend_from_slice(hash);
+        }
+        format!("c={}", base64_encode(&input))
+    }
+
+    pub fn client_first(&self) -> String {
+        format!("{}{}", self.gs2_header(), self.client_first_bare)
+    }
+
+    /// Answers the server's first message with the client's proof.
+    pub fn client_final(&mut self, server_first: &str) -> Result<String, ScramError> {
+        let mut nonce = None;
+        let mut salt = None;
+        let mut iterations = None;
+        for attribute in server_first.split(',') {
+            match attribute.split_at_checked(2) {
+                Some(("r=", value)) => nonce = Some(value),
+                Some(("s=", value)) => salt = Some(base64_decode(value)?),
+                Some(("i=", value)) => iterations = value.parse::<u32>().ok(),
+                _ => {}
+            }
+        }
+        let (nonce, salt, iterations) = match (nonce, salt, iterations) {
+            (Some(nonce), Some(salt), Some(iterations)) => (nonce, salt, iterations),
+            _ => return Err(ScramError::InvalidServerMessage),
+        };
+
+        if !nonce.starts_with(&self.nonce) {
+            return Err(ScramError::InvalidServerMessage);
+        }
+
+        let without_proof = format!("{},r={}", self.channel_binding_attribute(), nonce);
+        let auth_message = format!(
+            "{},{},{}",
+            self.client_first_bare, server_first, without_proof
+        );
+
+        let salted_password = hi(self.password.as_bytes(), &salt, iterations);
+        let client_key = hmac_sha256(&salted_password, b"Client Key");
+        let stored_key = sha256(&client_key);
+        let client_signature = hmac_sha256(&stored_key, auth_message.as_bytes());
+        let proof: Vec<u8> = client_key
+            .iter()
+            .zip(client_signature.iter())
+            .map(|(key, signature)| key ^ signature)
+            .collect();
+
+        let server_key = hmac_sha256(&salted_password, b"Server Key");
+        self.server_signature = Some(hmac_sha256(&server_key, auth_message.as_bytes()).to_vec());
+
+        Ok(format!("{},p={}", without_proof, base64_encode(&proof)))
+    }
+
+    /// Checks the server's signature, proving it knows the password too.
+    pub fn verify_server_final(&self, server_final: &str) -> Result<(), ScramError> {
+        let signature = server_final
+            .strip_prefix("v=")
+            .ok_or(ScramError::InvalidServerMessage)?;
+        match &self.server_signature {
+            Some(expected) if base64_decode(signature)? == *expected => Ok(()),
+            _ => Err(ScramError::InvalidServerSignature),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScramError {
+    InvalidServerMessage,
+    InvalidServerSignature,
+}
+
+/// 18 bytes from the std hasher's per-process random keys; not a CSPRNG, but
+/// the nonce only has to be unpredictable enough to not repeat.
+fn generate_nonce() -> String {
+    let mut bytes = vec![];
+    for _ in 0..3 {
+        bytes.extend_from_slice(&RandomState::new().build_hasher().finish().to_be_bytes()[..6]);
+    }
+    base64_encode(&bytes)
+}
+
+/// PBKDF2 with HMAC-SHA-256 and a single output block.
+fn hi(password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut input = salt.to_vec();
+    input.extend_from_slice(&1u32.to_be_bytes());
+    let mut previous = hmac_sha256(password, &input);
+    let mut result = previous;
+    for _ in 1..iterations {
+        previous = hmac_sha256(password, &previous);
+        for (byte, next) in result.iter_mut().zip(previous.iter()) {
+            *byte ^= next;
+        }
+    }
+    result
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > 64 {
+        block[..32].copy_from_slice(&sha256(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner: Vec<u8> = block.iter().map(|byte| byte ^ 0x36).collect();
+    inner.extend_from_slice(message);
+    let mut outer: Vec<u8> = block.iter().map(|byte| byte ^ 0x5c).collect();
+    outer.extend_from_slice(&sha256(&inner));
+    sha256(&outer)
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA-256 (FIPS 180-4).
+fn sha256(message: &[u8]) -> [u8; 32] {
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    let mut padded = message.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&(message.len() as u64 * 8).to_be_bytes());
+
+    for chunk in padded.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = hh
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (value, add) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+
+    let mut digest = [0u8; 32];
+    for (i, value) in h.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = (u32::from(chunk[0]) << 16)
+            | (u32::from(*chunk.get(1).unwrap_or(&0)) << 8)
+            | u32::from(*chunk.get(2).unwrap_or(&0));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(char::from(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn base64_decode(encoded: &str) -> Result<Vec<u8>, ScramError> {
+    let mut bytes = vec![];
+    let mut n = 0u32;
+    let mut bits = 0;
+    for c in encoded.bytes().filter(|&c| c != b'=') {
+        let value = BASE64
+            .iter()
+            .position(|&b| b == c)
+            .ok_or(ScramError::InvalidServerMessage)?;
+        n = (n << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((n >> bits) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::AuthMethod;
+    use crate::mock::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn hashes_match_known_vectors() {
+        assert_eq!(
+            hex(&sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // RFC 4231, test case 2.
+        assert_eq!(
+            hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn exchange_matches_rfc_7677() {
+        let nonce = "rOprNGfwEbeRWgbNEkqO";
+        let mut client = ScramClient::with_nonce("pencil", ChannelBindingData::None, nonce);
+        // The example names its user, where the server takes ours from the
+        // startup packet.
+        client.client_first_bare = format!("n=user,r={}", nonce);
+        assert_eq!(client.client_first(), "n,,n=user,r=rOprNGfwEbeRWgbNEkqO");
+
+        let client_final = client
+            .client_final(
+                "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                 s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096",
+            )
+            .unwrap();
+        assert_eq!(
+            client_final,
+            "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+             p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+        );
+        assert_eq!(
+            client.verify_server_final("v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4="),
+            Ok(())
+        );
+        assert_eq!(
+            client.verify_server_final("v=AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="),
+            Err(ScramError::InvalidServerSignature)
+        );
+    }
+
+    #[test]
+    fn gs2_header_reflects_channel_binding() {
+        let hash = vec![0xde, 0xad, 0xbe, 0xef];
+        let bound = ScramClient::with_nonce(
+            "pencil",
+            ChannelBindingData::TlsServerEndPoint(hash.clone()),
+            "nonce",
+        );
+        assert_eq!(bound.mechanism(), SCRAM_SHA_256_PLUS);
+        assert_eq!(bound.client_first(), "p=tls-server-end-point,,n=,r=nonce");
+        let mut input = b"p=tls-server-end-point,,".to_vec();
+        input.extend_from_slice(&hash);
+        assert_eq!(
+            bound.channel_binding_attribute(),
+            format!("c={}", base64_encode(&input))
+        );
+        assert_eq!(
+            bound.channel_binding_attribute(),
+            "c=cD10bHMtc2VydmVyLWVuZC1wb2ludCws3q2+7w=="
+        );
+
+        let not_offered =
+            ScramClient::with_nonce("pencil", ChannelBindingData::NotOffered, "nonce");
+        assert_eq!(not_offered.mechanism(), SCRAM_SHA_256);
+        assert_eq!(not_offered.client_first(), "y,,n=,r=nonce");
+        assert_eq!(not_offered.channel_binding_attribute(), "c=eSws");
+
+        let unbound = ScramClient::with_nonce("pencil", ChannelBindingData::None, "nonce");
+        assert_eq!(unbound.client_first(), "n,,n=,r=nonce");
+        assert_eq!(unbound.channel_binding_attribute(), "c=biws");
+    }
+
+    /// Plays the server side of a SCRAM-SHA-256 exchange for the password
+    /// `pencil`, checking the client's proof, then lets the client in.
+    fn serve_scram(session: &mut MockSession, mechanisms: &[&str]) {
+        session.read_startup();
+        let mut offer = 10u32.to_be_bytes().to_vec();
+        for mechanism in mechanisms {
+            offer.extend_from_slice(mechanism.as_bytes());
+            offer.push(0);
+        }
+        offer.push(0);
+        session.send(&frame(b'R', &offer));
+
+        let initial = session.expect(b'p');
+        let mechanism_end = initial.iter().position(|&byte| byte == 0).unwrap();
+        assert_eq!(&initial[..mechanism_end], SCRAM_SHA_256.as_bytes());
+        let client_first = std::str::from_utf8(&initial[mechanism_end + 5..]).unwrap();
+        let client_first_bare = client_first.strip_prefix("n,,").unwrap();
+        let nonce = client_first_bare.strip_prefix("n=,r=").unwrap();
+
+        let salt = b"salt";
+        let server_first = format!("r={}server,s={},i=1", nonce, base64_encode(salt));
+        let mut server_first_body = 11u32.to_be_bytes().to_vec();
+        server_first_body.extend_from_slice(server_first.as_bytes());
+        session.send(&frame(b'R', &server_first_body));
+
+        let client_final = String::from_utf8(session.expect(b'p')).unwrap();
+        let (without_proof, proof) = client_final.split_once(",p=").unwrap();
+        let auth_message = format!("{},{},{}", client_first_bare, server_first, without_proof);
+        let salted_password = hi(b"pencil", salt, 1);
+        let stored_key = sha256(&hmac_sha256(&salted_password, b"Client Key"));
+        let client_signature = hmac_sha256(&stored_key, auth_message.as_bytes());
+        let client_key: Vec<u8> = base64_decode(proof)
+            .unwrap()
+            .iter()
+            .zip(client_signature)
+            .map(|(proof, signature)| proof ^ signature)
+            .collect();
+        assert_eq!(sha256(&client_key), stored_key);
+
+        let server_key = hmac_sha256(&salted_password, b"Server Key");
+        let server_signature = hmac_sha256(&server_key, auth_message.as_bytes());
+        let mut server_final = 12u32.to_be_bytes().to_vec();
+        server_final
+            .extend_from_slice(format!("v={}", base64_encode(&server_signature)).as_bytes());
+        session.send(&frame(b'R', &server_final));
+        session.send(&frame(b'R', &0u32.to_be_bytes()));
+        session.send(&ready(b'I'));
+    }
+
+    #[test]
+    fn records_the_mechanism_picked_from_the_offer() {
+        let server = MockServer::start(|session| {
+            serve_scram(session, &[SCRAM_SHA_256_PLUS, SCRAM_SHA_256]);
+        });
+        let connection = server.connect();
+        // Without TLS there is nothing to bind to, so the plain mechanism is
+        // picked even though binding was offered.
+        assert_eq!(
+            connection.auth_method(),
+            &AuthMethod::Sasl {
+                offered: vec![SCRAM_SHA_256_PLUS.to_string(), SCRAM_SHA_256.to_string()],
+                selected: SCRAM_SHA_256.to_string(),
+            }
+        );
+        drop(connection);
+        server.finish();
+    }
+}
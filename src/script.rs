@@ -0,0 +1,183 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Reads a `.sql` file and splits it into its individual statements.
+pub fn load_script(path: impl AsRef<Path>) -> io::Result<Vec<String>> {
+    Ok(split_statements(&fs::read_to_string(path)?))
+}
+
+/// Splits a script on the semicolons that end its statements.
+///
+/// Semicolons inside string literals (including `E''` strings with backslash
+/// escapes), quoted identifiers, `$tag$` dollar-quoted bodies and comments are
+/// left alone. Chunks made up only of whitespace and comments are dropped,
+/// since the server would answer them with an `EmptyQueryResponse`.
+pub fn split_statements(script: &str) -> Vec<String> {
+    let chars: Vec<char> = script.chars().collect();
+    let mut statements = vec![];
+    let mut current = String::new();
+    let mut has_content = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        let end = match c {
+            ';' => {
+                if has_content {
+                    statements.push(current.trim().to_string());
+                }
+                current.clear();
+                has_content = false;
+                i += 1;
+                continue;
+            }
+            '-' if next == Some('-') => {
+                let end = chars[i..]
+                    .iter()
+                    .position(|&c| c == '\n')
+                    .map_or(chars.len(), |offset| i + offset + 1);
+                current.extend(&chars[i..end]);
+                i = end;
+                continue;
+            }
+            '/' if next == Some('*') => {
+                let end = block_comment_end(&chars, i);
+                current.extend(&chars[i..end]);
+                i = end;
+                continue;
+            }
+            '\'' => {
+                let escapes = i > 0
+                    && matches!(chars[i - 1], 'e' | 'E')
+                    && !chars[..i - 1].last().is_some_and(|&c| is_identifier(c));
+                quoted_end(&chars, i, '\'', escapes)
+            }
+            '"' => quoted_end(&chars, i, '"', false),
+            '$' if !chars[..i].last().is_some_and(|&c| is_identifier(c)) => {
+                match dollar_tag(&chars, i) {
+                    Some(tag) => dollar_quoted_end(&chars, i, &tag),
+                    None => i + 1,
+                }
+            }
+            _ => i + 1,
+        };
+
+        if !c.is_whitespace() {
+            has_content = true;
+        }
+        current.extend(&chars[i..end]);
+        i = end;
+    }
+
+    if has_content {
+        statements.push(current.trim().to_string());
+    }
+
+    statements
+}
+
+fn is_identifier(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+/// The index just past the closing quote of a literal starting at `start`.
+/// A doubled quote is an escaped quote, as is a backslash one in `E''`.
+fn quoted_end(chars: &[char], start: usize, quote: char, escapes: bool) -> usize {
+    let mut i = start + 1;
+    while i < chars.len() {
+        if escapes && chars[i] == '\\' {
+            i += 2;
+        } else if chars[i] == quote {
+            if chars.get(i + 1) == Some(&quote) {
+                i += 2;
+            } else {
+                return i + 1;
+            }
+        } else {
+            i += 1;
+        }
+    }
+    chars.len()
+}
+
+/// Block comments nest in PostgreSQL, unlike in the SQL standard.
+fn block_comment_end(chars: &[char], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match (chars[i], chars.get(i + 1)) {
+            ('/', Some('*')) => {
+                depth += 1;
+                i += 2;
+            }
+            ('*', Some('/')) => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    chars.len()
+}
+
+/// The `$tag$` opening a dollar-quoted string at `start`, if there is one.
+/// Tags follow identifier rules, which keeps `$1` parameters from matching.
+fn dollar_tag(chars: &[char], start: usize) -> Option<String> {
+    let mut i = start + 1;
+    while i < chars.len() && chars[i] != '$' {
+        let valid = if i == start + 1 {
+            chars[i].is_alphabetic() || chars[i] == '_'
+        } else {
+            chars[i].is_alphanumeric() || chars[i] == '_'
+        };
+        if !valid {
+            return None;
+        }
+        i += 1;
+    }
+    (i < chars.len()).then(|| chars[start..=i].iter().collect())
+}
+
+fn dollar_quoted_end(chars: &[char], start: usize, tag: &str) -> usize {
+    let tag: Vec<char> = tag.chars().collect();
+    let body = start + tag.len();
+    (body..chars.len())
+        .find(|&i| chars[i..].starts_with(&tag))
+        .map_or(chars.len(), |i| i + tag.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_only_on_semicolons_that_end_statements() {
+        let script = "\
+            CREATE TABLE \"a;b\" (x text DEFAULT 'x;y');\n\
+            -- a comment; still a comment\n\
+            INSERT INTO t VALUES (E'it\\'s;');\n\
+            CREATE FUNCTION f() RETURNS int AS $body$ SELECT 1; $body$ LANGUAGE sql;\n\
+            /* only; a comment */;\n\
+            SELECT 2";
+        assert_eq!(
+            split_statements(script),
+            [
+                "CREATE TABLE \"a;b\" (x text DEFAULT 'x;y')",
+                "-- a comment; still a comment\nINSERT INTO t VALUES (E'it\\'s;')",
+                "CREATE FUNCTION f() RETURNS int AS $body$ SELECT 1; $body$ LANGUAGE sql",
+                "SELECT 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_empty_statements() {
+        assert!(split_statements(" ;\n;; ").is_empty());
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+/// Which side of the connection sent a recorded chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Frontend,
+    Backend,
+}
+
+impl Direction {
+    fn code(self) -> u8 {
+        match self {
+            Self::Frontend => b'F',
+            Self::Backend => b'B',
+        }
+    }
+}
+
+/// Wraps a stream such as a [`crate::transport::Transport`] and logs every
+/// byte going through it, in either direction, to `log`.
+///
+/// Each chunk is logged as its direction (`F` or `B`), a big-endian `u32`
+/// length and the bytes themselves, which is what [`SessionReplayer`] reads.
+pub struct SessionRecorder<S, L = File> {
+    inner: S,
+    log: L,
+}
+
+impl<S: Read + Write> SessionRecorder<S> {
+    /// Records into a new file at `path`, truncating any previous session.
+    pub fn create(inner: S, path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(inner, File::create(path)?))
+    }
+}
+
+impl<S: Read + Write, L: Write> SessionRecorder<S, L> {
+    pub fn new(inner: S, log: L) -> Self {
+        Self { inner, log }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns the wrapped stream and the log.
+    pub fn into_inner(self) -> (S, L) {
+        (self.inner, self.log)
+    }
+
+    fn record(&mut self, direction: Direction, bytes: &[u8]) -> io::Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        let length = u32::try_from(bytes.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "chunk too large"))?;
+        self.log.write_all(&[direction.code()])?;
+        self.log.write_all(&length.to_be_bytes())?;
+        self.log.write_all(bytes)
+    }
+}
+
+impl<S: Read + Write, L: Write> Read for SessionRecorder<S, L> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.record(Direction::Backend, &buf[..count])?;
+        Ok(count)
+    }
+}
+
+impl<S: Read + Write, L: Write> Write for SessionRecorder<S, L> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        self.record(Direction::Frontend, &buf[..count])?;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()?;
+        self.log.flush()
+    }
+}
+
+/// Plays back a session logged by [`SessionRecorder`] in place of a server.
+///
+/// Reads return the recorded backend bytes, and writes are checked against
+/// the recorded frontend bytes, failing with `InvalidData` on the first
+/// difference. Consecutive chunks in the same direction are merged, so the
+/// client doesn't have to split its reads and writes the way the recording
+/// did, only send the same bytes in the same order.
+#[derive(Debug)]
+pub struct SessionReplayer {
+    chunks: VecDeque<(Direction, Vec<u8>)>,
+    offset: usize,
+}
+
+impl SessionReplayer {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(File::open(path)?)
+    }
+
+    /// Parses a whole recording from `log`.
+    pub fn new(mut log: impl Read) -> io::Result<Self> {
+        let mut bytes = vec![];
+        log.read_to_end(&mut bytes)?;
+
+        let mut chunks: VecDeque<(Direction, Vec<u8>)> = VecDeque::new();
+        let mut rest = bytes.as_slice();
+        while !rest.is_empty() {
+            let header = rest
+                .get(..5)
+                .ok_or_else(|| invalid("truncated chunk header"))?;
+            let direction = match header[0] {
+                b'F' => Direction::Frontend,
+                b'B' => Direction::Backend,
+                _ => return Err(invalid("unknown chunk direction")),
+            };
+            let length = u32::from_be_bytes(header[1..5].try_into().unwrap()) as usize;
+            let data = rest
+                .get(5..5 + length)
+                .ok_or_else(|| invalid("truncated chunk"))?;
+            match chunks.back_mut() {
+                Some((last, buffer)) if *last == direction => buffer.extend_from_slice(data),
+                _ => chunks.push_back((direction, data.to_vec())),
+            }
+            rest = &rest[5 + length..];
+        }
+
+        Ok(Self { chunks, offset: 0 })
+    }
+
+    /// Whether every recorded byte has been read or written.
+    pub fn is_finished(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    fn advance(&mut self, count: usize) {
+        self.offset += count;
+        if self
+            .chunks
+            .front()
+            .is_some_and(|(_, data)| self.offset == data.len())
+        {
+            self.chunks.pop_front();
+            self.offset = 0;
+        }
+    }
+}
+
+impl Read for SessionReplayer {
+    /// Returns end of file once the recording is exhausted. Reading while the
+    /// recording still expects the client to write fails instead of blocking,
+    /// since against a real server that read would never complete.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some((direction, data)) = self.chunks.front() else {
+            return Ok(0);
+        };
+        if *direction != Direction::Backend {
+            return Err(invalid("read while the recording expects a write"));
+        }
+        let count = buf.len().min(data.len() - self.offset);
+        buf[..count].copy_from_slice(&data[self.offset..self.offset + count]);
+        self.advance(count);
+        Ok(count)
+    }
+}
+
+impl Write for SessionReplayer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            let Some((Direction::Frontend, data)) = self.chunks.front() else {
+                return Err(invalid("write the recording doesn't expect"));
+            };
+            let count = (buf.len() - written).min(data.len() - self.offset);
+            if buf[written..written + count] != data[self.offset..self.offset + count] {
+                return Err(invalid("written bytes differ from the recording"));
+            }
+            self.advance(count);
+            written += count;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A peer that answers with `reply` whatever is written to it.
+    struct Peer {
+        reply: io::Cursor<Vec<u8>>,
+        received: Vec<u8>,
+    }
+
+    impl Read for Peer {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reply.read(buf)
+        }
+    }
+
+    impl Write for Peer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.received.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn record() -> Vec<u8> {
+        let peer = Peer {
+            reply: io::Cursor::new(b"world".to_vec()),
+            received: vec![],
+        };
+        let mut recorder = SessionRecorder::new(peer, vec![]);
+        recorder.write_all(b"hel").unwrap();
+        recorder.write_all(b"lo").unwrap();
+        let mut reply = [0; 3];
+        recorder.read_exact(&mut reply).unwrap();
+        recorder.read_exact(&mut reply[..2]).unwrap();
+        let (peer, log) = recorder.into_inner();
+        assert_eq!(peer.received, b"hello");
+        log
+    }
+
+    #[test]
+    fn replays_a_recording_regardless_of_how_it_was_split() {
+        let log = record();
+        assert_eq!(&log[..8], b"F\0\0\0\x03hel");
+
+        let mut replayer = SessionReplayer::new(log.as_slice()).unwrap();
+        replayer.write_all(b"hello").unwrap();
+        let mut reply = vec![];
+        replayer.read_to_end(&mut reply).unwrap();
+        assert_eq!(reply, b"world");
+        assert!(replayer.is_finished());
+    }
+
+    #[test]
+    fn rejects_writes_that_differ_from_the_recording() {
+        let log = record();
+        let mut replayer = SessionReplayer::new(log.as_slice()).unwrap();
+        let error = replayer.write_all(b"help!").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut replayer = SessionReplayer::new(log.as_slice()).unwrap();
+        let error = replayer.read(&mut [0; 5]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_a_truncated_recording() {
+        let log = record();
+        let error = SessionReplayer::new(&log[..log.len() - 1]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
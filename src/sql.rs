@@ -0,0 +1,39 @@
+/// Quotes a string as an SQL literal, doubling any single quotes.
+pub fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Quotes a string as an SQL identifier, doubling any double quotes.
+pub fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Parses a boolean setting as reported by `SHOW`.
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "on" | "true" => Some(true),
+        "off" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_by_doubling_the_quote_character() {
+        assert_eq!(quote_literal("it's"), "'it''s'");
+        assert_eq!(quote_identifier("My \"Table\""), "\"My \"\"Table\"\"\"");
+        assert_eq!(quote_identifier(""), "\"\"");
+    }
+
+    #[test]
+    fn parses_booleans_as_show_reports_them() {
+        assert_eq!(parse_bool("on"), Some(true));
+        assert_eq!(parse_bool("true"), Some(true));
+        assert_eq!(parse_bool("off"), Some(false));
+        assert_eq!(parse_bool("false"), Some(false));
+        assert_eq!(parse_bool("yes"), None);
+    }
+}
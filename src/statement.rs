@@ -0,0 +1,350 @@
+use crate::connection::*;
+use crate::message::*;
+use crate::row::*;
+use crate::stream::*;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// A named prepared statement created by [`Connection::prepare`].
+///
+/// The handle doesn't borrow the connection: dropping it pushes its name onto
+/// a queue shared with the connection, which sends the matching `Close` ahead
+/// of its next `Sync`, `Parse` or simple query.
+#[derive(Debug)]
+pub struct Statement {
+    name: String,
+    closed: Rc<RefCell<Vec<String>>>,
+}
+
+impl Statement {
+    pub(crate) fn new(name: String, closed: Rc<RefCell<Vec<String>>>) -> Self {
+        Self { name, closed }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Binds the text-format `params` to the unnamed portal and runs it,
+    /// returning the number of rows affected.
+    pub fn execute(
+        &self,
+        connection: &mut Connection,
+        params: &[Option<&str>],
+    ) -> Result<u64, RuntimeError> {
+        connection.send(FrontendMessage::Bind {
+            portal: UNNAMED.to_string(),
+            statement: self.name.clone(),
+            param_formats: vec![],
+            params: params
+                .iter()
+                .map(|param| param.map(|value| value.as_bytes().to_vec()))
+                .collect(),
+            result_formats: vec![],
+        })?;
+        connection.send(FrontendMessage::Execute {
+            portal: UNNAMED.to_string(),
+            max_rows: 0,
+        })?;
+        connection.sync()?;
+
+        let tag = connection.read_until_ready()?;
+        Ok(tag.as_deref().map(rows_affected).unwrap_or(0))
+    }
+
+    /// Runs the statement without parameters, yielding its rows lazily like
+    /// [`Connection::query_stream`] but without parsing the query again.
+    pub fn query_stream<'a>(
+        &self,
+        connection: &'a mut Connection,
+    ) -> Result<RowStream<'a>, RuntimeError> {
+        connection.bind_stream(&self.name)
+    }
+}
+
+/// A [`Statement`] along with the parameter types the server inferred for
+/// it and the columns it returns, from [`Connection::prepare_inferred`].
+#[derive(Debug)]
+pub struct PreparedStatement {
+    statement: Statement,
+    param_types: Vec<u32>,
+    fields: Vec<FieldDescription>,
+}
+
+impl PreparedStatement {
+    pub(crate) fn new(
+        statement: Statement,
+        param_types: Vec<u32>,
+        fields: Vec<FieldDescription>,
+    ) -> Self {
+        Self {
+            statement,
+            param_types,
+            fields,
+        }
+    }
+
+    pub fn statement(&self) -> &Statement {
+        &self.statement
+    }
+
+    /// The OID of each parameter's type, as the server resolved it.
+    pub fn param_types(&self) -> &[u32] {
+        &self.param_types
+    }
+
+    /// The result columns, empty for a statement that returns no rows.
+    pub fn fields(&self) -> &[FieldDescription] {
+        &self.fields
+    }
+
+    pub fn execute(
+        &self,
+        connection: &mut Connection,
+        params: &[Option<&str>],
+    ) -> Result<u64, RuntimeError> {
+        self.statement.execute(connection, params)
+    }
+}
+
+/// What the server reported for a statement in
+/// [`Connection::describe_statement`].
+#[derive(Debug)]
+pub struct StatementDescription {
+    pub param_types: Vec<u32>,
+    pub fields: Vec<FieldDescription>,
+    /// `false` when the server answered with `NoData`, telling apart a
+    /// statement that returns no rows from a query with zero columns.
+    pub returns_rows: bool,
+}
+
+/// A row of `pg_prepared_statements`, from [`Connection::prepared_statements`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreparedStmtInfo {
+    pub name: String,
+    /// The SQL text the statement was prepared from.
+    pub statement: String,
+    /// The parameter types as the server names them, such as `integer`.
+    pub parameter_types: Vec<String>,
+    /// How many times a generic plan was used. `None` before PostgreSQL 14.
+    pub generic_plans: Option<i64>,
+    /// How many times a custom plan was built. `None` before PostgreSQL 14.
+    pub custom_plans: Option<i64>,
+}
+
+impl PreparedStmtInfo {
+    /// Whether the server has switched the statement to a generic plan,
+    /// which it considers after the first five executions.
+    pub fn uses_generic_plan(&self) -> Option<bool> {
+        self.generic_plans.map(|count| count > 0)
+    }
+}
+
+/// Lists the session's prepared statements. The plan counters are read
+/// through `to_jsonb` so the query also runs on servers without them.
+const PREPARED_STATEMENTS_QUERY: &str = "SELECT name, statement, \
+     array_to_string(parameter_types, ','), \
+     to_jsonb(p) ->> 'generic_plans', to_jsonb(p) ->> 'custom_plans' \
+     FROM pg_prepared_statements p ORDER BY name";
+
+impl Connection {
+    /// The statements prepared on this session, as the server sees them,
+    /// to check that statements get reused and which plans they run with.
+    pub fn prepared_statements(&mut self) -> Result<Vec<PreparedStmtInfo>, RuntimeError> {
+        self.query(PREPARED_STATEMENTS_QUERY)?
+            .rows
+            .iter()
+            .map(|row| parse_prepared_statement(row).ok_or(RuntimeError::ParseMessageError))
+            .collect()
+    }
+}
+
+/// Parses a row of the query [`Connection::prepared_statements`] runs.
+pub fn parse_prepared_statement(row: &Row) -> Option<PreparedStmtInfo> {
+    let parameter_types = match row.get_str(2)? {
+        "" => vec![],
+        types => types.split(',').map(str::to_string).collect(),
+    };
+    let counter = |index| match row.get(index) {
+        None => Some(None),
+        Some(_) => row.get_str(index)?.parse().ok().map(Some),
+    };
+
+    Some(PreparedStmtInfo {
+        name: row.get_str(0)?.to_string(),
+        statement: row.get_str(1)?.to_string(),
+        parameter_types,
+        generic_plans: counter(3)?,
+        custom_plans: counter(4)?,
+    })
+}
+
+impl Drop for Statement {
+    fn drop(&mut self) {
+        self.closed
+            .borrow_mut()
+            .push(std::mem::take(&mut self.name));
+    }
+}
+
+/// Parses the row count out of a `CommandComplete` tag such as `UPDATE 3`.
+/// The count is always the last word, so the OID in `INSERT 0 1` is skipped.
+pub fn rows_affected(tag: &str) -> u64 {
+    tag.rsplit(' ')
+        .next()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(0)
+}
+
+/// The OID of the row an `INSERT` added, from a tag such as `INSERT 16401 1`.
+///
+/// Only a single row inserted into a table created `WITH OIDS`, before
+/// PostgreSQL 12, has one; the server sends 0 otherwise, which gives `None`.
+pub fn inserted_oid(tag: &str) -> Option<u32> {
+    match tag.split(' ').collect::<Vec<_>>()[..] {
+        ["INSERT", oid, _] => oid.parse().ok().filter(|&oid| oid != 0),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::*;
+
+    #[test]
+    fn reads_the_oid_of_a_single_row_insert() {
+        assert_eq!(inserted_oid("INSERT 12345 1"), Some(12345));
+        assert_eq!(inserted_oid("INSERT 0 1"), None);
+        assert_eq!(inserted_oid("INSERT 0 3"), None);
+        assert_eq!(inserted_oid("UPDATE 1"), None);
+        assert_eq!(inserted_oid("INSERT"), None);
+    }
+
+    #[test]
+    fn counts_rows_from_the_last_word_of_the_tag() {
+        assert_eq!(rows_affected("INSERT 12345 1"), 1);
+        assert_eq!(rows_affected("INSERT 0 3"), 3);
+        assert_eq!(rows_affected("UPDATE 7"), 7);
+        assert_eq!(rows_affected("CREATE TABLE"), 0);
+    }
+
+    #[test]
+    fn dropped_statements_are_closed_before_the_next_query() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            let parse = session.expect(b'P');
+            session.expect(b'S');
+            session.send(&[frame(b'1', b""), ready(b'I')].concat());
+
+            let name = &parse[..parse.iter().position(|&byte| byte == 0).unwrap() + 1];
+            assert_eq!(session.expect(b'C'), [b"S", name].concat());
+            assert_eq!(session.expect_query(), "SELECT 1");
+            let mut reply = frame(b'3', b"");
+            reply.extend(command_complete("SELECT 1"));
+            reply.extend(ready(b'I'));
+            session.send(&reply);
+        });
+        let mut connection = server.connect();
+        drop(connection.prepare("SELECT $1::int").unwrap());
+        connection.execute("SELECT 1").unwrap();
+        server.finish();
+    }
+
+    #[test]
+    fn parameter_types_are_inferred_by_the_server() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            session.expect(b'P');
+            session.expect(b'D');
+            session.expect(b'S');
+            let mut reply = frame(b'1', b"");
+            reply.extend(frame(b't', &[0, 1, 0, 0, 0, 23]));
+            reply.extend(row_description(&[("?column?", 23)]));
+            reply.extend(ready(b'I'));
+            session.send(&reply);
+        });
+        let mut connection = server.connect();
+
+        let statement = connection.prepare_inferred("SELECT $1 + 1").unwrap();
+        assert_eq!(statement.param_types(), [23]);
+        assert_eq!(statement.fields().len(), 1);
+        assert_eq!(statement.fields()[0].type_oid, 23);
+        drop(statement);
+        drop(connection);
+        server.finish();
+    }
+
+    #[test]
+    fn statement_names_carry_the_prefix_and_differ_across_connections() {
+        let server = MockServer::start_many(2, |session| {
+            session.accept();
+            for _ in 0..2 {
+                session.expect(b'P');
+                session.expect(b'S');
+                session.send(&[frame(b'1', b""), ready(b'I')].concat());
+            }
+            session.expect(b'X');
+        });
+        let mut config = server.config();
+        config.statement_prefix = "bench_".to_string();
+
+        let mut names = vec![];
+        for _ in 0..2 {
+            let mut connection = Connection::connect(&config).unwrap();
+            let first = connection.prepare("SELECT 1").unwrap();
+            let second = connection.prepare("SELECT 1").unwrap();
+            names.extend([first.name().to_string(), second.name().to_string()]);
+        }
+        server.finish();
+
+        assert!(names.iter().all(|name| name.starts_with("bench_")));
+        let mut unique = names.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), 4, "{:?}", names);
+    }
+
+    #[test]
+    fn parses_rows_of_pg_prepared_statements() {
+        let row = |values: [Option<&str>; 5]| {
+            Row::new(
+                values
+                    .iter()
+                    .map(|value| value.map(|value| value.as_bytes().to_vec()))
+                    .collect(),
+            )
+        };
+
+        // As recorded from PostgreSQL 16, after six executions.
+        let recorded = row([
+            Some("toydrv_1a2b3c4d_0"),
+            Some("SELECT * FROM t WHERE id = $1 AND name = $2"),
+            Some("integer,text"),
+            Some("1"),
+            Some("5"),
+        ]);
+        let info = parse_prepared_statement(&recorded).unwrap();
+        assert_eq!(
+            info,
+            PreparedStmtInfo {
+                name: "toydrv_1a2b3c4d_0".to_string(),
+                statement: "SELECT * FROM t WHERE id = $1 AND name = $2".to_string(),
+                parameter_types: vec!["integer".to_string(), "text".to_string()],
+                generic_plans: Some(1),
+                custom_plans: Some(5),
+            }
+        );
+        assert_eq!(info.uses_generic_plan(), Some(true));
+
+        // Before PostgreSQL 14, without the plan counters.
+        let older = row([Some("s"), Some("SELECT 1"), Some(""), None, None]);
+        let info = parse_prepared_statement(&older).unwrap();
+        assert!(info.parameter_types.is_empty());
+        assert_eq!(info.uses_generic_plan(), None);
+
+        let garbled = row([Some("s"), Some("SELECT 1"), Some(""), Some("many"), None]);
+        assert_eq!(parse_prepared_statement(&garbled), None);
+    }
+}
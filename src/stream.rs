@@ -0,0 +1,150 @@
+use crate::connection::*;
+use crate::message::*;
+use crate::row::*;
+
+use std::time::{Duration, Instant};
+
+/// Rows of an extended-protocol query, fetched lazily from the server.
+///
+/// When the connection has a fetch size, the portal is executed that many rows
+/// at a time and re-executed on every `PortalSuspended`, so the full result set
+/// is never held in memory. Dropping the stream early syncs the connection and
+/// discards whatever the server still had to send.
+pub struct RowStream<'a> {
+    connection: &'a mut Connection,
+    fetch_size: u32,
+    done: bool,
+    started: Instant,
+    first_response: Option<Duration>,
+}
+
+impl<'a> RowStream<'a> {
+    pub(crate) fn new(connection: &'a mut Connection, fetch_size: u32) -> Self {
+        Self {
+            connection,
+            fetch_size,
+            done: false,
+            started: Instant::now(),
+            first_response: None,
+        }
+    }
+
+    /// Time from sending the query to its first `DataRow`, or to its
+    /// `CommandComplete` (or `EmptyQueryResponse`) if it returned no rows.
+    /// `None` until either arrives.
+    pub fn time_to_first_byte(&self) -> Option<Duration> {
+        self.first_response
+    }
+
+    fn record_first_response(&mut self) {
+        if self.first_response.is_none() {
+            self.first_response = Some(self.started.elapsed());
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), RuntimeError> {
+        self.done = true;
+        self.connection.sync()?;
+        self.connection.read_until_ready()?;
+        Ok(())
+    }
+}
+
+impl Iterator for RowStream<'_> {
+    type Item = Result<Row, RuntimeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        loop {
+            let message = match self.connection.receive() {
+                Ok(message) => message,
+                Err(error) => {
+                    self.done = true;
+                    return Some(Err(error));
+                }
+            };
+
+            match message {
+                BackendMessage::DataRow { columns } => {
+                    self.record_first_response();
+                    return Some(Ok(Row::new(columns)));
+                }
+                BackendMessage::PortalSuspended => {
+                    let execute = FrontendMessage::Execute {
+                        portal: UNNAMED.to_string(),
+                        max_rows: self.fetch_size,
+                    };
+                    let flush = self
+                        .connection
+                        .send(execute)
+                        .and_then(|_| self.connection.send(FrontendMessage::Flush));
+                    if let Err(error) = flush {
+                        self.done = true;
+                        return Some(Err(error));
+                    }
+                }
+                // An empty query ends the portal without a `CommandComplete`.
+                BackendMessage::CommandComplete { .. } | BackendMessage::EmptyQueryResponse => {
+                    self.record_first_response();
+                    return self.finish().err().map(Err);
+                }
+                BackendMessage::ErrorResponse(fields) => {
+                    let error = RuntimeError::PgError(PgError { fields });
+                    return Some(Err(self.finish().err().unwrap_or(error)));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Drop for RowStream<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::*;
+
+    #[test]
+    fn portal_is_executed_once_per_batch() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            session.expect(b'P');
+            session.expect(b'B');
+            session.send(&[frame(b'1', b""), frame(b'2', b"")].concat());
+            for batch in [&["1", "2"][..], &["3", "4"], &["5"]] {
+                assert_eq!(session.expect(b'E'), b"\0\0\0\0\x02");
+                session.expect(b'H');
+                let mut reply = vec![];
+                for value in batch {
+                    reply.extend(data_row(&[Some(value)]));
+                }
+                match batch.len() {
+                    2 => reply.extend(frame(b's', b"")),
+                    _ => reply.extend(command_complete("SELECT 5")),
+                }
+                session.send(&reply);
+            }
+            session.expect(b'S');
+            session.send(&ready(b'I'));
+        });
+        let mut connection = server.connect();
+        connection.set_fetch_size(2);
+
+        let values: Vec<String> = connection
+            .query_stream("SELECT n FROM t")
+            .unwrap()
+            .map(|row| row.unwrap().get_str(0).unwrap().to_string())
+            .collect();
+        assert_eq!(values, ["1", "2", "3", "4", "5"]);
+        server.finish();
+    }
+}
@@ -0,0 +1,363 @@
+//! The backends behind [`crate::transport::Transport::Tls`]: native-tls, or
+//! rustls with the `rustls` feature. Each exposes the same functions over its
+//! own `TlsStream`.
+
+#[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+compile_error!("the `tls` feature needs a backend: enable `native-tls` or `rustls`");
+
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+pub use native_tls_backend::*;
+#[cfg(feature = "rustls")]
+pub use rustls_backend::*;
+
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+mod native_tls_backend {
+    use crate::connection::RuntimeError;
+    use crate::transport::TlsInfo;
+    use std::net::TcpStream;
+
+    pub type TlsStream = native_tls::TlsStream<TcpStream>;
+
+    /// Runs the handshake, verifying the server against the system's roots.
+    pub fn connect(host: &str, stream: TcpStream) -> Result<TlsStream, RuntimeError> {
+        Ok(native_tls::TlsConnector::new()?.connect(host, stream)?)
+    }
+
+    pub fn tcp_stream(stream: &TlsStream) -> &TcpStream {
+        stream.get_ref()
+    }
+
+    pub fn info(stream: &TlsStream) -> TlsInfo {
+        let peer_certificate = stream
+            .peer_certificate()
+            .ok()
+            .flatten()
+            .and_then(|certificate| certificate.to_der().ok());
+        let subject = peer_certificate.as_deref().and_then(certificate_subject);
+
+        TlsInfo {
+            protocol_version: None,
+            cipher_suite: None,
+            peer_certificate,
+            subject,
+        }
+    }
+
+    pub fn server_end_point(stream: &TlsStream) -> Option<Vec<u8>> {
+        stream.tls_server_end_point().ok().flatten()
+    }
+
+    #[cfg(not(any(target_os = "windows", target_vendor = "apple")))]
+    fn certificate_subject(der: &[u8]) -> Option<String> {
+        let certificate = openssl::x509::X509::from_der(der).ok()?;
+        let entries: Vec<String> = certificate
+            .subject_name()
+            .entries()
+            .filter_map(|entry| {
+                let name = entry.object().nid().short_name().ok()?;
+                let value = entry.data().as_utf8().ok()?;
+                Some(format!("{}={}", name, value))
+            })
+            .collect();
+        Some(entries.join(", "))
+    }
+
+    #[cfg(any(target_os = "windows", target_vendor = "apple"))]
+    fn certificate_subject(_der: &[u8]) -> Option<String> {
+        None
+    }
+}
+
+#[cfg(feature = "rustls")]
+mod rustls_backend {
+    use crate::connection::RuntimeError;
+    use crate::transport::TlsInfo;
+    use ring::digest;
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, ClientConnection, ProtocolVersion, RootCertStore, StreamOwned};
+    use std::net::TcpStream;
+    use std::str;
+    use std::sync::Arc;
+
+    pub type TlsStream = Box<StreamOwned<ClientConnection, TcpStream>>;
+
+    /// Runs the handshake, verifying the server against the webpki roots.
+    pub fn connect(host: &str, stream: TcpStream) -> Result<TlsStream, RuntimeError> {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        connect_with_roots(host, stream, roots)
+    }
+
+    pub(super) fn connect_with_roots(
+        host: &str,
+        mut stream: TcpStream,
+        roots: RootCertStore,
+    ) -> Result<TlsStream, RuntimeError> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let name = ServerName::try_from(host.to_string())
+            .map_err(|error| RuntimeError::TlsError(Box::new(error)))?;
+        let mut connection = ClientConnection::new(Arc::new(config), name)?;
+        // Finished here rather than on the first read, as native-tls does, so
+        // an untrusted certificate fails the connect.
+        while connection.is_handshaking() {
+            connection
+                .complete_io(&mut stream)
+                .map_err(|error| RuntimeError::TlsError(Box::new(error)))?;
+        }
+        Ok(Box::new(StreamOwned::new(connection, stream)))
+    }
+
+    pub fn tcp_stream(stream: &TlsStream) -> &TcpStream {
+        &stream.sock
+    }
+
+    pub fn info(stream: &TlsStream) -> TlsInfo {
+        let peer_certificate = peer_certificate(stream).map(<[u8]>::to_vec);
+        let subject = peer_certificate.as_deref().and_then(certificate_subject);
+
+        let protocol_version = stream.conn.protocol_version().map(|version| match version {
+            ProtocolVersion::TLSv1_2 => "TLSv1.2".to_string(),
+            ProtocolVersion::TLSv1_3 => "TLSv1.3".to_string(),
+            version => format!("{:?}", version),
+        });
+        let cipher_suite = stream
+            .conn
+            .negotiated_cipher_suite()
+            .map(|suite| format!("{:?}", suite.suite()));
+
+        TlsInfo {
+            protocol_version,
+            cipher_suite,
+            peer_certificate,
+            subject,
+        }
+    }
+
+    pub fn server_end_point(stream: &TlsStream) -> Option<Vec<u8>> {
+        let certificate = peer_certificate(stream)?;
+        let hash = end_point_hash(certificate)?;
+        Some(digest::digest(hash, certificate).as_ref().to_vec())
+    }
+
+    fn peer_certificate(stream: &TlsStream) -> Option<&[u8]> {
+        Some(stream.conn.peer_certificates()?.first()?.as_ref())
+    }
+
+    // rustls leaves certificates DER-encoded, and the little read from them
+    // here doesn't call for a full X.509 parser.
+
+    const SEQUENCE: u8 = 0x30;
+    const OBJECT_IDENTIFIER: u8 = 0x06;
+    /// The `[0]` tag of a certificate's optional version.
+    const VERSION: u8 = 0xa0;
+
+    /// Splits the first DER element off `input`, as its tag, its contents,
+    /// and what follows it.
+    fn der_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+        let (&tag, rest) = input.split_first()?;
+        let (&first, rest) = rest.split_first()?;
+        let (length, rest) = if first < 0x80 {
+            (usize::from(first), rest)
+        } else {
+            let (bytes, rest) = rest.split_at_checked(usize::from(first & 0x7f))?;
+            if bytes.is_empty() || bytes.len() > 4 {
+                return None;
+            }
+            let length = bytes
+                .iter()
+                .fold(0, |length, &byte| length << 8 | usize::from(byte));
+            (length, rest)
+        };
+        let (contents, rest) = rest.split_at_checked(length)?;
+        Some((tag, contents, rest))
+    }
+
+    /// The contents of a certificate's `tbsCertificate` and the OID of the
+    /// algorithm it's signed with.
+    fn certificate_parts(der: &[u8]) -> Option<(&[u8], &[u8])> {
+        let (SEQUENCE, certificate, _) = der_element(der)? else {
+            return None;
+        };
+        let (SEQUENCE, tbs, rest) = der_element(certificate)? else {
+            return None;
+        };
+        let (SEQUENCE, algorithm, _) = der_element(rest)? else {
+            return None;
+        };
+        let (OBJECT_IDENTIFIER, oid, _) = der_element(algorithm)? else {
+            return None;
+        };
+        Some((tbs, oid))
+    }
+
+    /// The certificate's subject, such as `CN=db.example.com`, with the
+    /// attributes [`attribute_name`] knows.
+    pub(super) fn certificate_subject(der: &[u8]) -> Option<String> {
+        let (tbs, _) = certificate_parts(der)?;
+        let mut fields = tbs;
+        if der_element(fields)?.0 == VERSION {
+            fields = der_element(fields)?.2;
+        }
+        // The serial number, signature algorithm, issuer and validity.
+        for _ in 0..4 {
+            fields = der_element(fields)?.2;
+        }
+        let (SEQUENCE, mut names, _) = der_element(fields)? else {
+            return None;
+        };
+
+        let mut entries = vec![];
+        while !names.is_empty() {
+            let (_, mut attributes, rest) = der_element(names)?;
+            names = rest;
+            while !attributes.is_empty() {
+                let (_, attribute, rest) = der_element(attributes)?;
+                attributes = rest;
+                let (OBJECT_IDENTIFIER, oid, value) = der_element(attribute)? else {
+                    return None;
+                };
+                if let Some(name) = attribute_name(oid) {
+                    let (_, value, _) = der_element(value)?;
+                    entries.push(format!("{}={}", name, str::from_utf8(value).ok()?));
+                }
+            }
+        }
+        Some(entries.join(", "))
+    }
+
+    fn attribute_name(oid: &[u8]) -> Option<&'static str> {
+        Some(match oid {
+            [0x55, 0x04, 0x03] => "CN",
+            [0x55, 0x04, 0x06] => "C",
+            [0x55, 0x04, 0x07] => "L",
+            [0x55, 0x04, 0x08] => "ST",
+            [0x55, 0x04, 0x0a] => "O",
+            [0x55, 0x04, 0x0b] => "OU",
+            _ => return None,
+        })
+    }
+
+    /// The hash `tls-server-end-point` takes of a certificate: the one it's
+    /// signed with, but SHA-256 in place of MD5 and SHA-1 (RFC 5929, section
+    /// 4.1). `None` for algorithms without a single hash, like Ed25519.
+    pub(super) fn end_point_hash(der: &[u8]) -> Option<&'static digest::Algorithm> {
+        const PKCS1: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01];
+        const ECDSA: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04];
+
+        let (_, oid) = certificate_parts(der)?;
+        let hash = if let Some(suffix) = oid.strip_prefix(PKCS1) {
+            match suffix {
+                [0x04 | 0x05 | 0x0b] => &digest::SHA256,
+                [0x0c] => &digest::SHA384,
+                [0x0d] => &digest::SHA512,
+                _ => return None,
+            }
+        } else {
+            match oid.strip_prefix(ECDSA)? {
+                [0x01] | [0x03, 0x02] => &digest::SHA256,
+                [0x03, 0x03] => &digest::SHA384,
+                [0x03, 0x04] => &digest::SHA512,
+                _ => return None,
+            }
+        };
+        Some(hash)
+    }
+}
+
+#[cfg(all(test, feature = "rustls"))]
+mod tests {
+    use super::rustls_backend::*;
+    use crate::connection::RuntimeError;
+    use crate::transport::Transport;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+    use rustls::{RootCertStore, ServerConfig, ServerConnection, StreamOwned};
+    use std::io::prelude::*;
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::Arc;
+    use std::thread;
+
+    /// A test CA, and a certificate it issued for `localhost` with its key.
+    const CA: &[u8] = include_bytes!("../testdata/ca.der");
+    const SERVER: &[u8] = include_bytes!("../testdata/server.der");
+    const SERVER_KEY: &[u8] = include_bytes!("../testdata/server.key.der");
+
+    /// Accepts one TLS connection and echoes back the first 4 bytes read.
+    fn echo_server() -> (u16, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let provider = Arc::new(rustls::crypto::ring::default_provider());
+            let config = ServerConfig::builder_with_provider(provider)
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(
+                    vec![CertificateDer::from(SERVER)],
+                    PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(SERVER_KEY)),
+                )
+                .unwrap();
+            let (socket, _) = listener.accept().unwrap();
+            let connection = ServerConnection::new(Arc::new(config)).unwrap();
+            let mut stream = StreamOwned::new(connection, socket);
+            let mut buf = [0u8; 4];
+            if stream.read_exact(&mut buf).is_ok() {
+                stream.write_all(&buf).unwrap();
+                stream.flush().unwrap();
+            }
+        });
+        (port, handle)
+    }
+
+    #[test]
+    fn connects_to_a_trusted_server() {
+        let (port, server) = echo_server();
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from(CA)).unwrap();
+        let socket = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut transport = Transport::Tls(connect_with_roots("localhost", socket, roots).unwrap());
+
+        transport.write_all(b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        transport.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        let info = transport.tls_info().unwrap();
+        assert_eq!(info.protocol_version.as_deref(), Some("TLSv1.3"));
+        assert!(info.cipher_suite.unwrap().starts_with("TLS13_"));
+        assert_eq!(info.peer_certificate.as_deref(), Some(SERVER));
+        assert_eq!(
+            info.subject.as_deref(),
+            Some("C=CA, O=toy-driver, CN=localhost")
+        );
+        // The certificate is signed with ECDSA and SHA-256.
+        let hash = ring::digest::digest(&ring::digest::SHA256, SERVER);
+        assert_eq!(transport.tls_server_end_point().unwrap(), hash.as_ref());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn rejects_an_untrusted_server() {
+        let (port, server) = echo_server();
+        let socket = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        assert!(matches!(
+            connect("localhost", socket),
+            Err(RuntimeError::TlsError(_))
+        ));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn reads_the_subject_and_hash_of_a_certificate() {
+        assert_eq!(certificate_subject(CA).as_deref(), Some("CN=Test CA"));
+        assert_eq!(
+            end_point_hash(SERVER).map(|hash| hash.output_len()),
+            Some(32)
+        );
+        assert_eq!(certificate_subject(&SERVER[..100]), None);
+    }
+}
@@ -0,0 +1,216 @@
+use crate::connection::*;
+use crate::message::*;
+use crate::sql::quote_identifier;
+
+use std::thread;
+use std::time::Duration;
+
+/// SQLSTATEs meaning the transaction lost a race with a concurrent one and
+/// may succeed if run again: `serialization_failure` and `deadlock_detected`.
+pub const RETRYABLE_SQLSTATES: [&str; 2] = ["40001", "40P01"];
+
+/// The wait before the first retry, doubled for each one after it.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(10);
+
+impl Connection {
+    /// Runs `f` inside a transaction and commits it, starting over when the
+    /// server aborts it with one of the [`RETRYABLE_SQLSTATES`].
+    ///
+    /// After a retryable failure the transaction is rolled back, and `f` is
+    /// run again after a short backoff, up to `max_retries` times. Any other
+    /// error rolls back and is returned right away. A failed `COMMIT` counts
+    /// too, since serializable transactions can be aborted there, as does one
+    /// the server answers with `ROLLBACK` ([`RuntimeError::RolledBack`]).
+    pub fn transaction_retry<T>(
+        &mut self,
+        max_retries: usize,
+        mut f: impl FnMut(&mut Connection) -> Result<T, RuntimeError>,
+    ) -> Result<T, RuntimeError> {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            let error = match self.run_transaction(&mut f) {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            if self.drain_to_ready()? != ReadyForQueryStatus::Idle {
+                self.execute("ROLLBACK")?;
+            }
+            if attempt == max_retries || !is_retryable(&error) {
+                return Err(error);
+            }
+            attempt += 1;
+            thread::sleep(backoff);
+            backoff *= 2;
+        }
+    }
+
+    /// Sets a savepoint named `name` inside the current transaction, such as
+    /// the one [`Connection::transaction_retry`] runs its closure in.
+    /// Savepoints nest, and rolling back to one leaves the work done before it
+    /// in place.
+    pub fn savepoint(&mut self, name: &str) -> Result<(), RuntimeError> {
+        self.execute_confirmed(
+            &format!("SAVEPOINT {}", quote_identifier(name)),
+            "SAVEPOINT",
+        )
+    }
+
+    /// Forgets the savepoint `name` and any set after it, keeping their work.
+    pub fn release_savepoint(&mut self, name: &str) -> Result<(), RuntimeError> {
+        self.execute_confirmed(
+            &format!("RELEASE SAVEPOINT {}", quote_identifier(name)),
+            "RELEASE",
+        )
+    }
+
+    /// Undoes everything since the savepoint `name` was set. The savepoint
+    /// itself stays, so it can be rolled back to again.
+    pub fn rollback_to_savepoint(&mut self, name: &str) -> Result<(), RuntimeError> {
+        self.execute_confirmed(
+            &format!("ROLLBACK TO SAVEPOINT {}", quote_identifier(name)),
+            "ROLLBACK",
+        )
+    }
+
+    /// Runs `sql` and checks the server answered with the command tag `tag`.
+    fn execute_confirmed(&mut self, sql: &str, tag: &str) -> Result<(), RuntimeError> {
+        self.execute(sql)?;
+        if self.last_command_tag() != Some(tag) {
+            return Err(RuntimeError::ParseMessageError);
+        }
+        Ok(())
+    }
+
+    fn run_transaction<T>(
+        &mut self,
+        f: &mut impl FnMut(&mut Connection) -> Result<T, RuntimeError>,
+    ) -> Result<T, RuntimeError> {
+        self.execute("BEGIN")?;
+        let value = f(self)?;
+        self.execute("COMMIT")?;
+        if self.last_command_tag() != Some("COMMIT") {
+            return Err(RuntimeError::RolledBack);
+        }
+        Ok(value)
+    }
+}
+
+/// Whether `error` aborted the transaction in a way worth retrying.
+pub fn is_retryable(error: &RuntimeError) -> bool {
+    match error {
+        RuntimeError::PgError(error) => error
+            .code()
+            .is_some_and(|code| RETRYABLE_SQLSTATES.contains(&code)),
+        RuntimeError::RolledBack => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::*;
+
+    /// Answers one attempt at a transaction running `SELECT 1`, ending it
+    /// with `commit_tag`.
+    fn serve_attempt(session: &mut MockSession, commit_tag: &str) {
+        assert_eq!(session.expect_query(), "BEGIN");
+        session.complete("BEGIN", b'T');
+        assert_eq!(session.expect_query(), "SELECT 1");
+        session.complete("SELECT 1", b'T');
+        assert_eq!(session.expect_query(), "COMMIT");
+        session.complete(commit_tag, b'I');
+    }
+
+    #[test]
+    fn commit_answered_with_rollback_is_retried() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            serve_attempt(session, "ROLLBACK");
+            serve_attempt(session, "COMMIT");
+        });
+        let mut connection = server.connect();
+        let mut attempts = 0;
+        let result = connection.transaction_retry(1, |connection| {
+            attempts += 1;
+            connection.execute("SELECT 1")
+        });
+        assert!(result.is_ok());
+        assert_eq!(attempts, 2);
+        server.finish();
+    }
+
+    #[test]
+    fn commit_answered_with_rollback_fails_once_retries_run_out() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            serve_attempt(session, "ROLLBACK");
+        });
+        let mut connection = server.connect();
+        let result = connection.transaction_retry(0, |connection| connection.execute("SELECT 1"));
+        assert!(matches!(result, Err(RuntimeError::RolledBack)));
+        assert_eq!(connection.transaction_status(), ReadyForQueryStatus::Idle);
+        server.finish();
+    }
+
+    #[test]
+    fn serialization_failures_are_retried_until_success() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            for _ in 0..2 {
+                assert_eq!(session.expect_query(), "BEGIN");
+                session.complete("BEGIN", b'T');
+                assert_eq!(session.expect_query(), "SELECT 1");
+                let mut reply = error_response("40001", "could not serialize access");
+                reply.extend(ready(b'E'));
+                session.send(&reply);
+                assert_eq!(session.expect_query(), "ROLLBACK");
+                session.complete("ROLLBACK", b'I');
+            }
+            serve_attempt(session, "COMMIT");
+        });
+        let mut connection = server.connect();
+        let mut attempts = 0;
+        let result = connection.transaction_retry(2, |connection| {
+            attempts += 1;
+            connection.execute("SELECT 1")
+        });
+        assert!(result.is_ok());
+        assert_eq!(attempts, 3);
+        server.finish();
+    }
+
+    #[test]
+    fn rolling_back_a_savepoint_keeps_the_outer_transaction() {
+        let server = MockServer::start(|session| {
+            session.accept();
+            let script = [
+                ("BEGIN", "BEGIN"),
+                ("INSERT INTO t VALUES (1)", "INSERT 0 1"),
+                ("SAVEPOINT \"inner\"", "SAVEPOINT"),
+                ("INSERT INTO t VALUES (2)", "INSERT 0 1"),
+                ("ROLLBACK TO SAVEPOINT \"inner\"", "ROLLBACK"),
+                ("RELEASE SAVEPOINT \"inner\"", "RELEASE"),
+            ];
+            for (query, tag) in script {
+                assert_eq!(session.expect_query(), query);
+                session.complete(tag, b'T');
+            }
+            assert_eq!(session.expect_query(), "COMMIT");
+            session.complete("COMMIT", b'I');
+        });
+        let mut connection = server.connect();
+        let result = connection.transaction_retry(0, |connection| {
+            connection.execute("INSERT INTO t VALUES (1)")?;
+            connection.savepoint("inner")?;
+            connection.execute("INSERT INTO t VALUES (2)")?;
+            connection.rollback_to_savepoint("inner")?;
+            connection.release_savepoint("inner")
+        });
+        assert!(result.is_ok());
+        assert_eq!(connection.last_command_tag(), Some("COMMIT"));
+        assert_eq!(connection.transaction_status(), ReadyForQueryStatus::Idle);
+        server.finish();
+    }
+}
@@ -0,0 +1,178 @@
+#[cfg(feature = "tls")]
+use crate::tls::{self, TlsStream};
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::TcpStream;
+
+/// The byte stream underneath a [`crate::connection::Connection`].
+#[derive(Debug)]
+pub enum Transport {
+    Plain(TcpStream),
+    /// Encrypted by whichever backend [`crate::tls`] was built with.
+    #[cfg(feature = "tls")]
+    Tls(TlsStream),
+}
+
+impl Transport {
+    pub fn tcp_stream(&self) -> &TcpStream {
+        match self {
+            Self::Plain(stream) => stream,
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => tls::tcp_stream(stream),
+        }
+    }
+
+    pub fn is_tls(&self) -> bool {
+        !matches!(self, Self::Plain(_))
+    }
+
+    /// Details of the TLS session, or `None` on an unencrypted transport.
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+        match self {
+            Self::Plain(_) => None,
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => Some(tls::info(stream)),
+        }
+    }
+
+    /// The `tls-server-end-point` channel binding data (RFC 5929): a hash of
+    /// the server's certificate. `None` when not on TLS.
+    pub fn tls_server_end_point(&self) -> Option<Vec<u8>> {
+        match self {
+            Self::Plain(_) => None,
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => tls::server_end_point(stream),
+        }
+    }
+}
+
+/// The reads and writes each thread made on a [`Transport`], for tests to
+/// count the syscalls an exchange takes.
+#[cfg(test)]
+pub(crate) mod syscalls {
+    use std::cell::Cell;
+
+    thread_local! {
+        pub(crate) static READS: Cell<usize> = const { Cell::new(0) };
+        pub(crate) static WRITES: Cell<usize> = const { Cell::new(0) };
+    }
+
+    pub(crate) fn count(counter: &'static std::thread::LocalKey<Cell<usize>>) {
+        counter.with(|count| count.set(count.get() + 1));
+    }
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        #[cfg(test)]
+        syscalls::count(&syscalls::READS);
+        match self {
+            Self::Plain(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        #[cfg(test)]
+        syscalls::count(&syscalls::WRITES);
+        match self {
+            Self::Plain(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Plain(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Buffers the read side of a stream, passing writes straight through.
+///
+/// Reading a message takes separate reads for its prefix, length and body;
+/// with the buffer, a batch of small messages is served from one read of the
+/// socket. Errors from the inner stream, such as a read timeout, surface
+/// unchanged.
+#[derive(Debug)]
+pub struct BufStream<S> {
+    reader: BufReader<S>,
+}
+
+impl<S: Read> BufStream<S> {
+    pub fn with_capacity(capacity: usize, inner: S) -> Self {
+        Self {
+            reader: BufReader::with_capacity(capacity, inner),
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        self.reader.get_ref()
+    }
+
+    /// Whether bytes have already been read off the inner stream but not
+    /// consumed, which polling the socket wouldn't see.
+    pub fn has_buffered(&self) -> bool {
+        !self.reader.buffer().is_empty()
+    }
+}
+
+impl<S: Read> Read for BufStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl<S: Read + Write> Write for BufStream<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.reader.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.reader.get_mut().flush()
+    }
+}
+
+/// What is known about an established TLS session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsInfo {
+    /// The negotiated protocol, such as `TLSv1.3`. native-tls doesn't expose
+    /// it, so it's only known with rustls.
+    pub protocol_version: Option<String>,
+    /// The negotiated cipher suite, such as `TLS13_AES_256_GCM_SHA384`. Only
+    /// known with rustls, like the protocol version.
+    pub cipher_suite: Option<String>,
+    /// The server's certificate, DER-encoded.
+    pub peer_certificate: Option<Vec<u8>>,
+    /// The certificate's subject, such as `CN=db.example.com`. Only decoded
+    /// with rustls, or where native-tls is backed by OpenSSL.
+    pub subject: Option<String>,
+}
+
+/// Asks the OS for a receive buffer of `size` bytes (`SO_RCVBUF`). The kernel
+/// may round or clamp it.
+pub fn set_receive_buffer_size(stream: &TcpStream, size: usize) -> std::io::Result<()> {
+    socket2::SockRef::from(stream).set_recv_buffer_size(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn plain_transport_has_no_tls_info() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let transport = Transport::Plain(stream);
+        assert!(!transport.is_tls());
+        assert_eq!(transport.tls_info(), None);
+        assert_eq!(transport.tls_server_end_point(), None);
+    }
+}
@@ -0,0 +1,1138 @@
+use crate::message::*;
+use crate::row::*;
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// A `timestamp` or `timestamptz` value. `timestamptz` is always in UTC on
+/// the wire, whatever the session's `TimeZone`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Timestamp {
+    NegativeInfinity,
+    /// Microseconds since 2000-01-01 00:00:00.
+    Micros(i64),
+    Infinity,
+}
+
+/// Decodes a binary timestamp, which is an `i64` of microseconds when the
+/// server has `integer_datetimes` on, and an `f64` of seconds otherwise.
+pub fn decode_timestamp(value: &[u8], integer_datetimes: bool) -> Option<Timestamp> {
+    let bytes: [u8; 8] = value.try_into().ok()?;
+    let timestamp = if integer_datetimes {
+        match i64::from_be_bytes(bytes) {
+            i64::MIN => Timestamp::NegativeInfinity,
+            i64::MAX => Timestamp::Infinity,
+            micros => Timestamp::Micros(micros),
+        }
+    } else {
+        let seconds = f64::from_be_bytes(bytes);
+        if seconds == f64::NEG_INFINITY {
+            Timestamp::NegativeInfinity
+        } else if seconds == f64::INFINITY {
+            Timestamp::Infinity
+        } else {
+            Timestamp::Micros((seconds * 1_000_000.0).round() as i64)
+        }
+    };
+    Some(timestamp)
+}
+
+/// The OID of `money`.
+pub const MONEY_OID: u32 = 790;
+
+/// Decodes a text `money` value such as `$1,234.56` or `-1.234,56 €` into
+/// cents, formatted as the server's `lc_monetary` dictates (`C` when unknown).
+///
+/// The text output depends on the server's locale, and `lc_monetary` isn't
+/// reported to clients unless it was set for the session, so this can only
+/// guess: anything but digits, the sign and the decimal separator is ignored,
+/// and the separator is picked from the locale's language. A value is
+/// negative when a `-` comes before its first digit, as in `-$1.00` or
+/// `$-1.00`, or when it is wrapped in parentheses. Locales that
+/// group or scale differently, like `de_CH` or currencies without cents,
+/// may decode wrongly. The binary format, an `i64` of cents, has no such
+/// ambiguity.
+pub fn decode_money(value: &str, lc_monetary: Option<&str>) -> Option<i64> {
+    let decimal = decimal_separator(lc_monetary.unwrap_or("C"));
+    let trimmed = value.trim();
+    let parenthesized = match trimmed.strip_prefix('(') {
+        Some(rest) => {
+            rest.strip_suffix(')')?;
+            true
+        }
+        None => false,
+    };
+
+    let mut negative = parenthesized;
+    let mut amount: i64 = 0;
+    let mut digits = 0;
+    let mut fraction_digits = None;
+    for c in value.chars() {
+        if c == '-' && digits == 0 {
+            if negative {
+                return None;
+            }
+            negative = true;
+        } else if let Some(digit) = c.to_digit(10) {
+            amount = amount.checked_mul(10)?.checked_add(digit.into())?;
+            digits += 1;
+            if let Some(count) = fraction_digits.as_mut() {
+                *count += 1;
+            }
+        } else if c == decimal && fraction_digits.replace(0).is_some() {
+            return None;
+        }
+    }
+
+    let scale = 2u32.checked_sub(fraction_digits.unwrap_or(0))?;
+    if digits == 0 {
+        return None;
+    }
+    let cents = amount.checked_mul(10i64.pow(scale))?;
+    Some(if negative { -cents } else { cents })
+}
+
+/// The decimal separator of a locale's currency amounts.
+fn decimal_separator(locale: &str) -> char {
+    let language = locale.split(['_', '.', '-']).next().unwrap_or(locale);
+    match language {
+        "de" | "fr" | "es" | "it" | "nl" | "pt" | "ru" | "pl" | "sv" | "da" | "nb" | "nn"
+        | "fi" | "cs" | "sk" | "tr" | "el" | "hu" | "ro" | "uk" | "id" => ',',
+        _ => '.',
+    }
+}
+
+/// The OID of `interval`.
+pub const INTERVAL_OID: u32 = 1186;
+
+const MICROS_PER_SECOND: i64 = 1_000_000;
+
+/// An `interval`, kept in the three parts the server stores, since months
+/// and days don't have a fixed length.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Interval {
+    pub months: i32,
+    pub days: i32,
+    pub micros: i64,
+}
+
+impl Interval {
+    // Each returns `None` when the part would overflow, so a decoder can
+    // reject the value with `?`.
+
+    fn add_months(&mut self, months: i32) -> Option<()> {
+        self.months = self.months.checked_add(months)?;
+        Some(())
+    }
+
+    fn add_days(&mut self, days: i32) -> Option<()> {
+        self.days = self.days.checked_add(days)?;
+        Some(())
+    }
+
+    fn add_micros(&mut self, micros: i64) -> Option<()> {
+        self.micros = self.micros.checked_add(micros)?;
+        Some(())
+    }
+}
+
+/// Decodes a text `interval` as formatted under the session's
+/// `IntervalStyle`: `postgres` (the default) or `postgres_verbose`, such as
+/// `1 year 2 mons 3 days 04:05:06`, `iso_8601`, such as `P1Y2M3DT4H5M6S`, or
+/// `sql_standard`, such as `1-2 3 4:05:06`.
+pub fn decode_interval(value: &str, interval_style: Option<&str>) -> Option<Interval> {
+    match interval_style {
+        Some("iso_8601") => parse_iso_8601_interval(value),
+        Some("sql_standard") => parse_sql_standard_interval(value),
+        _ => parse_postgres_interval(value),
+    }
+}
+
+/// `[@] <n> <unit>... [[-]HH:MM:SS] [ago]`, the `postgres` style and its
+/// verbose variant.
+fn parse_postgres_interval(value: &str) -> Option<Interval> {
+    let tokens: Vec<&str> = value.split_whitespace().filter(|&t| t != "@").collect();
+    let mut interval = Interval::default();
+    let mut ago = false;
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = tokens[i];
+        i += 1;
+        if token == "ago" {
+            ago = true;
+            continue;
+        }
+        if token.contains(':') {
+            interval.add_micros(parse_time(token)?)?;
+            continue;
+        }
+        let Some(unit) = tokens.get(i) else {
+            // A zero interval is just `@ 0` in the verbose style.
+            parse_seconds(token)?;
+            continue;
+        };
+        i += 1;
+        match unit.trim_end_matches('s') {
+            "year" => interval.add_months(token.parse::<i32>().ok()?.checked_mul(12)?)?,
+            "mon" => interval.add_months(token.parse().ok()?)?,
+            "day" => interval.add_days(token.parse().ok()?)?,
+            "hour" => interval.add_micros(
+                token
+                    .parse::<i64>()
+                    .ok()?
+                    .checked_mul(3600 * MICROS_PER_SECOND)?,
+            )?,
+            "min" => interval.add_micros(
+                token
+                    .parse::<i64>()
+                    .ok()?
+                    .checked_mul(60 * MICROS_PER_SECOND)?,
+            )?,
+            "sec" => interval.add_micros(parse_seconds(token)?)?,
+            _ => return None,
+        }
+    }
+    if ago {
+        interval = Interval {
+            months: interval.months.checked_neg()?,
+            days: interval.days.checked_neg()?,
+            micros: interval.micros.checked_neg()?,
+        };
+    }
+    Some(interval)
+}
+
+/// `P[nY][nM][nW][nD][T[nH][nM][nS]]`, each number possibly negative.
+fn parse_iso_8601_interval(value: &str) -> Option<Interval> {
+    let rest = value.strip_prefix('P')?;
+    let (date, time) = rest.split_once('T').unwrap_or((rest, ""));
+    let mut interval = Interval::default();
+
+    for (number, designator) in designated_numbers(date)? {
+        let number: i32 = number.parse().ok()?;
+        match designator {
+            'Y' => interval.add_months(number.checked_mul(12)?)?,
+            'M' => interval.add_months(number)?,
+            'W' => interval.add_days(number.checked_mul(7)?)?,
+            'D' => interval.add_days(number)?,
+            _ => return None,
+        }
+    }
+    for (number, designator) in designated_numbers(time)? {
+        let micros = match designator {
+            'H' => number
+                .parse::<i64>()
+                .ok()?
+                .checked_mul(3600 * MICROS_PER_SECOND)?,
+            'M' => number
+                .parse::<i64>()
+                .ok()?
+                .checked_mul(60 * MICROS_PER_SECOND)?,
+            'S' => parse_seconds(number)?,
+            _ => return None,
+        };
+        interval.add_micros(micros)?;
+    }
+    Some(interval)
+}
+
+/// Splits `1Y-2M3.5S` into its numbers and the letters following them.
+fn designated_numbers(value: &str) -> Option<Vec<(&str, char)>> {
+    let mut parts = vec![];
+    let mut start = 0;
+    for (index, c) in value.char_indices() {
+        if c.is_ascii_alphabetic() {
+            if index == start {
+                return None;
+            }
+            parts.push((&value[start..index], c));
+            start = index + 1;
+        }
+    }
+    (start == value.len()).then_some(parts)
+}
+
+/// `[Y-M] [D] [H:MM:SS]`. A sign is either on every field, or only on the
+/// first and then applies to all of them.
+fn parse_sql_standard_interval(value: &str) -> Option<Interval> {
+    let mut interval = Interval::default();
+    let mut leading_negative = None;
+    for field in value.split_whitespace() {
+        let negative = *leading_negative.get_or_insert(field.starts_with('-'));
+        let explicit = field.starts_with(['-', '+']);
+        let unsigned = field.trim_start_matches(['-', '+']);
+        let sign = match (explicit, negative) {
+            (true, _) => {
+                if field.starts_with('-') {
+                    -1
+                } else {
+                    1
+                }
+            }
+            (false, true) => -1,
+            (false, false) => 1,
+        };
+
+        if unsigned.contains(':') {
+            interval.add_micros(parse_time(unsigned)?.checked_mul(sign.into())?)?;
+        } else if let Some((years, months)) = unsigned.split_once('-') {
+            let years: i32 = years.parse().ok()?;
+            let months: i32 = months.parse().ok()?;
+            interval.add_months(
+                years
+                    .checked_mul(12)?
+                    .checked_add(months)?
+                    .checked_mul(sign)?,
+            )?;
+        } else {
+            interval.add_days(unsigned.parse::<i32>().ok()?.checked_mul(sign)?)?;
+        }
+    }
+    Some(interval)
+}
+
+/// `[-+]H:MM:SS[.ffffff]` into microseconds.
+fn parse_time(value: &str) -> Option<i64> {
+    let negative = value.starts_with('-');
+    let mut parts = value.trim_start_matches(['-', '+']).split(':');
+    let hours: i64 = parts.next()?.parse().ok()?;
+    let minutes: i64 = parts.next()?.parse().ok()?;
+    let seconds = match parts.next() {
+        Some(seconds) => parse_seconds(seconds)?,
+        None => 0,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+    let micros = hours
+        .checked_mul(60)?
+        .checked_add(minutes)?
+        .checked_mul(60 * MICROS_PER_SECOND)?
+        .checked_add(seconds)?;
+    Some(if negative { -micros } else { micros })
+}
+
+/// A possibly fractional, possibly signed number of seconds into
+/// microseconds, without going through a float.
+fn parse_seconds(value: &str) -> Option<i64> {
+    let negative = value.starts_with('-');
+    let unsigned = value.trim_start_matches(['-', '+']);
+    let (whole, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+    if whole.is_empty() || fraction.len() > 6 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let fraction = format!("{:0<6}", fraction);
+    let micros = whole
+        .parse::<i64>()
+        .ok()?
+        .checked_mul(MICROS_PER_SECOND)?
+        .checked_add(fraction.parse().ok()?)?;
+    Some(if negative { -micros } else { micros })
+}
+
+/// Decodes a column value in either wire format.
+pub trait FromSql: Sized {
+    fn from_text(value: &str) -> Option<Self>;
+    fn from_binary(value: &[u8]) -> Option<Self>;
+}
+
+macro_rules! from_sql_number {
+    ($($ty:ty),*) => {$(
+        impl FromSql for $ty {
+            fn from_text(value: &str) -> Option<Self> {
+                value.parse().ok()
+            }
+
+            fn from_binary(value: &[u8]) -> Option<Self> {
+                Some(<$ty>::from_be_bytes(value.try_into().ok()?))
+            }
+        }
+    )*};
+}
+
+from_sql_number!(i16, i32, i64, f32, f64);
+
+impl FromSql for bool {
+    fn from_text(value: &str) -> Option<Self> {
+        match value {
+            "t" => Some(true),
+            "f" => Some(false),
+            _ => None,
+        }
+    }
+
+    fn from_binary(value: &[u8]) -> Option<Self> {
+        match value {
+            [byte] => Some(*byte != 0),
+            _ => None,
+        }
+    }
+}
+
+impl FromSql for String {
+    fn from_text(value: &str) -> Option<Self> {
+        Some(value.to_string())
+    }
+
+    /// Text-like types (`text`, `varchar`, `name`...) send their UTF-8 bytes
+    /// as-is in binary too.
+    fn from_binary(value: &[u8]) -> Option<Self> {
+        String::from_utf8(value.to_vec()).ok()
+    }
+}
+
+/// The OID of `bytea`.
+pub const BYTEA_OID: u32 = 17;
+
+/// Encodes a parameter value for a `Bind`.
+pub trait ToSql {
+    /// The OID declared for the parameter in `Parse`, or 0 to let the server
+    /// infer it.
+    fn type_oid(&self) -> u32;
+    fn format(&self) -> Format;
+    /// The encoded value, or `None` for NULL.
+    fn to_sql(&self) -> Option<Vec<u8>>;
+}
+
+impl ToSql for str {
+    fn type_oid(&self) -> u32 {
+        0
+    }
+
+    fn format(&self) -> Format {
+        Format::Text
+    }
+
+    fn to_sql(&self) -> Option<Vec<u8>> {
+        Some(self.as_bytes().to_vec())
+    }
+}
+
+impl ToSql for String {
+    fn type_oid(&self) -> u32 {
+        self.as_str().type_oid()
+    }
+
+    fn format(&self) -> Format {
+        self.as_str().format()
+    }
+
+    fn to_sql(&self) -> Option<Vec<u8>> {
+        self.as_str().to_sql()
+    }
+}
+
+/// Sent as `bytea` in binary, the bytes verbatim: no hex or escape encoding
+/// to build on our side or to parse on the server's.
+impl ToSql for [u8] {
+    fn type_oid(&self) -> u32 {
+        BYTEA_OID
+    }
+
+    fn format(&self) -> Format {
+        Format::Binary
+    }
+
+    fn to_sql(&self) -> Option<Vec<u8>> {
+        Some(self.to_vec())
+    }
+}
+
+impl ToSql for Vec<u8> {
+    fn type_oid(&self) -> u32 {
+        self.as_slice().type_oid()
+    }
+
+    fn format(&self) -> Format {
+        self.as_slice().format()
+    }
+
+    fn to_sql(&self) -> Option<Vec<u8>> {
+        self.as_slice().to_sql()
+    }
+}
+
+impl<T: ToSql + ?Sized> ToSql for &T {
+    fn type_oid(&self) -> u32 {
+        (**self).type_oid()
+    }
+
+    fn format(&self) -> Format {
+        (**self).format()
+    }
+
+    fn to_sql(&self) -> Option<Vec<u8>> {
+        (**self).to_sql()
+    }
+}
+
+/// `None` is NULL, declared with OID 0 so the server infers its type: the
+/// value alone doesn't say which type `T` would have sent.
+impl<T: ToSql> ToSql for Option<T> {
+    fn type_oid(&self) -> u32 {
+        match self {
+            Some(value) => value.type_oid(),
+            None => 0,
+        }
+    }
+
+    fn format(&self) -> Format {
+        match self {
+            Some(value) => value.format(),
+            None => Format::Text,
+        }
+    }
+
+    fn to_sql(&self) -> Option<Vec<u8>> {
+        self.as_ref()?.to_sql()
+    }
+}
+
+/// The OIDs of `int4[]`, `int8[]` and `text[]`.
+pub const INT4_ARRAY_OID: u32 = 1007;
+pub const INT8_ARRAY_OID: u32 = 1016;
+pub const TEXT_ARRAY_OID: u32 = 1009;
+
+/// An element of a `Vec` bound as an array parameter.
+pub trait ArrayElement {
+    /// The OID of the array type, such as `int4[]` for `i32`.
+    const ARRAY_OID: u32;
+
+    /// Appends the element as it appears in an array literal.
+    fn write_element(&self, literal: &mut String);
+}
+
+impl ArrayElement for i32 {
+    const ARRAY_OID: u32 = INT4_ARRAY_OID;
+
+    fn write_element(&self, literal: &mut String) {
+        literal.push_str(&self.to_string());
+    }
+}
+
+impl ArrayElement for i64 {
+    const ARRAY_OID: u32 = INT8_ARRAY_OID;
+
+    fn write_element(&self, literal: &mut String) {
+        literal.push_str(&self.to_string());
+    }
+}
+
+/// Always double-quoted, with backslashes and quotes escaped, so no string
+/// is mistaken for `NULL` or split on a comma or brace.
+impl ArrayElement for String {
+    const ARRAY_OID: u32 = TEXT_ARRAY_OID;
+
+    fn write_element(&self, literal: &mut String) {
+        literal.push('"');
+        for c in self.chars() {
+            if matches!(c, '"' | '\\') {
+                literal.push('\\');
+            }
+            literal.push(c);
+        }
+        literal.push('"');
+    }
+}
+
+/// `None` is a NULL element, written as the bare word `NULL`.
+impl<T: ArrayElement> ArrayElement for Option<T> {
+    const ARRAY_OID: u32 = T::ARRAY_OID;
+
+    fn write_element(&self, literal: &mut String) {
+        match self {
+            Some(value) => value.write_element(literal),
+            None => literal.push_str("NULL"),
+        }
+    }
+}
+
+/// Sent as a text-format array literal such as `{1,2,3}`, for clauses like
+/// `= ANY($1)`.
+impl<T: ArrayElement> ToSql for [T] {
+    fn type_oid(&self) -> u32 {
+        T::ARRAY_OID
+    }
+
+    fn format(&self) -> Format {
+        Format::Text
+    }
+
+    fn to_sql(&self) -> Option<Vec<u8>> {
+        let mut literal = String::from("{");
+        for (i, element) in self.iter().enumerate() {
+            if i > 0 {
+                literal.push(',');
+            }
+            element.write_element(&mut literal);
+        }
+        literal.push('}');
+        Some(literal.into_bytes())
+    }
+}
+
+impl<T: ArrayElement> ToSql for Vec<T> {
+    fn type_oid(&self) -> u32 {
+        self.as_slice().type_oid()
+    }
+
+    fn format(&self) -> Format {
+        self.as_slice().format()
+    }
+
+    fn to_sql(&self) -> Option<Vec<u8>> {
+        self.as_slice().to_sql()
+    }
+}
+
+/// The OID of `text`.
+pub const TEXT_OID: u32 = 25;
+
+/// What [`TypeCache`] knows about a type from `pg_type`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeInfo {
+    pub name: String,
+    /// `typcategory`, such as `'E'` for enums or `'C'` for composites.
+    pub category: char,
+    /// `typbasetype`: the underlying type of a domain, 0 for anything else.
+    pub base_oid: u32,
+}
+
+/// Maps type OIDs to their `pg_type` entry, so columns of user-defined types
+/// can be decoded through a built-in type the decoders understand.
+#[derive(Debug, Clone, Default)]
+pub struct TypeCache {
+    types: HashMap<u32, TypeInfo>,
+}
+
+impl TypeCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, oid: u32, info: TypeInfo) {
+        self.types.insert(oid, info);
+    }
+
+    pub fn get(&self, oid: u32) -> Option<&TypeInfo> {
+        self.types.get(&oid)
+    }
+
+    pub fn len(&self) -> usize {
+        self.types.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+
+    /// The OID of the type named `name`, such as `hstore`, whose OID is
+    /// assigned when its extension is installed. Types in several schemas
+    /// under the same name give any one of them.
+    pub fn oid_of(&self, name: &str) -> Option<u32> {
+        self.types
+            .iter()
+            .find(|(_, info)| info.name == name)
+            .map(|(&oid, _)| oid)
+    }
+
+    /// The type to decode a value of type `oid` as: a domain as its base
+    /// type, following domains over domains, and an enum as `text`. Any other
+    /// type, known or not, resolves to itself.
+    pub fn resolve(&self, oid: u32) -> u32 {
+        let mut oid = oid;
+        // Bounded, so a corrupt cache with a cycle can't loop forever.
+        for _ in 0..=self.types.len() {
+            match self.types.get(&oid) {
+                Some(info) if info.category == 'E' => return TEXT_OID,
+                Some(info) if info.base_oid != 0 => oid = info.base_oid,
+                _ => break,
+            }
+        }
+        oid
+    }
+}
+
+/// The query [`parse_type_row`] reads the rows of.
+pub const TYPE_CACHE_QUERY: &str = "SELECT oid, typname, typcategory, typbasetype FROM pg_type";
+
+/// Parses a row of [`TYPE_CACHE_QUERY`] into an OID and its entry.
+pub fn parse_type_row(row: &Row) -> Option<(u32, TypeInfo)> {
+    let oid = row.get_str(0)?.parse().ok()?;
+    let info = TypeInfo {
+        name: row.get_str(1)?.to_string(),
+        category: row.get_str(2)?.chars().next()?,
+        base_oid: row.get_str(3)?.parse().ok()?,
+    };
+    Some((oid, info))
+}
+
+/// Splits a text-format composite value such as `(1,foo,"bar, baz")` into
+/// the raw text of each field, `None` for NULL.
+///
+/// An empty field is NULL, while an empty string is sent as `""`. Inside
+/// quotes a doubled quote stands for one quote, and a backslash escapes the
+/// byte after it anywhere. The server prints a record with one NULL field
+/// as `()`, the same as a record with no fields, which is how it decodes.
+pub fn decode_record(bytes: &[u8]) -> Result<Vec<Option<Vec<u8>>>, ReadMessageError> {
+    let inner = bytes
+        .strip_prefix(b"(")
+        .and_then(|rest| rest.strip_suffix(b")"))
+        .ok_or(ReadMessageError::ParseError)?;
+    if inner.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut fields = vec![];
+    let mut field = vec![];
+    let mut quoted = false;
+    let mut in_quotes = false;
+    let mut i = 0;
+    while i < inner.len() {
+        match inner[i] {
+            b'\\' => {
+                i += 1;
+                field.push(*inner.get(i).ok_or(ReadMessageError::ParseError)?);
+            }
+            b'"' if in_quotes && inner.get(i + 1) == Some(&b'"') => {
+                field.push(b'"');
+                i += 1;
+            }
+            b'"' => {
+                in_quotes = !in_quotes;
+                quoted = true;
+            }
+            b',' if !in_quotes => {
+                fields.push((quoted || !field.is_empty()).then(|| std::mem::take(&mut field)));
+                quoted = false;
+            }
+            byte => field.push(byte),
+        }
+        i += 1;
+    }
+    if in_quotes {
+        return Err(ReadMessageError::ParseError);
+    }
+    fields.push((quoted || !field.is_empty()).then_some(field));
+
+    Ok(fields)
+}
+
+/// Parses a text-format `hstore` such as `"a"=>"1", "b"=>NULL`. Its OID
+/// varies between databases; find it with [`TypeCache::oid_of`], or
+/// [`TypeCache::insert`] it by hand.
+///
+/// The server always quotes keys and values, escaping `"` and `\` with a
+/// backslash, but bare words are accepted as well. Only a bare `NULL` value
+/// is NULL.
+pub fn decode_hstore(bytes: &[u8]) -> Result<HashMap<String, Option<String>>, ReadMessageError> {
+    let text = std::str::from_utf8(bytes).map_err(|_| ReadMessageError::ParseError)?;
+    let mut rest = text.trim_start();
+    let mut pairs = HashMap::new();
+    while !rest.is_empty() {
+        let (key, _, after) = hstore_token(rest)?;
+        rest = after
+            .trim_start()
+            .strip_prefix("=>")
+            .ok_or(ReadMessageError::ParseError)?
+            .trim_start();
+        let (value, quoted, after) = hstore_token(rest)?;
+        let value = (quoted || !value.eq_ignore_ascii_case("NULL")).then_some(value);
+        pairs.insert(key, value);
+
+        rest = after.trim_start();
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after.trim_start();
+        } else if !rest.is_empty() {
+            return Err(ReadMessageError::ParseError);
+        }
+    }
+    Ok(pairs)
+}
+
+/// Reads a quoted or bare key or value off the front of `text`, returning
+/// it unescaped, whether it was quoted, and what follows it.
+fn hstore_token(text: &str) -> Result<(String, bool, &str), ReadMessageError> {
+    let mut token = String::new();
+    let Some(quoted) = text.strip_prefix('"') else {
+        let end = text
+            .find(|c: char| c.is_whitespace() || c == ',' || c == '=')
+            .unwrap_or(text.len());
+        if end == 0 {
+            return Err(ReadMessageError::ParseError);
+        }
+        token.push_str(&text[..end]);
+        return Ok((token, false, &text[end..]));
+    };
+
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => token.push(chars.next().ok_or(ReadMessageError::ParseError)?.1),
+            '"' => return Ok((token, true, &quoted[i + 1..])),
+            c => token.push(c),
+        }
+    }
+    Err(ReadMessageError::ParseError)
+}
+
+/// The OIDs of `point`, `line`, `box` and `circle`.
+pub const POINT_OID: u32 = 600;
+pub const LINE_OID: u32 = 628;
+pub const BOX_OID: u32 = 603;
+pub const CIRCLE_OID: u32 = 718;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+}
+
+/// An infinite `line`, as the coefficients of `a*x + b*y + c = 0`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Line {
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+}
+
+/// A `box`, by two opposite corners. The server stores the upper right one
+/// first. Named so it doesn't shadow [`std::boxed::Box`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PgBox {
+    pub high: Point,
+    pub low: Point,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Circle {
+    pub center: Point,
+    pub radius: f64,
+}
+
+// The decoders accept the spellings the server accepts on input, such as
+// `x,y` for a point or `((x1,y1),(x2,y2))` for a box, besides the one it
+// outputs. Brackets must match, and nothing may follow the value.
+
+/// Decodes a text `point`, `(x,y)`.
+pub fn decode_point(value: &str) -> Option<Point> {
+    let (point, rest) = geometric_point(value)?;
+    geometric_end(rest)?;
+    Some(point)
+}
+
+/// Decodes a text `line`, `{a,b,c}`.
+pub fn decode_line(value: &str) -> Option<Line> {
+    let rest = geometric_token(value, '{')?;
+    let (a, rest) = geometric_number(rest)?;
+    let (b, rest) = geometric_number(geometric_token(rest, ',')?)?;
+    let (c, rest) = geometric_number(geometric_token(rest, ',')?)?;
+    geometric_end(geometric_token(rest, '}')?)?;
+    Some(Line { a, b, c })
+}
+
+/// Decodes a text `box`, `(x1,y1),(x2,y2)`.
+pub fn decode_box(value: &str) -> Option<PgBox> {
+    let (high, low, rest) = match outer_parenthesis(value) {
+        Some(inner) => {
+            let (high, low, rest) = geometric_pair(inner)?;
+            (high, low, geometric_token(rest, ')')?)
+        }
+        None => geometric_pair(value)?,
+    };
+    geometric_end(rest)?;
+    Some(PgBox { high, low })
+}
+
+/// Decodes a text `circle`, `<(x,y),r>`.
+pub fn decode_circle(value: &str) -> Option<Circle> {
+    let (circle, rest) = if let Some(inner) = geometric_token(value, '<') {
+        let (circle, rest) = center_and_radius(inner)?;
+        (circle, geometric_token(rest, '>')?)
+    } else if let Some(inner) = outer_parenthesis(value) {
+        let (circle, rest) = center_and_radius(inner)?;
+        (circle, geometric_token(rest, ')')?)
+    } else {
+        center_and_radius(value)?
+    };
+    geometric_end(rest)?;
+    Some(circle)
+}
+
+/// What follows the `(` wrapping a whole value, like the outer one of
+/// `((x1,y1),(x2,y2))`, as opposed to the one opening its first point.
+fn outer_parenthesis(value: &str) -> Option<&str> {
+    geometric_token(value, '(').filter(|inner| inner.trim_start().starts_with('('))
+}
+
+fn center_and_radius(value: &str) -> Option<(Circle, &str)> {
+    let (center, rest) = geometric_point(value)?;
+    let (radius, rest) = geometric_number(geometric_token(rest, ',')?)?;
+    Some((Circle { center, radius }, rest))
+}
+
+fn geometric_pair(value: &str) -> Option<(Point, Point, &str)> {
+    let (first, rest) = geometric_point(value)?;
+    let (second, rest) = geometric_point(geometric_token(rest, ',')?)?;
+    Some((first, second, rest))
+}
+
+/// A point, `(x,y)` or `x,y`, and what follows it.
+fn geometric_point(value: &str) -> Option<(Point, &str)> {
+    let parenthesized = geometric_token(value, '(');
+    let (x, rest) = geometric_number(parenthesized.unwrap_or(value))?;
+    let (y, mut rest) = geometric_number(geometric_token(rest, ',')?)?;
+    if parenthesized.is_some() {
+        rest = geometric_token(rest, ')')?;
+    }
+    Some((Point { x, y }, rest))
+}
+
+/// A number, up to the next separator or closing bracket, and what follows.
+fn geometric_number(value: &str) -> Option<(f64, &str)> {
+    let end = value
+        .find([',', ')', '>', '}', '(', '<', '{'])
+        .unwrap_or(value.len());
+    Some((value[..end].trim().parse().ok()?, &value[end..]))
+}
+
+/// What follows `token`, if `value` starts with it after any whitespace.
+fn geometric_token(value: &str, token: char) -> Option<&str> {
+    value.trim_start().strip_prefix(token)
+}
+
+fn geometric_end(rest: &str) -> Option<()> {
+    rest.trim().is_empty().then_some(())
+}
+
+/// The OIDs of `inet` and `cidr`.
+pub const INET_OID: u32 = 869;
+pub const CIDR_OID: u32 = 650;
+
+/// An `inet` or `cidr` value: an address and, for a network, the length of
+/// its netmask in bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Inet {
+    pub addr: IpAddr,
+    pub netmask: Option<u8>,
+}
+
+/// Decodes a text `inet` or `cidr`, such as `192.168.0.1/24` or `::1`.
+///
+/// The server leaves the mask out of an `inet` when it covers the whole
+/// address, and always prints it for a `cidr`; either way it's only
+/// `Some` when present. A mask longer than the address is an error.
+pub fn decode_inet(value: &str) -> Result<Inet, ReadMessageError> {
+    let (addr, netmask) = match value.split_once('/') {
+        Some((addr, netmask)) => (
+            addr,
+            Some(
+                netmask
+                    .parse::<u8>()
+                    .map_err(|_| ReadMessageError::ParseError)?,
+            ),
+        ),
+        None => (value, None),
+    };
+    let addr: IpAddr = addr.parse().map_err(|_| ReadMessageError::ParseError)?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    if netmask.is_some_and(|netmask| netmask > max) {
+        return Err(ReadMessageError::ParseError);
+    }
+    Ok(Inet { addr, netmask })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_every_interval_style_alike() {
+        let expected = Interval {
+            months: 14,
+            days: 3,
+            micros: (4 * 3600 + 5 * 60 + 6) * MICROS_PER_SECOND,
+        };
+        for (value, style) in [
+            ("1 year 2 mons 3 days 04:05:06", Some("postgres")),
+            // Before the server reports IntervalStyle, `postgres` is assumed.
+            ("1 year 2 mons 3 days 04:05:06", None),
+            (
+                "@ 1 year 2 mons 3 days 4 hours 5 mins 6 secs",
+                Some("postgres_verbose"),
+            ),
+            ("P1Y2M3DT4H5M6S", Some("iso_8601")),
+            ("+1-2 +3 +4:05:06", Some("sql_standard")),
+        ] {
+            assert_eq!(decode_interval(value, style), Some(expected), "{value}");
+        }
+    }
+
+    #[test]
+    fn rejects_intervals_that_overflow() {
+        for (value, style) in [
+            ("9999999999999 hours", None),
+            ("9999999999999 mins", None),
+            ("2147483647 days 1 day", None),
+            ("2147483647 mons 1 mon", None),
+            ("-2147483648 days ago", None),
+            ("9999999999999:00:00", None),
+            ("PT9999999999999H", Some("iso_8601")),
+            ("P2147483647D1D", Some("iso_8601")),
+            ("-0-0 -2147483648", Some("sql_standard")),
+        ] {
+            assert_eq!(decode_interval(value, style), None, "{value}");
+        }
+    }
+
+    #[test]
+    fn decodes_money_in_the_c_locale() {
+        assert_eq!(decode_money("$1,234.56", None), Some(123456));
+        assert_eq!(decode_money("-$1,234.56", Some("C")), Some(-123456));
+        assert_eq!(decode_money("$-0.05", None), Some(-5));
+        assert_eq!(decode_money("($12.00)", None), Some(-1200));
+        assert_eq!(decode_money("$7", None), Some(700));
+        assert_eq!(decode_money("($12.00", None), None);
+        assert_eq!(decode_money("--$1.00", None), None);
+        assert_eq!(decode_money("$", None), None);
+    }
+
+    #[test]
+    fn decodes_comma_grouped_money() {
+        assert_eq!(
+            decode_money("1.234,56 €", Some("de_DE.UTF-8")),
+            Some(123456)
+        );
+        assert_eq!(
+            decode_money("-1.234,56 €", Some("de_DE.UTF-8")),
+            Some(-123456)
+        );
+        // A dash in the currency text after the amount isn't a sign.
+        assert_eq!(decode_money("12,50 kr-SE", Some("sv_SE")), Some(1250));
+    }
+
+    #[test]
+    fn decodes_one_value_of_each_geometric_type() {
+        let point = |x, y| Point { x, y };
+        assert_eq!(decode_point("(1.5,-2)"), Some(point(1.5, -2.0)));
+        assert_eq!(
+            decode_line("{1,-1,0}"),
+            Some(Line {
+                a: 1.0,
+                b: -1.0,
+                c: 0.0
+            })
+        );
+        let expected_box = PgBox {
+            high: point(3.0, 4.0),
+            low: point(1.0, 2.0),
+        };
+        assert_eq!(decode_box("(3,4),(1,2)"), Some(expected_box));
+        let expected_circle = Circle {
+            center: point(1.0, 2.0),
+            radius: 3.0,
+        };
+        assert_eq!(decode_circle("<(1,2),3>"), Some(expected_circle));
+
+        // Spellings the server accepts on input.
+        assert_eq!(decode_point(" 1.5 , -2 "), Some(point(1.5, -2.0)));
+        assert_eq!(decode_box("((3,4),(1,2))"), Some(expected_box));
+        assert_eq!(decode_circle("((1,2),3)"), Some(expected_circle));
+        assert_eq!(decode_circle("(1,2),3"), Some(expected_circle));
+        assert_eq!(decode_circle("1,2,3"), Some(expected_circle));
+    }
+
+    #[test]
+    fn rejects_unmatched_geometric_brackets() {
+        for value in ["1,2)", "(1,2", "(1,2))", "1,2,3", "(1,2)x"] {
+            assert_eq!(decode_point(value), None, "{value}");
+        }
+        for value in ["<(1,2),3", "(1,2),3>", "<(1,2),3)", "<1,2>"] {
+            assert_eq!(decode_circle(value), None, "{value}");
+        }
+        for value in ["((3,4),(1,2)", "(3,4),(1,2))", "(3,4)"] {
+            assert_eq!(decode_box(value), None, "{value}");
+        }
+        assert_eq!(decode_line("{1,-1,0"), None);
+        assert_eq!(decode_line("1,-1,0"), None);
+    }
+
+    #[test]
+    fn binds_arrays_as_text_literals() {
+        let ids = vec![1, -2, 3];
+        assert_eq!(ids.type_oid(), INT4_ARRAY_OID);
+        assert_eq!(ids.to_sql().unwrap(), b"{1,-2,3}");
+
+        let names = vec![
+            "plain".to_string(),
+            "a, \"b\"".to_string(),
+            "NULL".to_string(),
+        ];
+        assert_eq!(names.type_oid(), TEXT_ARRAY_OID);
+        assert_eq!(names.to_sql().unwrap(), br#"{"plain","a, \"b\"","NULL"}"#);
+
+        let sparse = vec![Some(1i64), None];
+        assert_eq!(sparse.type_oid(), INT8_ARRAY_OID);
+        assert_eq!(sparse.to_sql().unwrap(), b"{1,NULL}");
+    }
+
+    #[test]
+    fn splits_records_with_quoted_and_null_fields() {
+        let fields = decode_record(br#"(1,"a, b",,"")"#).unwrap();
+        assert_eq!(
+            fields,
+            [
+                Some(b"1".to_vec()),
+                Some(b"a, b".to_vec()),
+                None,
+                Some(vec![]),
+            ]
+        );
+        assert_eq!(
+            decode_record(br#"("say ""hi""",x)"#).unwrap(),
+            [Some(br#"say "hi""#.to_vec()), Some(b"x".to_vec())]
+        );
+        assert!(decode_record(br#"(1,"open)"#).is_err());
+    }
+
+    #[test]
+    fn decodes_hstore_pairs() {
+        let pairs = decode_hstore(br#""a"=>"1", "b"=>NULL, "say \"hi\""=>"NULL""#).unwrap();
+        assert_eq!(pairs.len(), 3);
+        assert_eq!(pairs["a"], Some("1".to_string()));
+        assert_eq!(pairs["b"], None);
+        // Only a bare NULL is NULL; a quoted one is the string.
+        assert_eq!(pairs[r#"say "hi""#], Some("NULL".to_string()));
+
+        assert!(decode_hstore(b"").unwrap().is_empty());
+        assert!(decode_hstore(br#""a"=>"1" "b"=>"2""#).is_err());
+        assert!(decode_hstore(br#""a"=>"1"#).is_err());
+    }
+
+    #[test]
+    fn decodes_inet_addresses_and_networks() {
+        assert_eq!(
+            decode_inet("192.168.0.1/24").unwrap(),
+            Inet {
+                addr: IpAddr::from([192, 168, 0, 1]),
+                netmask: Some(24),
+            }
+        );
+        assert_eq!(
+            decode_inet("::1").unwrap(),
+            Inet {
+                addr: IpAddr::from(std::net::Ipv6Addr::LOCALHOST),
+                netmask: None,
+            }
+        );
+        for value in [
+            "192.168.0/24",
+            "10.0.0.1/33",
+            "::1/129",
+            "10.0.0.1/",
+            "host",
+        ] {
+            assert!(decode_inet(value).is_err(), "{value}");
+        }
+    }
+}
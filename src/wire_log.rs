@@ -0,0 +1,137 @@
+use std::fmt::Debug;
+#[cfg(feature = "flate2")]
+use std::fs::File;
+#[cfg(feature = "flate2")]
+use std::io::prelude::*;
+
+#[cfg(feature = "flate2")]
+use flate2::{write::GzEncoder, Compression};
+
+/// Where a connection logs every message it sends (`-->`) and receives
+/// (`<--`), one line each, tagged with the connection's id.
+///
+/// Logging never fails the connection: a line that can't be written to the
+/// file is dropped. With the `log` feature, lines go to the `log` crate by
+/// default rather than to stdout.
+#[derive(Debug, Default)]
+pub enum WireLog {
+    #[cfg_attr(not(feature = "log"), default)]
+    Stdout,
+    /// Trace-level records for whichever logger the application installed.
+    #[cfg(feature = "log")]
+    #[default]
+    Log,
+    /// Gzip-compressed lines in a file, for long runs whose log would
+    /// otherwise be huge.
+    #[cfg(feature = "flate2")]
+    Gzip(GzEncoder<File>),
+}
+
+impl WireLog {
+    #[cfg(feature = "flate2")]
+    pub fn gzip(file: File) -> Self {
+        Self::Gzip(GzEncoder::new(file, Compression::default()))
+    }
+
+    pub fn log(&mut self, connection_id: u64, direction: &str, message: &dyn Debug) {
+        match self {
+            Self::Stdout => println!("[{}] {} {:?}", connection_id, direction, message),
+            #[cfg(feature = "log")]
+            Self::Log => log::trace!("[{}] {} {:?}", connection_id, direction, message),
+            #[cfg(feature = "flate2")]
+            Self::Gzip(encoder) => {
+                let _ = writeln!(encoder, "[{}] {} {:?}", connection_id, direction, message);
+            }
+        }
+    }
+
+    /// Writes out whatever is still buffered and, for a gzip file, its
+    /// trailer, so the file is complete even if the process is killed later.
+    pub fn finish(&mut self) {
+        match self {
+            Self::Stdout => {}
+            #[cfg(feature = "log")]
+            Self::Log => {}
+            #[cfg(feature = "flate2")]
+            Self::Gzip(encoder) => {
+                let _ = encoder.try_finish();
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "log"))]
+mod tests {
+    use crate::mock::*;
+    use std::sync::{Mutex, Once};
+
+    /// Keeps every line logged, from all threads.
+    struct CapturingLogger;
+
+    static LINES: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+    impl log::Log for CapturingLogger {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            LINES.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn capture() {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            log::set_logger(&CapturingLogger).unwrap();
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+    }
+
+    #[test]
+    fn tags_log_lines_with_the_connection_id() {
+        capture();
+        let servers = [(); 2].map(|_| {
+            MockServer::start(|session| {
+                session.accept();
+            })
+        });
+        let ids = servers.each_ref().map(|server| server.connect().id());
+        assert_ne!(ids[0], ids[1]);
+
+        let lines = LINES.lock().unwrap();
+        for id in ids {
+            let sent = format!("[{}] --> StartupMessage", id);
+            let received = format!("[{}] <-- ReadyForQuery", id);
+            assert!(lines.iter().any(|line| line.starts_with(&sent)));
+            assert!(lines.iter().any(|line| line.starts_with(&received)));
+        }
+        drop(lines);
+        servers.into_iter().for_each(MockServer::finish);
+    }
+}
+
+#[cfg(all(test, feature = "flate2"))]
+mod gzip_tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::fs;
+
+    #[test]
+    fn gzip_log_decodes_back_to_its_lines() {
+        let path = std::env::temp_dir().join(format!("toydrv-wire-{}.log.gz", std::process::id()));
+        let mut log = WireLog::gzip(File::create(&path).unwrap());
+        log.log(1, "-->", &"Sync");
+        log.log(1, "<--", &"ReadyForQuery");
+        log.finish();
+
+        let mut text = String::new();
+        GzDecoder::new(File::open(&path).unwrap())
+            .read_to_string(&mut text)
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(text, "[1] --> \"Sync\"\n[1] <-- \"ReadyForQuery\"\n");
+    }
+}